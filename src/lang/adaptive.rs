@@ -0,0 +1,87 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+use crate::lang::types::{AdaptivePaddingSpec, Distribution};
+
+/// Where the machine is relative to the bursts of real records.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    /// Waiting for the application, without sending any dummy records.
+    Idle,
+    /// The last record carried application data.
+    Burst,
+    /// The last record was a dummy.
+    Gap,
+}
+
+/// Burst-aware adaptive padding for one direction, in the style of WTF-PAD.
+///
+/// After every record we sample how long to wait for the application from the
+/// histogram for the current state. If the wait expires first, we send a dummy
+/// record to fill the gap. Sampling `INFINITY` stops padding until the
+/// application sends again.
+pub struct AdaptivePadding {
+    spec: AdaptivePaddingSpec,
+    state: State,
+}
+
+impl AdaptivePadding {
+    pub fn new(spec: AdaptivePaddingSpec) -> Self {
+        Self {
+            spec,
+            state: State::Idle,
+        }
+    }
+
+    /// Returns how long to wait for application data before sending a dummy
+    /// record, or `None` to wait indefinitely.
+    pub fn next_timeout<R: Rng + ?Sized>(&mut self, rng: &mut R) -> Option<Duration> {
+        let dist = match self.state {
+            State::Idle => return None,
+            State::Burst => &self.spec.burst,
+            State::Gap => &self.spec.gap,
+        };
+
+        match dist.sample(rng) {
+            Distribution::INFINITY => {
+                self.state = State::Idle;
+                None
+            }
+            millis => Some(Duration::from_millis(millis as u64)),
+        }
+    }
+
+    /// Records that we are sending a record, which is a dummy if `is_dummy`.
+    pub fn on_record(&mut self, is_dummy: bool) {
+        self.state = match is_dummy {
+            true => State::Gap,
+            false => State::Burst,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transitions() {
+        let mut rng = rand::thread_rng();
+        let mut ap = AdaptivePadding::new(AdaptivePaddingSpec {
+            burst: Distribution::Fixed(5),
+            gap: Distribution::Histogram(vec![(Distribution::INFINITY, 1)]),
+        });
+
+        // Nothing to pad until the application has sent something.
+        assert_eq!(ap.next_timeout(&mut rng), None);
+
+        ap.on_record(false);
+        assert_eq!(ap.next_timeout(&mut rng), Some(Duration::from_millis(5)));
+
+        // Gap samples are infinite, so one dummy ends the padding.
+        ap.on_record(true);
+        assert_eq!(ap.next_timeout(&mut rng), None);
+        assert_eq!(ap.state, State::Idle);
+    }
+}
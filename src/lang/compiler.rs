@@ -2,10 +2,12 @@
 
 use std::iter::Iterator;
 
+use anyhow::{anyhow, Result};
 use petgraph::visit::EdgeRef;
 use petgraph::Directed;
 
 use crate::lang::common::Role;
+use crate::lang::message::Message;
 use crate::lang::task::*;
 use crate::lang::types::*;
 
@@ -15,12 +17,19 @@ use crate::lang::types::*;
  * - There is one length field and one payload field for payload-carrying messages
  * - The length field for the payload field is not undefined
  * - The fixed-size length field for the payload should be in the prefix
+ * - A dynamic padding field has its own length field, also in the prefix, and
+ *   the payload length field covers the padding too
+ * - An optional field has a fixed size when present, and the field its
+ *   condition tests is in the prefix and is not a length field or checksum
+ * - Delimited arrays come before the payload
+ * - A checksum field is an unsigned number the size of its checksum
+ * - Records in a list we send size their dynamic arrays by length fields
  */
 
 /*
  * Identifier here is a format identifier
 */
-type Graph = petgraph::graph::Graph<(), (Role, Identifier), Directed, usize>;
+type Graph = petgraph::graph::Graph<(), (Role, Phase, Identifier), Directed, usize>;
 
 #[derive(Clone)]
 pub struct TaskGraphImpl {
@@ -38,6 +47,20 @@ impl TaskGraphImpl {
         }
     }
 
+    pub fn constant_rate(&self) -> Option<ConstantRate> {
+        self.psf.traffic_spec.constant_rate
+    }
+
+    /// Sends at the given constant rate, if every message can be padded out
+    /// to its record size.
+    pub fn set_constant_rate(&mut self, rate: ConstantRate) -> Result<()> {
+        if !self.psf.constant_rate_valid(rate) {
+            return Err(anyhow!("messages cannot be sent at {:?}", rate));
+        }
+        self.psf.traffic_spec.constant_rate = Some(rate);
+        Ok(())
+    }
+
     pub fn next(&self, task_completed: TaskID) -> TaskSet {
         let edges: Vec<_> = self
             .graph
@@ -47,10 +70,17 @@ impl TaskGraphImpl {
         match edges.len() {
             1 => {
                 let edge_role = &(edges[0].weight()).0;
-                let edge_format = &(edges[0].weight()).1;
-
-                let ins =
-                    compile_message_to_instrs(self.my_role, *edge_role, edge_format, &self.psf);
+                let edge_phase = &(edges[0].weight()).1;
+                let edge_format = &(edges[0].weight()).2;
+
+                let ins = compile_message_to_instrs(
+                    self.my_role,
+                    *edge_role,
+                    *edge_phase,
+                    edge_format,
+                    &self.psf,
+                )
+                .expect("checked when the spec was built");
 
                 let t = Task {
                     ins,
@@ -66,16 +96,30 @@ impl TaskGraphImpl {
             }
             2 => {
                 let edge0_role = &(edges[0].weight()).0;
-                let edge0_format = &(edges[0].weight()).1;
+                let edge0_phase = &(edges[0].weight()).1;
+                let edge0_format = &(edges[0].weight()).2;
 
                 let edge1_role = &(edges[1].weight()).0;
-                let edge1_format = &(edges[1].weight()).1;
-
-                let ins0 =
-                    compile_message_to_instrs(self.my_role, *edge0_role, edge0_format, &self.psf);
-
-                let ins1 =
-                    compile_message_to_instrs(self.my_role, *edge1_role, edge1_format, &self.psf);
+                let edge1_phase = &(edges[1].weight()).1;
+                let edge1_format = &(edges[1].weight()).2;
+
+                let ins0 = compile_message_to_instrs(
+                    self.my_role,
+                    *edge0_role,
+                    *edge0_phase,
+                    edge0_format,
+                    &self.psf,
+                )
+                .expect("checked when the spec was built");
+
+                let ins1 = compile_message_to_instrs(
+                    self.my_role,
+                    *edge1_role,
+                    *edge1_phase,
+                    edge1_format,
+                    &self.psf,
+                )
+                .expect("checked when the spec was built");
 
                 let t0 = Task {
                     ins: ins0,
@@ -106,6 +150,16 @@ impl TaskGraphImpl {
         }
     }
 
+    /// Compiles the message on every edge of the graph, to report anything
+    /// the compiler cannot handle before the connection starts.
+    pub fn check(&self) -> Result<()> {
+        for edge in self.graph.edge_references() {
+            let (role, phase, format) = edge.weight();
+            compile_message_to_instrs(self.my_role, *role, *phase, format, &self.psf)?;
+        }
+        Ok(())
+    }
+
     pub fn init_task(&self) -> Task {
         let mut ins: Vec<Instruction> = vec![];
 
@@ -121,6 +175,10 @@ impl TaskGraphImpl {
             }
         }
 
+        if let Some(spec) = self.psf.traffic_spec.adaptive_padding.get(&self.my_role) {
+            ins.push(InitAdaptivePaddingArgs { spec: spec.clone() }.into());
+        }
+
         Task {
             id: Default::default(),
             ins,
@@ -135,7 +193,7 @@ pub fn compile_task_graph<'a, T: Iterator<Item = &'a SequenceSpecifier>>(itr: T)
 
     let mut prev_node = start_node;
     for seqspec in itr {
-        let edge_weight = (seqspec.role, seqspec.format.clone());
+        let edge_weight = (seqspec.role, seqspec.phase, seqspec.format.clone());
 
         match seqspec.phase {
             Phase::Handshake => {
@@ -152,13 +210,119 @@ pub fn compile_task_graph<'a, T: Iterator<Item = &'a SequenceSpecifier>>(itr: T)
     graph
 }
 
+/// How the value of a length field relates to the size of its array.
+#[derive(Debug)]
+enum LengthMeaning {
+    /// The field counts a run of bytes that holds the array, `fixed_size` bytes
+    /// of fixed-size fields, and the other dynamic arrays in `covered_arrays`.
+    Covers {
+        fixed_size: usize,
+        covered_arrays: Vec<Identifier>,
+    },
+    /// The field sizes the array alone, through an expression.
+    Expr(LengthExpr),
+}
+
+/// How a length field whose size depends on its value is written. We encode
+/// these before laying out the message, and decode them from the bytes we read
+/// off the network.
+#[derive(Clone, Debug)]
+enum LengthEncoding {
+    Varint(VarintType),
+    /// A textual number ending at a delimiter.
+    Text(TextNumber),
+}
+
+impl LengthEncoding {
+    fn encode_instr(&self, from_heap_id: Identifier, to_heap_id: Identifier) -> Instruction {
+        match self {
+            LengthEncoding::Varint(varint) => EncodeVarintArgs {
+                from_heap_id,
+                varint: *varint,
+                to_heap_id,
+            }
+            .into(),
+            LengthEncoding::Text(text) => EncodeTextArgs {
+                from_heap_id,
+                text: text.clone(),
+                to_heap_id,
+            }
+            .into(),
+        }
+    }
+
+    fn decode_instr(&self, from_heap_id: Identifier, to_heap_id: Identifier) -> Instruction {
+        match self {
+            LengthEncoding::Varint(varint) => DecodeVarintArgs {
+                from_heap_id,
+                varint: *varint,
+                to_heap_id,
+            }
+            .into(),
+            LengthEncoding::Text(text) => DecodeTextArgs {
+                from_heap_id,
+                text: text.clone(),
+                to_heap_id,
+            }
+            .into(),
+        }
+    }
+}
+
 #[derive(Debug)]
 struct HintsDynamicPayload {
     payload_field_name: Identifier,
     length_field_name: Identifier,
+    // The largest payload the length field can describe.
     length_field_max: usize,
-    length_field_nbytes: usize,
-    static_prefix_last_field: Identifier,
+    length_field_encoding: Option<LengthEncoding>,
+    length_meaning: LengthMeaning,
+    // Set when a fixed-size length field counts everything after the static
+    // prefix, which we can compute from the message itself.
+    static_prefix_last_field: Option<Identifier>,
+}
+
+/// Gets the largest value a length field can hold and, if its size depends on
+/// its value, how it is encoded.
+fn length_field_bounds(length_field: &Field) -> (usize, Option<LengthEncoding>) {
+    match length_field.dtype {
+        // No buffer can hold more than isize::MAX bytes, so there is no point in
+        // allowing larger lengths (and it leaves room to add the overhead).
+        Array::Varint(Varint(vtype, _)) => (
+            usize::try_from(vtype.max_value())
+                .unwrap_or(usize::MAX)
+                .min(isize::MAX as usize),
+            Some(LengthEncoding::Varint(vtype)),
+        ),
+        // Fixed-width textual numbers are set through the message like any
+        // other fixed-size field.
+        Array::Text(ref text) => (
+            usize::try_from(text.max_value())
+                .unwrap_or(usize::MAX)
+                .min(isize::MAX as usize),
+            match text.end {
+                TextEnd::Width(_) => None,
+                TextEnd::Until(_) => Some(LengthEncoding::Text(text.clone())),
+            },
+        ),
+        _ => {
+            let len_field_type = TryInto::<NumericType>::try_into(
+                TryInto::<PrimitiveArray>::try_into(length_field.dtype.clone()).unwrap(),
+            )
+            .unwrap();
+            (
+                <u128 as TryInto<usize>>::try_into(len_field_type.bounds().1).unwrap(),
+                None,
+            )
+        }
+    }
+}
+
+/// Gets the size of the largest array an expression allows.
+fn length_expr_max(expr: &LengthExpr, field_max: usize) -> usize {
+    expr.array_len(field_max as u128)
+        .unwrap_or(isize::MAX as usize)
+        .min(isize::MAX as usize)
 }
 
 fn generate_dynamic_payload_hints(
@@ -169,47 +333,313 @@ fn generate_dynamic_payload_hints(
     let payload_field_id = semantics.find_field_id(FieldSemantic::Payload).unwrap();
     let payload_field = format.try_get_field_by_name(&payload_field_id).unwrap();
 
-    let (static_prefix, dynamic_suffix) = format.split_into_fixed_sized_prefix_dynamic_suffix();
+    let payload_array = match payload_field.dtype {
+        Array::Dynamic(d) => d,
+        _ => return None,
+    };
+
+    let length_field_id = payload_array.try_get_length_field().unwrap();
+    let length_field = format.try_get_field_by_name(&length_field_id).unwrap();
+    let (field_max, length_field_encoding) = length_field_bounds(&length_field);
+
+    let position = |id: &Identifier| format.fields.iter().position(|f| &f.name == id).unwrap();
+
+    // Figure out which fields the length field counts. By default, fixed-size
+    // length fields count everything after the static prefix, and those whose
+    // size depends on their value count everything after themselves.
+    let (static_prefix, _) = format.split_into_fixed_sized_prefix_dynamic_suffix();
+    let mut static_prefix_last_field = None;
+
+    let covered = match semantics.get_length_coverage(&length_field_id) {
+        Some((first, last)) => position(&first)..position(&last) + 1,
+        None => match length_field_encoding {
+            Some(_) => position(&length_field_id) + 1..format.fields.len(),
+            None => {
+                // There must be something to count the bytes after.
+                static_prefix_last_field = Some(static_prefix.fields.last()?.name.clone());
+                static_prefix.fields.len()..format.fields.len()
+            }
+        },
+    };
+
+    let (length_meaning, length_field_max) = match payload_array.try_get_length_expr() {
+        Some(expr) => {
+            static_prefix_last_field = None;
+            let max = length_expr_max(&expr, field_max);
+            (LengthMeaning::Expr(expr), max)
+        }
+        None => {
+            let covered_fields = &format.fields[covered];
+            let fixed_size = covered_fields
+                .iter()
+                .filter_map(|f| f.maybe_wire_size_of())
+                .sum();
+            let delimited_fields: Vec<Identifier> = generate_delimited_hints(format)
+                .into_iter()
+                .map(|h| h.field_name)
+                .collect();
+            let covered_arrays = covered_fields
+                .iter()
+                .filter(|f| {
+                    (f.dtype.try_as_dynamic().is_some()
+                        || matches!(f.dtype, Array::Conditional(_))
+                        || delimited_fields.contains(&f.name))
+                        && f.name != payload_field_id
+                })
+                .map(|f| f.name.clone())
+                .collect();
+
+            // Optional fields may take up some of the length too.
+            let optional_size: usize = covered_fields
+                .iter()
+                .filter_map(|f| match f.dtype {
+                    Array::Conditional(ref conditional) => conditional.array.maybe_size_of(),
+                    _ => None,
+                })
+                .sum();
+
+            (
+                LengthMeaning::Covers {
+                    fixed_size,
+                    covered_arrays,
+                },
+                field_max.saturating_sub(fixed_size + optional_size),
+            )
+        }
+    };
 
-    let (_, dynamic_suffix_fixed_part) =
-        dynamic_suffix.split_into_dynamic_prefix_and_fixed_suffix();
-    let suffix_fixed_size = dynamic_suffix_fixed_part.fixed_fields_size();
+    Some(HintsDynamicPayload {
+        payload_field_name: payload_field_id,
+        length_field_name: length_field_id,
+        length_field_max,
+        length_field_encoding,
+        length_meaning,
+        static_prefix_last_field,
+    })
+}
 
-    let static_prefix_last_field: Identifier;
-    if let Some(last_field) = static_prefix.fields.last() {
-        static_prefix_last_field = last_field.name.clone();
-    } else {
-        return None;
-    }
+/// A dynamic array other than the payload, sized by its own length field.
+#[derive(Debug)]
+struct HintsArray {
+    field_name: Identifier,
+    length_field_name: Identifier,
+    length_field_max: usize,
+    length_field_encoding: Option<LengthEncoding>,
+    // Set when the array is sized by an expression rather than `size_of`,
+    // which counts the array bytes directly.
+    length_expr: Option<LengthExpr>,
+    // Where the size of the array in bytes is kept on the heap.
+    length_heap_id: Identifier,
+}
 
-    let len_field_max: usize;
-    let length_field_id: Identifier;
-    let length_field_nbytes: usize;
+impl HintsArray {
+    fn step(&self) -> usize {
+        self.length_expr.as_ref().map_or(1, |expr| expr.scale)
+    }
+}
 
-    if let Array::Dynamic(d) = payload_field.dtype {
-        length_field_id = d.try_get_length_field().unwrap();
-        let length_field = format.try_get_field_by_name(&length_field_id).unwrap();
+/// Generates hints for every dynamic array in the format except the payload,
+/// in the order they appear. Lists of records count as byte arrays here.
+fn generate_array_hints(format: &Format, semantics: &Semantics) -> Vec<HintsArray> {
+    let payload_field_id = semantics.find_field_id(FieldSemantic::Payload);
 
-        let len_field_type = TryInto::<NumericType>::try_into(
-            TryInto::<PrimitiveArray>::try_into(length_field.dtype).unwrap(),
-        )
-        .unwrap();
+    format
+        .fields
+        .iter()
+        .filter(|field| Some(&field.name) != payload_field_id.as_ref())
+        .filter_map(|field| match field.dtype.try_as_dynamic() {
+            Some(ref d) => {
+                // Delimited arrays have no length field.
+                let length_field_id = d.try_get_length_field()?;
+                let length_field = format.try_get_field_by_name(&length_field_id).unwrap();
+                let (field_max, length_field_encoding) = length_field_bounds(&length_field);
+                let length_expr = d.try_get_length_expr();
+
+                Some(HintsArray {
+                    field_name: field.name.clone(),
+                    length_field_name: length_field_id,
+                    length_field_max: length_expr
+                        .as_ref()
+                        .map_or(field_max, |expr| length_expr_max(expr, field_max)),
+                    length_field_encoding,
+                    length_expr,
+                    length_heap_id: (field.name.0.clone() + "_length_on_heap").as_str().id(),
+                })
+            }
+            _ => None,
+        })
+        .collect()
+}
 
-        len_field_max = <u128 as TryInto<usize>>::try_into(len_field_type.bounds().1).unwrap()
-            - suffix_fixed_size;
+/// Lays out the record we put in each list we send: its fixed strings, with
+/// every other value zero and every dynamic array empty. Sending one record
+/// keeps the list's length field in step with what the peer parses.
+fn empty_record(record: &Format, psf: &Psf) -> Result<Vec<u8>> {
+    let fixed_fields = psf
+        .formats
+        .get(&record.name)
+        .map(|afs| afs.semantics.get_fixed_fields())
+        .unwrap_or_default();
+
+    let mut lengths = vec![];
+    for field in &record.fields {
+        if field.maybe_size_of().is_some() {
+            continue;
+        }
+        let value = match field.dtype {
+            Array::Dynamic(DynamicArray(UnaryOp::SizeOf(ref id))) => Some((id.clone(), 0)),
+            Array::Dynamic(DynamicArray(UnaryOp::Expr(ref expr))) => {
+                expr.field_value(0).map(|value| (expr.field.clone(), value))
+            }
+            _ => None,
+        };
+        lengths.push((
+            field.name.clone(),
+            value.ok_or_else(|| anyhow!("cannot lay out an empty {}", field.name.0))?,
+        ));
+    }
 
-        length_field_nbytes = len_field_type.size_of();
-    } else {
-        return None;
+    let sizes = lengths.iter().map(|(id, _)| (id.clone(), 0)).collect();
+    let format = AbstractFormat {
+        format: record.clone(),
+        fixed_fields,
+    }
+    .concretize(&sizes);
+    let mut msg = Message::new(format).unwrap();
+    for (field_name, (length_field_name, value)) in &lengths {
+        msg.set_field_unsigned_numeric(length_field_name, *value)
+            .map_err(|_| anyhow!("cannot set the length of {}", field_name.0))?;
     }
 
-    Some(HintsDynamicPayload {
-        payload_field_name: payload_field_id,
-        length_field_name: length_field_id,
-        length_field_max: len_field_max,
-        length_field_nbytes,
-        static_prefix_last_field,
-    })
+    Ok(msg.into_inner().to_vec())
+}
+
+/// A field ending at the first occurrence of a delimiter, other than a length
+/// field.
+#[derive(Debug)]
+struct HintsDelimited {
+    field_name: Identifier,
+    delimiter: Vec<u8>,
+    // What the field holds when we have nothing to put in it.
+    empty: Vec<u8>,
+    // Where the size of the field in bytes is kept on the heap.
+    length_heap_id: Identifier,
+}
+
+fn generate_delimited_hints(format: &Format) -> Vec<HintsDelimited> {
+    // Delimited text length fields are encoded from their value instead.
+    let length_fields: Vec<Identifier> = format
+        .fields
+        .iter()
+        .filter_map(|field| field.dtype.try_as_dynamic()?.try_get_length_field())
+        .collect();
+
+    format
+        .fields
+        .iter()
+        .filter(|field| !length_fields.contains(&field.name))
+        .filter_map(|field| {
+            let delimiter = field.dtype.try_get_delimiter()?;
+            let empty = match field.dtype {
+                Array::Text(ref text) => text.encode(0)?,
+                _ => delimiter.clone(),
+            };
+            Some(HintsDelimited {
+                field_name: field.name.clone(),
+                delimiter,
+                empty,
+                length_heap_id: (field.name.0.clone() + "_length_on_heap").as_str().id(),
+            })
+        })
+        .collect()
+}
+
+/// A field that is only present when its condition holds.
+#[derive(Debug)]
+struct HintsConditional {
+    field_name: Identifier,
+    condition: Condition,
+    present_len: usize,
+    // Where the size of the field in bytes is kept on the heap.
+    length_heap_id: Identifier,
+}
+
+fn generate_conditional_hints(format: &Format) -> Vec<HintsConditional> {
+    format
+        .fields
+        .iter()
+        .filter_map(|field| match field.dtype {
+            Array::Conditional(ref conditional) => Some(HintsConditional {
+                field_name: field.name.clone(),
+                condition: conditional.condition.clone(),
+                // Unwrap OK: the parser checks optional fields have a fixed size.
+                present_len: conditional.array.maybe_size_of().unwrap(),
+                length_heap_id: (field.name.0.clone() + "_length_on_heap").as_str().id(),
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+/// A field holding a checksum over a run of fields.
+#[derive(Debug)]
+struct HintsChecksum {
+    field_name: Identifier,
+    algorithm: ChecksumAlgorithm,
+    endianness: Endianness,
+    // The fields it covers, in order.
+    covered: Vec<Identifier>,
+    // Where the checksum is kept on the heap.
+    checksum_heap_id: Identifier,
+}
+
+fn generate_checksum_hints(format: &Format, semantics: &Semantics) -> Vec<HintsChecksum> {
+    let mut hints: Vec<HintsChecksum> = format
+        .fields
+        .iter()
+        .filter_map(|field| {
+            let (algorithm, first, last) = semantics.get_checksum(&field.name)?;
+            // Unwraps OK: the parser checks the covered fields exist.
+            let first = format.fields.iter().position(|f| f.name == first).unwrap();
+            let last = format.fields.iter().position(|f| f.name == last).unwrap();
+            Some(HintsChecksum {
+                field_name: field.name.clone(),
+                algorithm,
+                endianness: field.endianness,
+                covered: format.fields[first..=last]
+                    .iter()
+                    .map(|f| f.name.clone())
+                    .collect(),
+                checksum_heap_id: (field.name.0.clone() + "_on_heap").as_str().id(),
+            })
+        })
+        .collect();
+
+    // A checksum covering other checksum fields must be taken after them.
+    // Unwraps OK: the parser checks checksums do not cover each other in a cycle.
+    let order = semantics.checksum_order(format).unwrap();
+    hints.sort_by_key(|h| order.iter().position(|name| *name == h.field_name));
+
+    hints
+}
+
+/// Whether a checksum and all the fields it covers are read in the prefix.
+fn is_read_in_prefix(prefix: &Format, hints_checksum: &HintsChecksum) -> bool {
+    std::iter::once(&hints_checksum.field_name)
+        .chain(&hints_checksum.covered)
+        .all(|name| prefix.try_get_field_by_name(name).is_some())
+}
+
+/// Checks the checksum of a received message against the bytes we read off
+/// the network, once decoded but before anything in them is decrypted.
+fn verify_checksum_instr(hints_checksum: &HintsChecksum) -> Instruction {
+    VerifyChecksumArgs {
+        from_heap_ids: hints_checksum.covered.clone(),
+        algorithm: hints_checksum.algorithm,
+        checksum_heap_id: hints_checksum.field_name.clone(),
+        endianness: hints_checksum.endianness,
+    }
+    .into()
 }
 
 #[derive(Debug)]
@@ -240,11 +670,63 @@ fn generate_encryption_hints(format: &Format, crypto_spec: &CryptoSpec) -> Optio
     }
 }
 
+/// Gets the encoding the given field is sent with, if any.
+fn field_encoding(format: &Format, field_id: &Identifier) -> Option<ByteEncoding> {
+    format.try_get_field_by_name(field_id)?.encoding
+}
+
+/// Gets the compression the given field is sent with, if any.
+fn field_compression(format: &Format, field_id: &Identifier) -> Option<Compression> {
+    format.try_get_field_by_name(field_id)?.compression
+}
+
+/// Gets where the sender keeps the bytes of a field as they are sent, which we
+/// need to measure the field. Fields are only copied out when encoded.
+fn wire_heap_id(format: &Format, field_id: &Identifier) -> Identifier {
+    match field_encoding(format, field_id) {
+        Some(_) => (field_id.0.clone() + "_wire_on_heap").as_str().id(),
+        None => field_id.clone(),
+    }
+}
+
+/// Copies the bytes of an encoded field out to its wire heap id.
+fn encode_for_wire_instr(format: &Format, field_id: &Identifier) -> Option<Instruction> {
+    Some(
+        EncodeBytesArgs {
+            from_heap_id: field_id.clone(),
+            encoding: field_encoding(format, field_id)?,
+            to_heap_id: wire_heap_id(format, field_id),
+        }
+        .into(),
+    )
+}
+
+/// Decodes the bytes of an encoded field we read off the network in place.
+fn decode_from_wire_instr(field: &Field) -> Option<Instruction> {
+    Some(
+        DecodeBytesArgs {
+            from_heap_id: field.name.clone(),
+            encoding: field.encoding?,
+            to_heap_id: field.name.clone(),
+        }
+        .into(),
+    )
+}
+
 static CFORMAT_HEAP_NAME: &str = "cformat_on_heap";
 static MESSAGE_HEAP_NAME: &str = "message_on_heap";
 static LEN_FIELD_HEAP_NAME: &str = "length_value_on_heap";
+static TARGET_SIZE_HEAP_NAME: &str = "target_size_on_heap";
+static MSG_PFX_HEAP_NAME: &str = "message_prefix_on_heap";
 
-fn compile_plaintext_commands_sender(format_id: &Identifier, psf: &Psf) -> Vec<Instruction> {
+/// The most bytes we read looking for the delimiter at the end of a field.
+const MAX_DELIMITED_LEN: usize = 8192;
+
+fn compile_plaintext_commands_sender(
+    format_id: &Identifier,
+    role: Role,
+    psf: &Psf,
+) -> Result<Vec<Instruction>> {
     let mut instrs: Vec<Instruction> = vec![];
 
     let afs = psf.formats.get(format_id).unwrap();
@@ -252,20 +734,329 @@ fn compile_plaintext_commands_sender(format_id: &Identifier, psf: &Psf) -> Vec<I
     let semantics = &afs.semantics;
 
     let maybe_hints_dynamic_payload = generate_dynamic_payload_hints(format, semantics);
+    let hints_arrays = generate_array_hints(format, semantics);
+    let maybe_padding_field_id = semantics.find_field_id(FieldSemantic::Padding);
+    let maybe_hints_padding = hints_arrays
+        .iter()
+        .find(|h| Some(&h.field_name) == maybe_padding_field_id.as_ref());
+    let maybe_size_dist = psf.traffic_spec.get_size(format_id);
+
+    // Dynamic arrays that are neither payload nor padding carry their fixed
+    // string, if they have one, and are otherwise left empty. Lists hold a
+    // single empty record.
+    let fixed_fields = semantics.get_fixed_fields();
+    let fixed_bytes = |id: &Identifier| {
+        fixed_fields
+            .iter()
+            .find(|(fixed_id, _)| fixed_id == id)
+            .map(|(_, bytes)| bytes.clone())
+    };
+    let wire_len = |id: &Identifier, len: usize| {
+        field_encoding(format, id).map_or(len, |encoding| encoding.encoded_len(len))
+    };
+    let other_arrays = hints_arrays
+        .iter()
+        .filter(|h| Some(&h.field_name) != maybe_padding_field_id.as_ref())
+        .map(|h| {
+            let bytes = match format.try_get_field_by_name(&h.field_name).unwrap().dtype {
+                Array::List(list) => empty_record(&list.record, psf)?,
+                _ => fixed_bytes(&h.field_name).unwrap_or_default(),
+            };
+            Ok((h, bytes))
+        })
+        .collect::<Result<Vec<(&HintsArray, Vec<u8>)>>>()?;
+
+    // Delimited fields carry their fixed string, if they have one, and always
+    // end with their delimiter. Textual numbers are otherwise zero.
+    let delimited: Vec<(Identifier, Vec<u8>)> = generate_delimited_hints(format)
+        .into_iter()
+        .map(|h| {
+            let bytes = match fixed_bytes(&h.field_name) {
+                Some(bytes) => [bytes, h.delimiter].concat(),
+                None => h.empty,
+            };
+            (h.field_name, bytes)
+        })
+        .collect();
+
+    // Optional fields hold their fixed string, if they have one, and are
+    // otherwise zero. They are dropped again if their condition does not hold.
+    let conditionals: Vec<(HintsConditional, Vec<u8>)> = generate_conditional_hints(format)
+        .into_iter()
+        .map(|h| {
+            let bytes = fixed_bytes(&h.field_name).unwrap_or(vec![0; h.present_len]);
+            (h, bytes)
+        })
+        .collect();
 
     // Handle dynamic length fields
     let mut dynamic_field_names = vec![];
 
     if let Some(ref hints_dynamic_payload) = maybe_hints_dynamic_payload {
+        let overhead = format.fixed_fields_size()
+            + other_arrays
+                .iter()
+                .map(|(h, bytes)| wire_len(&h.field_name, bytes.len()))
+                .sum::<usize>()
+            + delimited
+                .iter()
+                .map(|(_, bytes)| bytes.len())
+                .sum::<usize>();
+
+        // We only know which optional fields we send once we lay out the
+        // message, so we leave room for all of them.
+        let max_overhead = overhead
+            + conditionals
+                .iter()
+                .map(|(_, bytes)| bytes.len())
+                .sum::<usize>();
+
+        // The length field may also count the other arrays we lay out, which
+        // leaves that much less room for the payload.
+        let covered_overhead = match hints_dynamic_payload.length_meaning {
+            LengthMeaning::Covers {
+                ref covered_arrays, ..
+            } => {
+                other_arrays
+                    .iter()
+                    .filter(|(h, _)| covered_arrays.contains(&h.field_name))
+                    .map(|(h, bytes)| wire_len(&h.field_name, bytes.len()))
+                    .sum::<usize>()
+                    + delimited
+                        .iter()
+                        .filter(|(id, _)| covered_arrays.contains(id))
+                        .map(|(_, bytes)| bytes.len())
+                        .sum::<usize>()
+            }
+            LengthMeaning::Expr(_) => 0,
+        };
+
+        // Aligning may add up to a block less one byte of padding on top of
+        // the target, which the length field must still be able to count.
+        let align = psf.traffic_spec.alignments.get(format_id).copied();
+        let payload_max = hints_dynamic_payload
+            .length_field_max
+            .saturating_sub(covered_overhead + align.map_or(0, |align| align - 1));
+
+        // Pick the size we want the message to have on the wire. Without a
+        // size directive we never pad, so the target is just a placeholder.
+        if maybe_size_dist.is_some() || maybe_hints_padding.is_some() {
+            instrs.push(
+                SampleSizeArgs {
+                    from_distribution: maybe_size_dist.clone().unwrap_or(Distribution::Fixed(0)),
+                    within: max_overhead + 1..max_overhead + payload_max + 1,
+                    to_heap_id: TARGET_SIZE_HEAP_NAME.id(),
+                }
+                .into(),
+            );
+        }
+
+        let from_len = match maybe_size_dist {
+            Some(_) => {
+                ReadAppLength::UpToIdentifierMinus((TARGET_SIZE_HEAP_NAME.id(), max_overhead))
+            }
+            None => ReadAppLength::Range(1..payload_max),
+        };
+
         instrs.push(
             ReadAppArgs {
-                from_len: 1..hints_dynamic_payload.length_field_max,
+                from_len,
+                timeout: psf.traffic_spec.chaff.get(format_id).cloned(),
+                compression: field_compression(format, &hints_dynamic_payload.payload_field_name),
+                encoding: field_encoding(format, &hints_dynamic_payload.payload_field_name),
                 to_heap_id: hints_dynamic_payload.payload_field_name.clone(),
             }
             .into(),
         );
 
+        // Compressed app data is laid out and measured as it is sent.
+        if let Some(compression) =
+            field_compression(format, &hints_dynamic_payload.payload_field_name)
+        {
+            instrs.push(
+                CompressBytesArgs {
+                    from_heap_id: hints_dynamic_payload.payload_field_name.clone(),
+                    compression,
+                    to_heap_id: hints_dynamic_payload.payload_field_name.clone(),
+                }
+                .into(),
+            );
+        }
+
+        // Encoded fields are laid out as raw bytes, but we measure them as
+        // they are sent.
+        instrs.extend(encode_for_wire_instr(
+            format,
+            &hints_dynamic_payload.payload_field_name,
+        ));
+
         dynamic_field_names.push(hints_dynamic_payload.payload_field_name.clone());
+
+        for (hints_array, bytes) in &other_arrays {
+            instrs.push(
+                StoreBytesArgs {
+                    bytes: bytes.clone(),
+                    to_heap_id: hints_array.field_name.clone(),
+                }
+                .into(),
+            );
+            instrs.extend(encode_for_wire_instr(format, &hints_array.field_name));
+
+            dynamic_field_names.push(hints_array.field_name.clone());
+        }
+
+        for (field_name, bytes) in &delimited {
+            instrs.push(
+                StoreBytesArgs {
+                    bytes: bytes.clone(),
+                    to_heap_id: field_name.clone(),
+                }
+                .into(),
+            );
+
+            dynamic_field_names.push(field_name.clone());
+        }
+
+        // Optional fields depend on values in the static prefix, which we
+        // lay out on its own to test their conditions like the receiver does.
+        if !conditionals.is_empty() {
+            let (static_prefix, _) = format.split_into_fixed_sized_prefix_dynamic_suffix();
+            instrs.push(
+                ConcretizeFormatArgs {
+                    from_format: AbstractFormat {
+                        format: static_prefix,
+                        fixed_fields: fixed_fields.clone(),
+                    },
+                    to_heap_id: CFORMAT_HEAP_NAME.id(),
+                }
+                .into(),
+            );
+            instrs.push(
+                CreateMessageArgs {
+                    from_format_heap_id: CFORMAT_HEAP_NAME.id(),
+                    to_heap_id: MSG_PFX_HEAP_NAME.id(),
+                }
+                .into(),
+            );
+        }
+
+        for (hints_conditional, bytes) in &conditionals {
+            instrs.push(
+                StoreBytesArgs {
+                    bytes: bytes.clone(),
+                    to_heap_id: hints_conditional.field_name.clone(),
+                }
+                .into(),
+            );
+            instrs.extend(load_conditional_length_instrs(
+                hints_conditional,
+                MSG_PFX_HEAP_NAME.id(),
+            ));
+            instrs.push(
+                TruncateBytesArgs {
+                    from_heap_id: hints_conditional.field_name.clone(),
+                    from_len_heap_id: hints_conditional.length_heap_id.clone(),
+                    to_heap_id: hints_conditional.field_name.clone(),
+                }
+                .into(),
+            );
+
+            dynamic_field_names.push(hints_conditional.field_name.clone());
+        }
+
+        // Fill whatever the app data did not cover with padding.
+        if let Some(hints_padding) = maybe_hints_padding {
+            instrs.push(
+                GenPaddingArgs {
+                    from_target_heap_id: TARGET_SIZE_HEAP_NAME.id(),
+                    from_heap_ids: std::iter::once(wire_heap_id(
+                        format,
+                        &hints_dynamic_payload.payload_field_name,
+                    ))
+                    .chain(conditionals.iter().map(|(h, _)| h.field_name.clone()))
+                    .collect(),
+                    overhead,
+                    max_len: hints_padding.length_field_max,
+                    step: hints_padding.step(),
+                    align,
+                    to_heap_id: hints_padding.field_name.clone(),
+                }
+                .into(),
+            );
+
+            dynamic_field_names.push(hints_padding.field_name.clone());
+        }
+
+        // Unless the length field counts everything after the static prefix,
+        // we work out its value from the sizes of the arrays it describes.
+        if hints_dynamic_payload.static_prefix_last_field.is_none() {
+            let (from_heap_ids, plus, unit) = match hints_dynamic_payload.length_meaning {
+                LengthMeaning::Covers {
+                    fixed_size,
+                    ref covered_arrays,
+                } => {
+                    let mut from_heap_ids = vec![hints_dynamic_payload.payload_field_name.clone()];
+                    from_heap_ids.extend(covered_arrays.iter().cloned());
+                    (from_heap_ids, fixed_size as isize, 1)
+                }
+                LengthMeaning::Expr(ref expr) => (
+                    vec![hints_dynamic_payload.payload_field_name.clone()],
+                    -expr.bias,
+                    expr.scale,
+                ),
+            };
+
+            instrs.push(
+                SumLengthsArgs {
+                    from_heap_ids: from_heap_ids
+                        .iter()
+                        .map(|id| wire_heap_id(format, id))
+                        .collect(),
+                    plus,
+                    unit,
+                    to_heap_id: LEN_FIELD_HEAP_NAME.id(),
+                }
+                .into(),
+            );
+
+            // Varint and delimited text length fields take their size from
+            // their value, so we encode them before laying out the message.
+            if let Some(ref encoding) = hints_dynamic_payload.length_field_encoding {
+                instrs.push(encoding.encode_instr(
+                    LEN_FIELD_HEAP_NAME.id(),
+                    hints_dynamic_payload.length_field_name.clone(),
+                ));
+
+                dynamic_field_names.push(hints_dynamic_payload.length_field_name.clone());
+            }
+        }
+
+        // Every other array has a length field of its own.
+        for hints_array in &hints_arrays {
+            let (plus, unit) = hints_array
+                .length_expr
+                .as_ref()
+                .map_or((0, 1), |expr| (-expr.bias, expr.scale));
+
+            instrs.push(
+                SumLengthsArgs {
+                    from_heap_ids: vec![wire_heap_id(format, &hints_array.field_name)],
+                    plus,
+                    unit,
+                    to_heap_id: hints_array.length_heap_id.clone(),
+                }
+                .into(),
+            );
+
+            if let Some(ref encoding) = hints_array.length_field_encoding {
+                instrs.push(encoding.encode_instr(
+                    hints_array.length_heap_id.clone(),
+                    hints_array.length_field_name.clone(),
+                ));
+
+                dynamic_field_names.push(hints_array.length_field_name.clone());
+            }
+        }
     }
 
     instrs.push(
@@ -298,23 +1089,261 @@ fn compile_plaintext_commands_sender(format_id: &Identifier, psf: &Psf) -> Vec<I
         );
     }
 
-    // If there's a length field to set, set it here.
-
-    if let Some(ref hints_dynamic_payload) = maybe_hints_dynamic_payload {
+    // Sequence numbers count the messages we send with this format.
+    if let Some(field_id) = afs.semantics.find_field_id(FieldSemantic::SequenceNumber) {
+        let num_heap_id = (field_id.0.clone() + "_value_on_heap").as_str().id();
         instrs.push(
-            ComputeLengthArgs {
-                from_msg_heap_id: MESSAGE_HEAP_NAME.id(),
-                from_field_id: hints_dynamic_payload.static_prefix_last_field.clone(),
-                to_heap_id: LEN_FIELD_HEAP_NAME.id(),
+            NextSequenceNumberArgs {
+                counter_id: sequence_counter_id(format_id, &field_id, true),
+                // Unwrap OK: the parser checks sequence numbers are unsigned.
+                max: format.try_get_unsigned_max(&field_id).unwrap(),
+                to_heap_id: num_heap_id.clone(),
             }
             .into(),
         );
+        instrs.push(
+            SetNumericValueArgs {
+                from_heap_id: num_heap_id,
+                to_msg_heap_id: MESSAGE_HEAP_NAME.id(),
+                to_field_id: field_id,
+            }
+            .into(),
+        );
+    }
 
+    // Constrained fields take a random value among those allowed.
+    for (field_id, constraint) in afs.semantics.get_constraints() {
+        let value_heap_id = (field_id.0.clone() + "_value_on_heap").as_str().id();
+        instrs.push(
+            SampleValueArgs {
+                from_constraint: constraint,
+                to_heap_id: value_heap_id.clone(),
+            }
+            .into(),
+        );
         instrs.push(
             SetNumericValueArgs {
-                from_heap_id: LEN_FIELD_HEAP_NAME.id(),
+                from_heap_id: value_heap_id,
                 to_msg_heap_id: MESSAGE_HEAP_NAME.id(),
-                to_field_id: hints_dynamic_payload.length_field_name.clone(),
+                to_field_id: field_id,
+            }
+            .into(),
+        );
+    }
+
+    // Saved fields take random bytes the first time we send them, which they
+    // then keep for the rest of the connection.
+    for (field_id, var_id) in afs.semantics.get_saved_fields() {
+        // Unwrap OK: the parser checks saved fields have a fixed size.
+        let size = format
+            .try_get_field_by_name(&field_id)
+            .and_then(|field| field.maybe_size_of())
+            .unwrap();
+        let bytes_heap_id = (field_id.0.clone() + "_bytes_on_heap").as_str().id();
+        instrs.push(
+            GenVariableArgs {
+                variable_id: connection_variable_id(&var_id, role),
+                len: size,
+                to_heap_id: bytes_heap_id.clone(),
+            }
+            .into(),
+        );
+        instrs.push(
+            SetArrayBytesArgs {
+                from_heap_id: bytes_heap_id,
+                to_msg_heap_id: MESSAGE_HEAP_NAME.id(),
+                to_field_id: field_id,
+            }
+            .into(),
+        );
+    }
+
+    for (field_id, var_id) in afs.semantics.get_loaded_fields() {
+        let bytes_heap_id = (field_id.0.clone() + "_bytes_on_heap").as_str().id();
+        instrs.push(
+            LoadVariableArgs {
+                from_variable_id: loaded_variable_id(psf, &var_id, role),
+                to_heap_id: bytes_heap_id.clone(),
+            }
+            .into(),
+        );
+        instrs.push(
+            SetArrayBytesArgs {
+                from_heap_id: bytes_heap_id,
+                to_msg_heap_id: MESSAGE_HEAP_NAME.id(),
+                to_field_id: field_id,
+            }
+            .into(),
+        );
+    }
+
+    if let Some(ref hints_dynamic_payload) = maybe_hints_dynamic_payload {
+        if let Some(ref static_prefix_last_field) = hints_dynamic_payload.static_prefix_last_field {
+            instrs.push(
+                ComputeLengthArgs {
+                    from_msg_heap_id: MESSAGE_HEAP_NAME.id(),
+                    from_field_id: static_prefix_last_field.clone(),
+                    to_heap_id: LEN_FIELD_HEAP_NAME.id(),
+                }
+                .into(),
+            );
+        }
+
+        if hints_dynamic_payload.length_field_encoding.is_none() {
+            instrs.push(
+                SetNumericValueArgs {
+                    from_heap_id: LEN_FIELD_HEAP_NAME.id(),
+                    to_msg_heap_id: MESSAGE_HEAP_NAME.id(),
+                    to_field_id: hints_dynamic_payload.length_field_name.clone(),
+                }
+                .into(),
+            );
+        }
+
+        for hints_array in &hints_arrays {
+            if hints_array.length_field_encoding.is_none() {
+                instrs.push(
+                    SetNumericValueArgs {
+                        from_heap_id: hints_array.length_heap_id.clone(),
+                        to_msg_heap_id: MESSAGE_HEAP_NAME.id(),
+                        to_field_id: hints_array.length_field_name.clone(),
+                    }
+                    .into(),
+                );
+            }
+        }
+    }
+
+    Ok(instrs)
+}
+
+/// Names the connection's counter for a sequence number field. Messages we
+/// send and receive are counted apart, even when they share a format.
+fn sequence_counter_id(
+    format_id: &Identifier,
+    field_id: &Identifier,
+    is_sender: bool,
+) -> Identifier {
+    let direction = if is_sender { "sent" } else { "received" };
+    format!("{}.{}.{}", format_id.0, field_id.0, direction)
+        .as_str()
+        .id()
+}
+
+/// Checks a received sequence number, if the message has one and it is held
+/// in the given part of the message.
+fn verify_sequence_number_instr(
+    format_id: &Identifier,
+    semantics: &Semantics,
+    part: &Format,
+    msg_heap_id: Identifier,
+) -> Option<Instruction> {
+    let field_id = semantics.find_field_id(FieldSemantic::SequenceNumber)?;
+    let max = part.try_get_unsigned_max(&field_id)?;
+    Some(
+        VerifySequenceNumberArgs {
+            from_msg_heap_id: msg_heap_id,
+            counter_id: sequence_counter_id(format_id, &field_id, false),
+            from_field_id: field_id,
+            max,
+        }
+        .into(),
+    )
+}
+
+/// Names a connection variable. Each role saves its own variables, even when
+/// they share a name, so the name includes the role that saves it.
+fn connection_variable_id(var_id: &Identifier, saver: Role) -> Identifier {
+    let role = match saver {
+        Role::Client => "client",
+        Role::Server => "server",
+    };
+    format!("{}.{}", var_id.0, role).as_str().id()
+}
+
+/// Names the connection variable a field in a message sent by `role` loads.
+fn loaded_variable_id(psf: &Psf, var_id: &Identifier, role: Role) -> Identifier {
+    // Unwrap OK: the parser checks loaded variables are saved.
+    connection_variable_id(var_id, psf.variable_saver(var_id, role).unwrap())
+}
+
+/// Saves the received fields held in the given part of the message to their
+/// connection variables, and checks those that should hold a variable's value.
+fn variable_instrs(
+    psf: &Psf,
+    edge_role: Role,
+    semantics: &Semantics,
+    part: &Format,
+    msg_heap_id: Identifier,
+) -> Vec<Instruction> {
+    let saved = semantics
+        .get_saved_fields()
+        .into_iter()
+        .filter(|(field_id, _)| part.try_get_field_by_name(field_id).is_some())
+        .map(|(field_id, var_id)| {
+            SaveVariableArgs {
+                from_msg_heap_id: msg_heap_id.clone(),
+                from_field_id: field_id,
+                to_variable_id: connection_variable_id(&var_id, edge_role),
+            }
+            .into()
+        });
+    let loaded = semantics
+        .get_loaded_fields()
+        .into_iter()
+        .filter(|(field_id, _)| part.try_get_field_by_name(field_id).is_some())
+        .map(|(field_id, var_id)| {
+            VerifyVariableArgs {
+                from_msg_heap_id: msg_heap_id.clone(),
+                from_field_id: field_id,
+                variable_id: loaded_variable_id(psf, &var_id, edge_role),
+            }
+            .into()
+        });
+    saved.chain(loaded).collect()
+}
+
+/// Loads the value of a length field onto the number heap. Fixed-size length
+/// fields are read from the received prefix message, while varint and delimited
+/// text length fields are decoded straight from the bytes we read off the
+/// network.
+fn load_length_instr(
+    length_field_name: &Identifier,
+    encoding: Option<&LengthEncoding>,
+    from_msg_heap_id: Identifier,
+    to_heap_id: Identifier,
+) -> Instruction {
+    match encoding {
+        Some(encoding) => encoding.decode_instr(length_field_name.clone(), to_heap_id),
+        None => GetNumericValueArgs {
+            from_msg_heap_id,
+            from_field_id: length_field_name.clone(),
+            to_heap_id,
+        }
+        .into(),
+    }
+}
+
+/// Loads the size in bytes of a dynamic array other than the payload onto the
+/// number heap.
+fn load_array_length_instrs(
+    hints_array: &HintsArray,
+    from_msg_heap_id: Identifier,
+) -> Vec<Instruction> {
+    let mut instrs = vec![load_length_instr(
+        &hints_array.length_field_name,
+        hints_array.length_field_encoding.as_ref(),
+        from_msg_heap_id,
+        hints_array.length_heap_id.clone(),
+    )];
+
+    if let Some(ref expr) = hints_array.length_expr {
+        instrs.push(
+            ScaleNumericValueArgs {
+                from_heap_id: hints_array.length_heap_id.clone(),
+                scale: expr.scale,
+                bias: expr.bias,
+                to_heap_id: hints_array.length_heap_id.clone(),
             }
             .into(),
         );
@@ -323,12 +1352,36 @@ fn compile_plaintext_commands_sender(format_id: &Identifier, psf: &Psf) -> Vec<I
     instrs
 }
 
+/// Loads the size in bytes of an optional field onto the number heap. Its
+/// condition only depends on the prefix message.
+fn load_conditional_length_instrs(
+    hints_conditional: &HintsConditional,
+    from_msg_heap_id: Identifier,
+) -> Vec<Instruction> {
+    vec![
+        GetNumericValueArgs {
+            from_msg_heap_id,
+            from_field_id: hints_conditional.condition.field.clone(),
+            to_heap_id: hints_conditional.length_heap_id.clone(),
+        }
+        .into(),
+        EvalConditionArgs {
+            from_heap_id: hints_conditional.length_heap_id.clone(),
+            condition: hints_conditional.condition.clone(),
+            present_len: hints_conditional.present_len,
+            to_heap_id: hints_conditional.length_heap_id.clone(),
+        }
+        .into(),
+    ]
+}
+
 fn compile_message_to_instrs(
     my_role: Role,
     edge_role: Role,
+    edge_phase: Phase,
     format_id: &Identifier,
     psf: &Psf,
-) -> Vec<Instruction> {
+) -> Result<Vec<Instruction>> {
     let mut instrs: Vec<Instruction> = vec![];
 
     let afs = psf.formats.get(format_id).unwrap();
@@ -338,6 +1391,10 @@ fn compile_message_to_instrs(
     let is_sender = my_role == edge_role;
 
     let maybe_hints_dynamic_payload = generate_dynamic_payload_hints(format, semantics);
+    let hints_arrays = generate_array_hints(format, semantics);
+    let hints_conditionals = generate_conditional_hints(format);
+    let hints_delimited = generate_delimited_hints(format);
+    let hints_checksums = generate_checksum_hints(format, semantics);
 
     if is_sender {
         if let Some(ref crypto_spec) = psf.crypto_spec {
@@ -351,8 +1408,9 @@ fn compile_message_to_instrs(
                 // Set up the original message
                 instrs.extend(compile_plaintext_commands_sender(
                     &hints_encryption.starting_format,
+                    edge_role,
                     psf,
-                ));
+                )?);
 
                 // Then encrypt whatever fields we need to encrypt
                 for field_dir in &hints_encryption.enc_field_dirs {
@@ -389,10 +1447,50 @@ fn compile_message_to_instrs(
                     );
                 }
             } else {
-                instrs.extend(compile_plaintext_commands_sender(format_id, psf));
+                instrs.extend(compile_plaintext_commands_sender(
+                    format_id, edge_role, psf,
+                )?);
             }
         } else {
-            instrs.extend(compile_plaintext_commands_sender(format_id, psf));
+            instrs.extend(compile_plaintext_commands_sender(
+                format_id, edge_role, psf,
+            )?);
+        }
+
+        // Checksums cover the fields as laid out in the message, i.e. after
+        // encryption but before any encoding for the wire, so we take them
+        // once everything else is set and encrypted.
+        for hints_checksum in &hints_checksums {
+            instrs.push(
+                ComputeChecksumArgs {
+                    from_msg_heap_id: MESSAGE_HEAP_NAME.id(),
+                    // Unwraps OK: a checksum covers at least one field.
+                    from_first_field_id: hints_checksum.covered.first().unwrap().clone(),
+                    from_last_field_id: hints_checksum.covered.last().unwrap().clone(),
+                    algorithm: hints_checksum.algorithm,
+                    checksum_field_id: hints_checksum.field_name.clone(),
+                    to_heap_id: hints_checksum.checksum_heap_id.clone(),
+                }
+                .into(),
+            );
+            instrs.push(
+                SetNumericValueArgs {
+                    from_heap_id: hints_checksum.checksum_heap_id.clone(),
+                    to_msg_heap_id: MESSAGE_HEAP_NAME.id(),
+                    to_field_id: hints_checksum.field_name.clone(),
+                }
+                .into(),
+            );
+        }
+
+        // Hold the message back to mimic the timing of the cover protocol.
+        if let Some(dist) = psf.traffic_spec.get_delay(format_id, edge_phase) {
+            instrs.push(
+                DelayArgs {
+                    from_distribution: dist.clone(),
+                }
+                .into(),
+            );
         }
 
         instrs.push(
@@ -411,15 +1509,43 @@ fn compile_message_to_instrs(
         const CFORMAT_PFX_HEAP_NAME: &str = "cformat_prefix_on_heap";
         const CFORMAT_SFX_HEAP_NAME: &str = "cformat_suffix_on_heap";
 
-        const MSG_PFX_HEAP_NAME: &str = "message_prefix_on_heap";
         const MSG_SFX_HEAP_NAME: &str = "message_suffix_on_heap";
 
         const LENGTH_ON_HEAP_NAME: &str = "num_payload_bytes_on_heap";
 
+        // Delimited fields hold their fixed string followed by the delimiter.
+        let fixed_fields: Vec<(Identifier, Vec<u8>)> = semantics
+            .get_fixed_fields()
+            .into_iter()
+            .map(|(id, bytes)| {
+                let delimiter = format
+                    .try_get_field_by_name(&id)
+                    .and_then(|field| field.dtype.try_get_delimiter());
+                (id, [bytes, delimiter.unwrap_or_default()].concat())
+            })
+            .collect();
+
+        // The PSF does not say whether fixed fields are checked in constant
+        // time. We infer it: if the CRYPTO segment encrypts any fixed field of
+        // the format, its value is a plaintext the check must not leak by
+        // taking longer the more of it matches, so all fixed fields of the
+        // format are checked in constant time. Fixed fields that are only sent
+        // in the clear are no secret, and are checked as usual.
+        let fixed_in_constant_time = psf
+            .crypto_spec
+            .as_ref()
+            .and_then(|crypto_spec| generate_encryption_hints(format, crypto_spec))
+            .is_some_and(|hints| {
+                hints
+                    .enc_field_dirs
+                    .iter()
+                    .any(|dir| fixed_fields.iter().any(|(id, _)| id == &dir.ptext_name))
+            });
+
         if has_prefix {
             // Read the fixed-size elements
             for field in &prefix.fields[..] {
-                let field_nbytes = field.maybe_size_of().unwrap();
+                let field_nbytes = field.maybe_wire_size_of().unwrap();
 
                 instrs.push(
                     ReadNetArgs {
@@ -428,6 +1554,13 @@ fn compile_message_to_instrs(
                     }
                     .into(),
                 );
+                instrs.extend(decode_from_wire_instr(field));
+            }
+
+            for hints_checksum in &hints_checksums {
+                if is_read_in_prefix(&prefix, hints_checksum) {
+                    instrs.push(verify_checksum_instr(hints_checksum));
+                }
             }
 
             instrs.push(
@@ -508,49 +1641,216 @@ fn compile_message_to_instrs(
                     }
                 }
             }
+
+            // Fixed and constrained values are checked in plaintext.
+            instrs.push(
+                VerifyFixedFieldsArgs {
+                    from_msg_heap_id: MSG_PFX_HEAP_NAME.id(),
+                    fixed_fields: fixed_fields.clone(),
+                    constant_time: fixed_in_constant_time,
+                }
+                .into(),
+            );
+            instrs.push(
+                VerifyValuesArgs {
+                    from_msg_heap_id: MSG_PFX_HEAP_NAME.id(),
+                    constraints: semantics.get_constraints(),
+                }
+                .into(),
+            );
+            instrs.extend(verify_sequence_number_instr(
+                format_id,
+                semantics,
+                &prefix,
+                MSG_PFX_HEAP_NAME.id(),
+            ));
+            instrs.extend(variable_instrs(
+                psf,
+                edge_role,
+                semantics,
+                &prefix,
+                MSG_PFX_HEAP_NAME.id(),
+            ));
         } // has_prefix
 
         if has_suffix {
-            // Figure out how much stuff is the the fixed-sized tail on the suffix,
-            // which is covered by the length field.
-            let (_suffix_dynamic_head, suffix_fixed_tail) =
-                suffix.split_into_dynamic_prefix_and_fixed_suffix();
+            if let Some(ref hints_dynamic_payload) = maybe_hints_dynamic_payload {
+                // The sizes of the other arrays we have loaded onto the heap.
+                let mut loaded_arrays: Vec<&Identifier> = vec![];
+
+                for field in &suffix.fields {
+                    let from_len = match (field.maybe_wire_size_of(), &field.dtype) {
+                        (Some(field_len), _) => ReadNetLength::Range(field_len..field_len + 1),
+                        (None, Array::Varint(Varint(vtype, _))) => ReadNetLength::Varint(*vtype),
+                        (None, _) if field.name == hints_dynamic_payload.payload_field_name => {
+                            // Assumes there's only one payload...
+                            instrs.push(load_length_instr(
+                                &hints_dynamic_payload.length_field_name,
+                                hints_dynamic_payload.length_field_encoding.as_ref(),
+                                MSG_PFX_HEAP_NAME.id(),
+                                LENGTH_ON_HEAP_NAME.id(),
+                            ));
+
+                            match hints_dynamic_payload.length_meaning {
+                                LengthMeaning::Covers {
+                                    fixed_size,
+                                    ref covered_arrays,
+                                } => {
+                                    // The length field also covers the other arrays, like
+                                    // padding, so we take their sizes away.
+                                    for hints_array in hints_arrays
+                                        .iter()
+                                        .filter(|h| covered_arrays.contains(&h.field_name))
+                                    {
+                                        if !loaded_arrays.contains(&&hints_array.field_name) {
+                                            instrs.extend(load_array_length_instrs(
+                                                hints_array,
+                                                MSG_PFX_HEAP_NAME.id(),
+                                            ));
+                                            loaded_arrays.push(&hints_array.field_name);
+                                        }
+
+                                        instrs.push(
+                                            SubtractNumericValueArgs {
+                                                from_heap_id: LENGTH_ON_HEAP_NAME.id(),
+                                                minus_heap_id: hints_array.length_heap_id.clone(),
+                                                to_heap_id: LENGTH_ON_HEAP_NAME.id(),
+                                            }
+                                            .into(),
+                                        );
+                                    }
 
-            let fixed_tail_size = suffix_fixed_tail.fixed_fields_size();
+                                    // Delimited arrays come before the payload, so we
+                                    // have already read them.
+                                    for hints_delimited in hints_delimited
+                                        .iter()
+                                        .filter(|h| covered_arrays.contains(&h.field_name))
+                                    {
+                                        instrs.push(
+                                            SumLengthsArgs {
+                                                from_heap_ids: vec![hints_delimited
+                                                    .field_name
+                                                    .clone()],
+                                                plus: 0,
+                                                unit: 1,
+                                                to_heap_id: hints_delimited.length_heap_id.clone(),
+                                            }
+                                            .into(),
+                                        );
+                                        instrs.push(
+                                            SubtractNumericValueArgs {
+                                                from_heap_id: LENGTH_ON_HEAP_NAME.id(),
+                                                minus_heap_id: hints_delimited
+                                                    .length_heap_id
+                                                    .clone(),
+                                                to_heap_id: LENGTH_ON_HEAP_NAME.id(),
+                                            }
+                                            .into(),
+                                        );
+                                    }
 
-            if let Some(ref hints_dynamic_payload) = maybe_hints_dynamic_payload {
-                // The length field must exist in the fixed-size prefix.
-                // Assumes there's only one payload...
-                instrs.push(
-                    GetNumericValueArgs {
-                        from_msg_heap_id: MSG_PFX_HEAP_NAME.id(),
-                        from_field_id: hints_dynamic_payload.length_field_name.clone(),
-                        to_heap_id: LENGTH_ON_HEAP_NAME.id(),
-                    }
-                    .into(),
-                );
+                                    for hints_conditional in hints_conditionals
+                                        .iter()
+                                        .filter(|h| covered_arrays.contains(&h.field_name))
+                                    {
+                                        if !loaded_arrays.contains(&&hints_conditional.field_name) {
+                                            instrs.extend(load_conditional_length_instrs(
+                                                hints_conditional,
+                                                MSG_PFX_HEAP_NAME.id(),
+                                            ));
+                                            loaded_arrays.push(&hints_conditional.field_name);
+                                        }
+
+                                        instrs.push(
+                                            SubtractNumericValueArgs {
+                                                from_heap_id: LENGTH_ON_HEAP_NAME.id(),
+                                                minus_heap_id: hints_conditional
+                                                    .length_heap_id
+                                                    .clone(),
+                                                to_heap_id: LENGTH_ON_HEAP_NAME.id(),
+                                            }
+                                            .into(),
+                                        );
+                                    }
 
-                instrs.push(
-                    ReadNetArgs {
-                        from_len: ReadNetLength::IdentifierMinus((
-                            LENGTH_ON_HEAP_NAME.id(),
-                            fixed_tail_size,
-                        )),
-                        to_heap_id: hints_dynamic_payload.payload_field_name.clone(),
-                    }
-                    .into(),
-                );
+                                    ReadNetLength::IdentifierMinus((
+                                        LENGTH_ON_HEAP_NAME.id(),
+                                        fixed_size,
+                                    ))
+                                }
+                                LengthMeaning::Expr(ref expr) => {
+                                    instrs.push(
+                                        ScaleNumericValueArgs {
+                                            from_heap_id: LENGTH_ON_HEAP_NAME.id(),
+                                            scale: expr.scale,
+                                            bias: expr.bias,
+                                            to_heap_id: LENGTH_ON_HEAP_NAME.id(),
+                                        }
+                                        .into(),
+                                    );
+
+                                    ReadNetLength::Identifier(LENGTH_ON_HEAP_NAME.id())
+                                }
+                            }
+                        }
+                        (None, Array::Dynamic(DynamicArray(UnaryOp::Until(delimiter))))
+                        | (
+                            None,
+                            Array::Text(TextNumber {
+                                end: TextEnd::Until(delimiter),
+                                ..
+                            }),
+                        ) => ReadNetLength::Delimiter((delimiter.clone(), MAX_DELIMITED_LEN)),
+                        (None, Array::Conditional(_)) => {
+                            let hints_conditional = hints_conditionals
+                                .iter()
+                                .find(|h| h.field_name == field.name)
+                                .unwrap();
+
+                            if !loaded_arrays.contains(&&hints_conditional.field_name) {
+                                instrs.extend(load_conditional_length_instrs(
+                                    hints_conditional,
+                                    MSG_PFX_HEAP_NAME.id(),
+                                ));
+                                loaded_arrays.push(&hints_conditional.field_name);
+                            }
 
-                for field in &suffix_fixed_tail.fields {
-                    let field_len = field.maybe_size_of().unwrap();
+                            ReadNetLength::Identifier(hints_conditional.length_heap_id.clone())
+                        }
+                        (None, _) => {
+                            let hints_array = hints_arrays
+                                .iter()
+                                .find(|h| h.field_name == field.name)
+                                .ok_or_else(|| {
+                                    anyhow!("no length field gives the size of {}", field.name.0)
+                                })?;
+
+                            if !loaded_arrays.contains(&&hints_array.field_name) {
+                                instrs.extend(load_array_length_instrs(
+                                    hints_array,
+                                    MSG_PFX_HEAP_NAME.id(),
+                                ));
+                                loaded_arrays.push(&hints_array.field_name);
+                            }
+
+                            ReadNetLength::Identifier(hints_array.length_heap_id.clone())
+                        }
+                    };
 
                     instrs.push(
                         ReadNetArgs {
-                            from_len: ReadNetLength::Range(field_len..field_len + 1),
+                            from_len,
                             to_heap_id: field.name.clone(),
                         }
                         .into(),
                     );
+                    instrs.extend(decode_from_wire_instr(field));
+                }
+
+                for hints_checksum in &hints_checksums {
+                    if !is_read_in_prefix(&prefix, hints_checksum) {
+                        instrs.push(verify_checksum_instr(hints_checksum));
+                    }
                 }
 
                 instrs.push(
@@ -631,10 +1931,46 @@ fn compile_message_to_instrs(
                     }
                 }
 
+                instrs.push(
+                    VerifyFixedFieldsArgs {
+                        from_msg_heap_id: MSG_SFX_HEAP_NAME.id(),
+                        fixed_fields: fixed_fields.clone(),
+                        constant_time: fixed_in_constant_time,
+                    }
+                    .into(),
+                );
+                instrs.push(
+                    VerifyValuesArgs {
+                        from_msg_heap_id: MSG_SFX_HEAP_NAME.id(),
+                        constraints: semantics.get_constraints(),
+                    }
+                    .into(),
+                );
+                instrs.extend(verify_sequence_number_instr(
+                    format_id,
+                    semantics,
+                    &suffix,
+                    MSG_SFX_HEAP_NAME.id(),
+                ));
+                instrs.extend(variable_instrs(
+                    psf,
+                    edge_role,
+                    semantics,
+                    &suffix,
+                    MSG_SFX_HEAP_NAME.id(),
+                ));
+
                 instrs.push(
                     WriteAppArgs {
                         from_msg_heap_id: MSG_SFX_HEAP_NAME.id(),
                         from_field_id: hints_dynamic_payload.payload_field_name.clone(),
+                        // The sender never reads more app data than the
+                        // payload could hold uncompressed.
+                        compression: field_compression(
+                            &suffix,
+                            &hints_dynamic_payload.payload_field_name,
+                        )
+                        .map(|compression| (compression, hints_dynamic_payload.length_field_max)),
                     }
                     .into(),
                 );
@@ -642,7 +1978,7 @@ fn compile_message_to_instrs(
         } // has_suffix
     } // receiver
 
-    instrs
+    Ok(instrs)
 }
 
 #[cfg(test)]
@@ -3,33 +3,54 @@ use std::{
     ops::Range,
     sync::{Arc, Mutex},
     task::Poll,
+    time::Duration,
 };
 
 use bytes::{BufMut, Bytes, BytesMut};
+use rand::{Rng, RngCore};
 
 use crate::crypto::{
     chacha::{Cipher, CipherKind},
     kdf,
 };
 use crate::lang::{
+    adaptive::AdaptivePadding,
     common::Role,
     interpreter,
     mem::Heap,
-    message::Message,
+    message::{GetFieldError, Message},
     spec::proteus::ProteusSpec,
-    task::{Instruction, ReadNetLength, Task, TaskID, TaskProvider, TaskSet},
-    types::{ConcreteFormat, Identifier},
+    task::{Instruction, ReadAppLength, ReadNetLength, Task, TaskID, TaskProvider, TaskSet},
+    types::{ConcreteFormat, Endianness, Identifier, ToIdentifier},
 };
 
 #[derive(std::fmt::Debug)]
 pub enum Error {
     ExecuteFailed,
+    CompressedTooLong,
+    DecompressionFailed,
+    ChecksumMismatch,
+    FixedFieldMismatch,
+    ValueNotAllowed,
+    UnexpectedSequenceNumber,
+    VariableMismatch,
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Error::ExecuteFailed => write!(f, "Failed to execute instruction"),
+            Error::CompressedTooLong => write!(f, "Compressed data outgrew its bound"),
+            Error::DecompressionFailed => {
+                write!(f, "Compressed data was malformed or decompressed too long")
+            }
+            Error::ChecksumMismatch => write!(f, "Checksum did not match the message"),
+            Error::FixedFieldMismatch => write!(f, "Field did not hold its fixed value"),
+            Error::ValueNotAllowed => write!(f, "Field held a value it does not allow"),
+            Error::UnexpectedSequenceNumber => {
+                write!(f, "Sequence number was not the one expected")
+            }
+            Error::VariableMismatch => write!(f, "Field did not hold its variable's value"),
         }
     }
 }
@@ -50,14 +71,25 @@ pub struct SendArgs {
 pub struct RecvArgs {
     // Receive this many bytes.
     pub len: Range<usize>,
+    // Give up and store empty bytes if nothing arrives within this time.
+    pub timeout: Option<Duration>,
+    // Receive up to and including the first occurrence of these bytes instead.
+    pub delimiter: Option<Vec<u8>>,
     // Store the bytes at this addr on the heap.
     pub addr: Identifier,
 }
 
+#[derive(Debug)]
+pub struct SleepArgs {
+    // Wait this long before running the next command.
+    pub duration: Duration,
+}
+
 #[derive(Debug)]
 pub enum NetOpOut {
     RecvApp(RecvArgs),
     SendNet(SendArgs),
+    Sleep(SleepArgs),
     _Close,
     Error(String),
 }
@@ -70,6 +102,9 @@ pub enum NetOpIn {
     Error(String),
 }
 
+/// Where pieces of a varint are received before being appended to the rest.
+static VARINT_PART_HEAP_NAME: &str = "varint_part_on_heap";
+
 struct Program {
     task: Task,
     next_ins_index: usize,
@@ -95,11 +130,51 @@ impl Program {
         self.next_ins_index < self.task.ins.len()
     }
 
+    /// Whether the next instruction loads a variable the connection has not
+    /// saved yet, e.g. because we have not received the field holding it.
+    fn waits_for_variable(&self, interpreter: &Interpreter) -> bool {
+        match &self.task.ins[self.next_ins_index] {
+            Instruction::LoadVariable(args) => {
+                interpreter.variables.get(&args.from_variable_id).is_none()
+            }
+            _ => false,
+        }
+    }
+
     fn execute_next_instruction(
         &mut self,
         interpreter: &mut Interpreter,
     ) -> Result<(), interpreter::Error> {
         match &self.task.ins[self.next_ins_index] {
+            Instruction::CompressBytes(args) => {
+                let bytes = self
+                    .bytes_heap
+                    .get(&args.from_heap_id)
+                    .ok_or(Error::ExecuteFailed)?;
+                let compressed = args.compression.compress(bytes);
+                // We only read as much app data as fits in the message if it
+                // compresses no worse than this.
+                if compressed.len() > args.compression.max_compressed_len(bytes.len()) {
+                    return Err(Error::CompressedTooLong);
+                }
+                self.bytes_heap
+                    .insert(args.to_heap_id.clone(), Bytes::from(compressed));
+            }
+            Instruction::ComputeChecksum(args) => {
+                let msg = self
+                    .message_heap
+                    .get(&args.from_msg_heap_id)
+                    .ok_or(Error::ExecuteFailed)?;
+                let checksum = msg
+                    .checksum(
+                        args.algorithm,
+                        &args.from_first_field_id,
+                        &args.from_last_field_id,
+                        &args.checksum_field_id,
+                    )
+                    .ok_or(Error::ExecuteFailed)?;
+                self.number_heap.insert(args.to_heap_id.clone(), checksum);
+            }
             Instruction::ComputeLength(args) => {
                 let msg = self
                     .message_heap
@@ -148,6 +223,31 @@ impl Program {
                 // Store the message for use in later instructions.
                 self.message_heap.insert(args.to_heap_id.clone(), msg);
             }
+            Instruction::DecodeBytes(args) => {
+                let bytes = self
+                    .bytes_heap
+                    .get(&args.from_heap_id)
+                    .ok_or(Error::ExecuteFailed)?;
+                let decoded = args.encoding.decode(bytes).ok_or(Error::ExecuteFailed)?;
+                self.bytes_heap
+                    .insert(args.to_heap_id.clone(), Bytes::from(decoded));
+            }
+            Instruction::DecodeText(args) => {
+                let bytes = self
+                    .bytes_heap
+                    .get(&args.from_heap_id)
+                    .ok_or(Error::ExecuteFailed)?;
+                let val = args.text.decode(bytes).ok_or(Error::ExecuteFailed)?;
+                self.number_heap.insert(args.to_heap_id.clone(), val);
+            }
+            Instruction::DecodeVarint(args) => {
+                let bytes = self
+                    .bytes_heap
+                    .get(&args.from_heap_id)
+                    .ok_or(Error::ExecuteFailed)?;
+                let val = args.varint.decode(bytes).ok_or(Error::ExecuteFailed)?;
+                self.number_heap.insert(args.to_heap_id.clone(), val.into());
+            }
             Instruction::DecryptField(args) => {
                 match interpreter.cipher.as_mut() {
                     Some(cipher) => {
@@ -176,6 +276,43 @@ impl Program {
                     None => panic!("No cipher for decryption"),
                 }
             }
+            Instruction::Delay(args) => {
+                let millis = args.from_distribution.sample(&mut rand::thread_rng());
+                let netop = NetOpOut::Sleep(SleepArgs {
+                    duration: Duration::from_millis(millis as u64),
+                });
+                interpreter.next_netop_out = Some(netop);
+            }
+            Instruction::EncodeBytes(args) => {
+                let bytes = self
+                    .bytes_heap
+                    .get(&args.from_heap_id)
+                    .ok_or(Error::ExecuteFailed)?;
+                let encoded = args.encoding.encode(bytes);
+                self.bytes_heap
+                    .insert(args.to_heap_id.clone(), Bytes::from(encoded));
+            }
+            Instruction::EncodeText(args) => {
+                let val = *self
+                    .number_heap
+                    .get(&args.from_heap_id)
+                    .ok_or(Error::ExecuteFailed)?;
+                let bytes = args.text.encode(val).ok_or(Error::ExecuteFailed)?;
+                self.bytes_heap
+                    .insert(args.to_heap_id.clone(), Bytes::from(bytes));
+            }
+            Instruction::EncodeVarint(args) => {
+                let val = *self
+                    .number_heap
+                    .get(&args.from_heap_id)
+                    .ok_or(Error::ExecuteFailed)?;
+                let bytes = u64::try_from(val)
+                    .ok()
+                    .and_then(|v| args.varint.encode(v))
+                    .ok_or(Error::ExecuteFailed)?;
+                self.bytes_heap
+                    .insert(args.to_heap_id.clone(), Bytes::from(bytes));
+            }
             Instruction::EncryptField(args) => match interpreter.cipher.as_mut() {
                 Some(cipher) => {
                     let msg = self
@@ -200,8 +337,76 @@ impl Program {
                 }
                 None => panic!("No cipher for encryption"),
             },
-            Instruction::GenRandomBytes(_args) => {
-                unimplemented!()
+            Instruction::EvalCondition(args) => {
+                let num = *self
+                    .number_heap
+                    .get(&args.from_heap_id)
+                    .ok_or(Error::ExecuteFailed)?;
+                let len = match args.condition.holds(num) {
+                    true => args.present_len,
+                    false => 0,
+                };
+                self.number_heap
+                    .insert(args.to_heap_id.clone(), len as u128);
+            }
+            Instruction::GenPadding(args) => {
+                let target = *self
+                    .number_heap
+                    .get(&args.from_target_heap_id)
+                    .ok_or(Error::ExecuteFailed)? as usize;
+                let mut payload_len = 0;
+                for id in &args.from_heap_ids {
+                    payload_len += self.bytes_heap.get(id).ok_or(Error::ExecuteFailed)?.len();
+                }
+
+                let len = std::cmp::min(
+                    target.saturating_sub(args.overhead + payload_len),
+                    args.max_len,
+                );
+                let len = len - len % args.step;
+
+                // Round the message up to a whole number of blocks, or down if
+                // the padding cannot grow that far. The parser checks padding
+                // can be any size up to one byte short of a block.
+                let len = match args.align {
+                    Some(align) => {
+                        let len =
+                            len + (align - (args.overhead + payload_len + len) % align) % align;
+                        if len > args.max_len {
+                            len - align
+                        } else {
+                            len
+                        }
+                    }
+                    None => len,
+                };
+
+                let mut buf = BytesMut::zeroed(len);
+                rand::thread_rng().fill_bytes(&mut buf);
+                self.bytes_heap
+                    .insert(args.to_heap_id.clone(), buf.freeze());
+            }
+            Instruction::GenRandomBytes(args) => {
+                let mut rng = rand::thread_rng();
+                let mut buf = BytesMut::zeroed(rng.gen_range(args.from_len.clone()));
+                rng.fill_bytes(&mut buf);
+                self.bytes_heap
+                    .insert(args.to_heap_id.clone(), buf.freeze());
+            }
+            Instruction::GenVariable(args) => {
+                let bytes = match interpreter.variables.get(&args.variable_id) {
+                    Some(bytes) => bytes.clone(),
+                    None => {
+                        let mut buf = BytesMut::zeroed(args.len);
+                        rand::thread_rng().fill_bytes(&mut buf);
+                        let bytes = buf.freeze();
+                        interpreter
+                            .variables
+                            .insert(args.variable_id.clone(), bytes.clone());
+                        bytes
+                    }
+                };
+                self.bytes_heap.insert(args.to_heap_id.clone(), bytes);
             }
             Instruction::GetArrayBytes(args) => {
                 let msg = self
@@ -213,6 +418,18 @@ impl Program {
                     .map_err(|_| Error::ExecuteFailed)?;
                 self.bytes_heap.insert(args.to_heap_id.clone(), bytes);
             }
+            Instruction::GetArrayLength(args) => {
+                let msg = self
+                    .message_heap
+                    .get(&args.from_msg_heap_id)
+                    .ok_or(Error::ExecuteFailed)?;
+                let len = msg
+                    .get_field_bytes(&args.from_field_id)
+                    .map_err(|_| Error::ExecuteFailed)?
+                    .len();
+                self.number_heap
+                    .insert(args.to_heap_id.clone(), len as u128);
+            }
             Instruction::GetNumericValue(args) => {
                 let msg = self
                     .message_heap
@@ -223,6 +440,9 @@ impl Program {
                     .map_err(|_| Error::ExecuteFailed)?;
                 self.number_heap.insert(args.to_heap_id.clone(), num);
             }
+            Instruction::InitAdaptivePadding(args) => {
+                interpreter.adaptive_padding = Some(AdaptivePadding::new(args.spec.clone()));
+            }
             Instruction::InitFixedSharedKey(args) => {
                 let salt = "stupid stupid stupid";
                 let skey = kdf::derive_key_256(args.password.as_str(), salt);
@@ -233,9 +453,60 @@ impl Program {
                 };
                 interpreter.cipher = Some(Cipher::new(skey, kind));
             }
+            Instruction::LoadVariable(args) => {
+                let bytes = interpreter
+                    .variables
+                    .get(&args.from_variable_id)
+                    .ok_or(Error::ExecuteFailed)?;
+                self.bytes_heap
+                    .insert(args.to_heap_id.clone(), bytes.clone());
+            }
+            Instruction::NextSequenceNumber(args) => {
+                let num = interpreter.next_sequence_number(&args.counter_id, args.max);
+                self.number_heap.insert(args.to_heap_id.clone(), num);
+            }
             Instruction::ReadApp(args) => {
+                let len = match &args.from_len {
+                    ReadAppLength::UpToIdentifierMinus((id, sub)) => {
+                        let num = self.number_heap.get(id).ok_or(Error::ExecuteFailed)?;
+                        let val = (*num as usize).saturating_sub(*sub);
+                        Range {
+                            start: 1,
+                            end: std::cmp::max(val, 1) + 1,
+                        }
+                    }
+                    ReadAppLength::Range(r) => r.clone(),
+                };
+
+                // Only read as much as still fits once compressed and encoded.
+                // If that is less than we wanted at least, we take what fits.
+                let shrink = |len: Range<usize>, max: usize| len.start.min(max)..max + 1;
+                let len = match args.encoding {
+                    Some(encoding) => {
+                        let max = encoding.max_decoded_len(len.end - 1);
+                        shrink(len, max)
+                    }
+                    None => len,
+                };
+                let len = match args.compression {
+                    Some(compression) => {
+                        let max = compression.max_decompressed_len(len.end - 1);
+                        shrink(len, max)
+                    }
+                    None => len,
+                };
+
+                let timeout = match interpreter.adaptive_padding.as_mut() {
+                    Some(ap) => ap.next_timeout(&mut rand::thread_rng()),
+                    None => args.timeout.as_ref().map(|dist| {
+                        Duration::from_millis(dist.sample(&mut rand::thread_rng()) as u64)
+                    }),
+                };
+
                 let netop = NetOpOut::RecvApp(RecvArgs {
-                    len: args.from_len.clone(),
+                    len,
+                    timeout,
+                    delimiter: None,
                     addr: args.to_heap_id.clone(),
                 });
                 interpreter.next_netop_out = Some(netop);
@@ -259,14 +530,87 @@ impl Program {
                         }
                     }
                     ReadNetLength::Range(r) => r.clone(),
+                    ReadNetLength::Varint(varint) => {
+                        // Collect the pieces of the varint read so far. We only
+                        // move on to the next instruction once it is complete.
+                        let mut read = BytesMut::new();
+                        for id in [&args.to_heap_id, &VARINT_PART_HEAP_NAME.id()] {
+                            if let Some(bytes) = self.bytes_heap.remove(id) {
+                                read.put(bytes);
+                            }
+                        }
+
+                        let remaining = varint.remaining_len(&read).ok_or(Error::ExecuteFailed)?;
+                        self.bytes_heap
+                            .insert(args.to_heap_id.clone(), read.freeze());
+
+                        if remaining > 0 {
+                            let netop = NetOpIn::RecvNet(RecvArgs {
+                                len: remaining..remaining + 1,
+                                timeout: None,
+                                delimiter: None,
+                                addr: VARINT_PART_HEAP_NAME.id(),
+                            });
+                            interpreter.next_netop_in = Some(netop);
+                            return Ok(());
+                        }
+
+                        self.next_ins_index += 1;
+                        return Ok(());
+                    }
+                    ReadNetLength::Delimiter((delimiter, max_len)) => delimiter.len()..max_len + 1,
+                };
+
+                let delimiter = match &args.from_len {
+                    ReadNetLength::Delimiter((delimiter, _)) => Some(delimiter.clone()),
+                    _ => None,
                 };
 
                 let netop = NetOpIn::RecvNet(RecvArgs {
                     len,
+                    timeout: None,
+                    delimiter,
                     addr: args.to_heap_id.clone(),
                 });
                 interpreter.next_netop_in = Some(netop);
             }
+            Instruction::SampleSize(args) => {
+                let size = args
+                    .from_distribution
+                    .sample(&mut rand::thread_rng())
+                    .clamp(args.within.start, args.within.end - 1);
+                self.number_heap
+                    .insert(args.to_heap_id.clone(), size as u128);
+            }
+            Instruction::SampleValue(args) => {
+                let value = args.from_constraint.sample(&mut rand::thread_rng());
+                self.number_heap.insert(args.to_heap_id.clone(), value);
+            }
+            Instruction::SaveVariable(args) => {
+                let msg = self
+                    .message_heap
+                    .get(&args.from_msg_heap_id)
+                    .ok_or(Error::ExecuteFailed)?;
+                let bytes = msg
+                    .get_field_bytes(&args.from_field_id)
+                    .map_err(|_| Error::ExecuteFailed)?;
+                interpreter
+                    .variables
+                    .insert(args.to_variable_id.clone(), bytes);
+            }
+            Instruction::ScaleNumericValue(args) => {
+                let num = *self
+                    .number_heap
+                    .get(&args.from_heap_id)
+                    .ok_or(Error::ExecuteFailed)?;
+                let val = i128::try_from(num)
+                    .ok()
+                    .and_then(|n| n.checked_mul(args.scale as i128))
+                    .and_then(|n| n.checked_add(args.bias as i128))
+                    .and_then(|n| u128::try_from(n).ok())
+                    .ok_or(Error::ExecuteFailed)?;
+                self.number_heap.insert(args.to_heap_id.clone(), val);
+            }
             Instruction::SetArrayBytes(args) => {
                 let bytes = self
                     .bytes_heap
@@ -293,17 +637,142 @@ impl Program {
                     .map_err(|_| Error::ExecuteFailed)?;
                 self.message_heap.insert(args.to_msg_heap_id.clone(), msg);
             }
+            Instruction::StoreBytes(args) => {
+                self.bytes_heap
+                    .insert(args.to_heap_id.clone(), Bytes::from(args.bytes.clone()));
+            }
+            Instruction::SubtractNumericValue(args) => {
+                let val = *self
+                    .number_heap
+                    .get(&args.from_heap_id)
+                    .ok_or(Error::ExecuteFailed)?;
+                let minus = *self
+                    .number_heap
+                    .get(&args.minus_heap_id)
+                    .ok_or(Error::ExecuteFailed)?;
+                let diff = val.checked_sub(minus).ok_or(Error::ExecuteFailed)?;
+                self.number_heap.insert(args.to_heap_id.clone(), diff);
+            }
+            Instruction::SumLengths(args) => {
+                let mut sum = args.plus as i128;
+                for id in &args.from_heap_ids {
+                    sum += self.bytes_heap.get(id).ok_or(Error::ExecuteFailed)?.len() as i128;
+                }
+                if sum < 0 || sum % args.unit as i128 != 0 {
+                    return Err(Error::ExecuteFailed);
+                }
+                self.number_heap
+                    .insert(args.to_heap_id.clone(), (sum / args.unit as i128) as u128);
+            }
+            Instruction::TruncateBytes(args) => {
+                let len = *self
+                    .number_heap
+                    .get(&args.from_len_heap_id)
+                    .ok_or(Error::ExecuteFailed)?;
+                let mut bytes = self
+                    .bytes_heap
+                    .get(&args.from_heap_id)
+                    .ok_or(Error::ExecuteFailed)?
+                    .clone();
+                bytes.truncate(usize::try_from(len).unwrap_or(usize::MAX));
+                self.bytes_heap.insert(args.to_heap_id.clone(), bytes);
+            }
+            Instruction::VerifyChecksum(args) => {
+                let expected = self
+                    .bytes_heap
+                    .get(&args.checksum_heap_id)
+                    .ok_or(Error::ExecuteFailed)?;
+                let mut covered = vec![];
+                for id in &args.from_heap_ids {
+                    let bytes = self.bytes_heap.get(id).ok_or(Error::ExecuteFailed)?;
+                    if *id == args.checksum_heap_id {
+                        covered.resize(covered.len() + bytes.len(), 0);
+                    } else {
+                        covered.extend_from_slice(bytes);
+                    }
+                }
+
+                let expected = match args.endianness {
+                    Endianness::Big => expected.iter().fold(0, |v, &b| (v << 8) | b as u128),
+                    Endianness::Little => {
+                        expected.iter().rev().fold(0, |v, &b| (v << 8) | b as u128)
+                    }
+                };
+                if args.algorithm.compute(&covered) != expected {
+                    return Err(Error::ChecksumMismatch);
+                }
+            }
+            Instruction::VerifyFixedFields(args) => {
+                let msg = self
+                    .message_heap
+                    .get(&args.from_msg_heap_id)
+                    .ok_or(Error::ExecuteFailed)?;
+                if !msg.holds_values(&args.fixed_fields, args.constant_time) {
+                    return Err(Error::FixedFieldMismatch);
+                }
+            }
+            Instruction::VerifySequenceNumber(args) => {
+                let msg = self
+                    .message_heap
+                    .get(&args.from_msg_heap_id)
+                    .ok_or(Error::ExecuteFailed)?;
+                let num = msg
+                    .get_field_unsigned_numeric(&args.from_field_id)
+                    .map_err(|_| Error::ExecuteFailed)?;
+                if num != interpreter.next_sequence_number(&args.counter_id, args.max) {
+                    return Err(Error::UnexpectedSequenceNumber);
+                }
+            }
+            Instruction::VerifyValues(args) => {
+                let msg = self
+                    .message_heap
+                    .get(&args.from_msg_heap_id)
+                    .ok_or(Error::ExecuteFailed)?;
+                for (field_id, constraint) in &args.constraints {
+                    match msg.get_field_unsigned_numeric(field_id) {
+                        Ok(value) if constraint.allows(value) => {}
+                        Err(GetFieldError::NotDefined) => {}
+                        _ => return Err(Error::ValueNotAllowed),
+                    }
+                }
+                if !msg.holds_valid_primitives() {
+                    return Err(Error::ValueNotAllowed);
+                }
+            }
+            Instruction::VerifyVariable(args) => {
+                let msg = self
+                    .message_heap
+                    .get(&args.from_msg_heap_id)
+                    .ok_or(Error::ExecuteFailed)?;
+                let bytes = interpreter
+                    .variables
+                    .get(&args.variable_id)
+                    .ok_or(Error::ExecuteFailed)?;
+                if !msg.holds_values(&[(args.from_field_id.clone(), bytes.to_vec())], true) {
+                    return Err(Error::VariableMismatch);
+                }
+            }
             Instruction::WriteApp(args) => {
                 let msg = self
                     .message_heap
                     .remove(&args.from_msg_heap_id)
                     .ok_or(Error::ExecuteFailed)?;
-                let netop = NetOpIn::SendApp(SendArgs {
-                    bytes: msg
-                        .into_inner_field(&args.from_field_id)
-                        .ok_or(Error::ExecuteFailed)?,
-                });
-                interpreter.next_netop_in = Some(netop);
+                let bytes = msg
+                    .into_inner_field(&args.from_field_id)
+                    .ok_or(Error::ExecuteFailed)?;
+                let bytes = match args.compression {
+                    Some((compression, max_len)) => compression
+                        .decompress(&bytes, max_len)
+                        .map(Bytes::from)
+                        .ok_or(Error::DecompressionFailed)?,
+                    None => bytes,
+                };
+
+                // Messages without payload are chaff, so silently drop them.
+                if !bytes.is_empty() {
+                    let netop = NetOpIn::SendApp(SendArgs { bytes });
+                    interpreter.next_netop_in = Some(netop);
+                }
             }
             Instruction::WriteNet(args) => {
                 let msg = self
@@ -311,7 +780,7 @@ impl Program {
                     .remove(&args.from_msg_heap_id)
                     .ok_or(Error::ExecuteFailed)?;
                 let netop = NetOpOut::SendNet(SendArgs {
-                    bytes: msg.into_inner(),
+                    bytes: msg.into_wire(),
                 });
                 interpreter.next_netop_out = Some(netop);
             }
@@ -330,6 +799,12 @@ impl Program {
 pub struct Interpreter {
     spec: Box<dyn TaskProvider + Send + 'static>,
     cipher: Option<Cipher>,
+    adaptive_padding: Option<AdaptivePadding>,
+    // The next value of each sequence number counter, kept for the whole
+    // connection rather than a single task.
+    sequence_numbers: Heap<u128>,
+    // Field values saved for later tasks of the connection to load.
+    variables: Heap<Bytes>,
     next_netop_out: Option<NetOpOut>,
     next_netop_in: Option<NetOpIn>,
     current_prog_out: Option<Program>,
@@ -343,6 +818,9 @@ impl Interpreter {
         Self {
             spec,
             cipher: None,
+            adaptive_padding: None,
+            sequence_numbers: Heap::new(),
+            variables: Heap::new(),
             next_netop_out: None,
             next_netop_in: None,
             current_prog_out: None,
@@ -361,6 +839,14 @@ impl Interpreter {
         Ok(())
     }
 
+    /// Gets the current value of a sequence number counter and advances it.
+    fn next_sequence_number(&mut self, counter_id: &Identifier, max: u128) -> u128 {
+        let num = self.sequence_numbers.get(counter_id).copied().unwrap_or(0);
+        let next = if num == max { 0 } else { num + 1 };
+        self.sequence_numbers.insert(counter_id.clone(), next);
+        num
+    }
+
     /// Loads task from the task provider. Panics if we already have a current
     /// task in/out, we receive another one from the provider, and the ID of the
     /// new task does not match that of the existing task.
@@ -401,6 +887,11 @@ impl Interpreter {
             match self.current_prog_in.take() {
                 Some(mut program) => {
                     while program.has_next_instruction() {
+                        if program.waits_for_variable(self) {
+                            self.current_prog_in = Some(program);
+                            return Err(());
+                        }
+
                         if let Err(e) = program.execute_next_instruction(self) {
                             self.next_netop_in = Some(NetOpIn::Error(e.into()));
                         };
@@ -430,6 +921,11 @@ impl Interpreter {
             match self.current_prog_out.take() {
                 Some(mut program) => {
                     while program.has_next_instruction() {
+                        if program.waits_for_variable(self) {
+                            self.current_prog_out = Some(program);
+                            return Err(());
+                        }
+
                         if let Err(e) = program.execute_next_instruction(self) {
                             self.next_netop_out = Some(NetOpOut::Error(e.into()));
                         };
@@ -455,6 +951,10 @@ impl Interpreter {
     }
 
     pub fn store_out(&mut self, addr: Identifier, bytes: Bytes) {
+        // Empty app data means we are about to send a dummy record.
+        if let Some(ap) = self.adaptive_padding.as_mut() {
+            ap.on_record(bytes.is_empty());
+        }
         if let Some(t) = self.current_prog_out.as_mut() {
             t.store_bytes(addr, bytes);
         }
@@ -551,13 +1051,57 @@ impl SharedAsyncInterpreter {
 #[cfg(test)]
 mod tests {
     use crate::lang::{
-        parse::{proteus::ProteusParser, Parse},
+        compiler::{compile_task_graph, TaskGraphImpl},
+        parse::{implementation::parse_psf, proteus::ProteusParser, Parse},
         spec::test::basic::LengthPayloadSpec,
+        types::{ConstantRate, StaticallySized},
     };
     use bytes::{Buf, BufMut, BytesMut};
+    use rand::Rng;
 
     use super::*;
 
+    /// Builds an interpreter for the given PSF contents rather than a file.
+    fn interpreter_from_psf(psf: &str, role: Role) -> Interpreter {
+        let psf = parse_psf(psf).unwrap();
+        let graph = compile_task_graph(psf.sequence.iter());
+        let tgi = TaskGraphImpl::new(graph, role, psf);
+        tgi.check().unwrap();
+        let spec = ProteusSpec::new(tgi);
+        let mut int = Interpreter::new(Box::new(spec));
+        assert!(int.init().is_ok());
+        int
+    }
+
+    /// Has the interpreter send the given app data, and returns the message it
+    /// writes to the network.
+    fn send_message(int: &mut Interpreter, app: &'static [u8]) -> Bytes {
+        let args = match int.next_net_cmd_out().unwrap() {
+            NetOpOut::RecvApp(args) => args,
+            _ => panic!("Unexpected interpreter command"),
+        };
+        int.store_out(args.addr, Bytes::from_static(app));
+
+        loop {
+            match int.next_net_cmd_out().unwrap() {
+                NetOpOut::Sleep(_) => continue,
+                NetOpOut::SendNet(args) => return args.bytes,
+                _ => panic!("Unexpected interpreter command"),
+            }
+        }
+    }
+
+    /// Feeds a message to the interpreter as it asks to read it off the
+    /// network, and returns the first command that is not such a read.
+    fn recv_message(int: &mut Interpreter, mut wire: Bytes) -> NetOpIn {
+        loop {
+            match int.next_net_cmd_in().unwrap() {
+                NetOpIn::RecvNet(args) => int.store_in(args.addr, wire.split_to(args.len.start)),
+                op => return op,
+            }
+        }
+    }
+
     fn get_task_providers() -> Vec<Box<dyn TaskProvider + Send + 'static>> {
         vec![
             Box::new(LengthPayloadSpec::new(Role::Client)),
@@ -634,6 +1178,17 @@ mod tests {
         }
     }
 
+    #[test]
+    fn sequence_numbers_wrap() {
+        let mut int = Interpreter::new(Box::new(LengthPayloadSpec::new(Role::Client)));
+        let (a, b) = ("a".id(), "b".id());
+
+        let nums: Vec<u128> = (0..5).map(|_| int.next_sequence_number(&a, 2)).collect();
+        assert_eq!(nums, [0, 1, 2, 0, 1]);
+        assert_eq!(int.next_sequence_number(&b, 2), 0);
+        assert_eq!(int.next_sequence_number(&a, 2), 2);
+    }
+
     fn read_app_write_net_pipeline(int: &mut Interpreter) {
         let payload = read_app(int);
         write_net(int, payload);
@@ -739,4 +1294,447 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn shaped_message_sizes_and_delays() {
+        let tp = ProteusParser::parse("examples/psf/shaped.psf", Role::Client).unwrap();
+        let mut int = Interpreter::new(Box::new(tp));
+        assert!(int.init().is_ok());
+
+        for app_len in [1, 100, 1000, 10_000] {
+            let args = match int.next_net_cmd_out().unwrap() {
+                NetOpOut::RecvApp(args) => args,
+                _ => panic!("Unexpected interpreter command"),
+            };
+
+            let num = std::cmp::min(app_len, args.len.end - 1);
+            int.store_out(args.addr, Bytes::from(vec![0u8; num]));
+
+            match int.next_net_cmd_out().unwrap() {
+                NetOpOut::Sleep(args) => assert!(args.duration.as_millis() <= 5),
+                _ => panic!("Unexpected interpreter command"),
+            };
+
+            let args = match int.next_net_cmd_out().unwrap() {
+                NetOpOut::SendNet(args) => args,
+                _ => panic!("Unexpected interpreter command"),
+            };
+
+            assert!([60, 576, 1500].contains(&args.bytes.len()));
+        }
+    }
+
+    #[test]
+    fn constant_rate_overrides_sizes() {
+        // Formats without padding can't be sent at a constant rate, and
+        // records can't be smaller than the fixed fields of a message.
+        let mut tp = ProteusParser::parse("examples/psf/simple.psf", Role::Client).unwrap();
+        assert!(tp
+            .set_constant_rate(ConstantRate {
+                size: 1000,
+                interval: 10,
+            })
+            .is_err());
+        let mut tp = ProteusParser::parse("examples/psf/shaped.psf", Role::Client).unwrap();
+        assert!(tp
+            .set_constant_rate(ConstantRate {
+                size: 35,
+                interval: 10,
+            })
+            .is_err());
+        tp.set_constant_rate(ConstantRate {
+            size: 1000,
+            interval: 10,
+        })
+        .unwrap();
+        let mut int = Interpreter::new(Box::new(tp));
+        assert!(int.init().is_ok());
+
+        for app_len in [0, 100, 10_000] {
+            let args = match int.next_net_cmd_out().unwrap() {
+                NetOpOut::RecvApp(args) => args,
+                _ => panic!("Unexpected interpreter command"),
+            };
+
+            let num = std::cmp::min(app_len, args.len.end - 1);
+            int.store_out(args.addr, Bytes::from(vec![0u8; num]));
+
+            // The phase delay gives way to the schedule.
+            let bytes = match int.next_net_cmd_out().unwrap() {
+                NetOpOut::SendNet(args) => args.bytes,
+                _ => panic!("Unexpected interpreter command"),
+            };
+            assert_eq!(bytes.len(), 1000);
+        }
+    }
+
+    #[test]
+    fn adaptive_padding_sets_read_timeouts() {
+        // Without infinite bins, every record is followed by a timeout.
+        let psf = "@SEGMENT.FORMATS
+            DEFINE Packet
+              { NAME: length     ; TYPE: u16 },
+              { NAME: pad_length ; TYPE: u16 },
+              { NAME: payload    ; TYPE: [u8; length.size_of] },
+              { NAME: padding    ; TYPE: [u8; pad_length.size_of] };
+            @SEGMENT.SEMANTICS
+              { FORMAT: Packet; FIELD: length;  SEMANTIC: LENGTH };
+              { FORMAT: Packet; FIELD: payload; SEMANTIC: PAYLOAD };
+              { FORMAT: Packet; FIELD: padding; SEMANTIC: PADDING };
+            @SEGMENT.SEQUENCE
+              { ROLE: CLIENT; PHASE: DATA; FORMAT: Packet };
+            @SEGMENT.TRAFFIC
+              { FORMAT: Packet; SIZE: HISTOGRAM(60: 2, 576: 3, 1500: 5) };
+              { ROLE: CLIENT; BURST: HISTOGRAM(2: 5, 10: 3); GAP: HISTOGRAM(20: 3, 100: 3) };";
+        let mut int = interpreter_from_psf(psf, Role::Client);
+
+        // Nothing to pad before the app has sent anything.
+        let args = match int.next_net_cmd_out().unwrap() {
+            NetOpOut::RecvApp(args) => args,
+            _ => panic!("Unexpected interpreter command"),
+        };
+        assert_eq!(args.timeout, None);
+
+        for is_dummy in [false, true, true] {
+            let bytes = match is_dummy {
+                true => Bytes::new(),
+                false => Bytes::from_static(b"hello"),
+            };
+            int.store_out(args.addr.clone(), bytes);
+
+            match int.next_net_cmd_out().unwrap() {
+                NetOpOut::SendNet(_) => {}
+                _ => panic!("Unexpected interpreter command"),
+            };
+
+            let args = match int.next_net_cmd_out().unwrap() {
+                NetOpOut::RecvApp(args) => args,
+                _ => panic!("Unexpected interpreter command"),
+            };
+            let allowed = match is_dummy {
+                true => [20, 100],
+                false => [2, 10],
+            };
+            let timeout = args.timeout.unwrap();
+            assert!(allowed.contains(&(timeout.as_millis() as usize)));
+        }
+    }
+
+    #[test]
+    fn aligned_messages_fill_whole_blocks() {
+        // Small payloads want more padding than pad_length can describe, so the
+        // padding has to be rounded down to a block rather than up.
+        let psf = "@SEGMENT.FORMATS
+            DEFINE Packet
+              { NAME: length     ; TYPE: u16 },
+              { NAME: pad_length ; TYPE: u8 },
+              { NAME: payload    ; TYPE: [u8; length.size_of] },
+              { NAME: padding    ; TYPE: [u8; pad_length.size_of] };
+            @SEGMENT.SEMANTICS
+              { FORMAT: Packet; FIELD: length;  SEMANTIC: LENGTH };
+              { FORMAT: Packet; FIELD: payload; SEMANTIC: PAYLOAD };
+              { FORMAT: Packet; FIELD: padding; SEMANTIC: PADDING };
+            @SEGMENT.SEQUENCE
+              { ROLE: CLIENT; PHASE: DATA; FORMAT: Packet };
+            @SEGMENT.TRAFFIC
+              { FORMAT: Packet; SIZE: FIXED(1500) };
+              { FORMAT: Packet; ALIGN: 16 };";
+        let mut int = interpreter_from_psf(psf, Role::Client);
+
+        for _ in 0..100 {
+            let args = match int.next_net_cmd_out().unwrap() {
+                NetOpOut::RecvApp(args) => args,
+                _ => panic!("Unexpected interpreter command"),
+            };
+
+            let num = rand::thread_rng().gen_range(0..args.len.end);
+            int.store_out(args.addr, Bytes::from(vec![0u8; num]));
+
+            let mut bytes = match int.next_net_cmd_out().unwrap() {
+                NetOpOut::SendNet(args) => args.bytes,
+                _ => panic!("Unexpected interpreter command"),
+            };
+            assert_eq!(bytes.len() % 16, 0);
+
+            let len = bytes.get_u16() as usize;
+            let pad_len = bytes.get_u8() as usize;
+            assert_eq!(len, num + pad_len);
+        }
+    }
+
+    #[test]
+    fn aligned_messages_get_padding_added() {
+        // Without padding of its own, the format gets a padding length byte
+        // after the length field and padding after the payload.
+        let psf = "@SEGMENT.FORMATS
+            DEFINE Packet
+              { NAME: length  ; TYPE: u16 },
+              { NAME: payload ; TYPE: [u8; length.size_of] };
+            @SEGMENT.SEMANTICS
+              { FORMAT: Packet; FIELD: length;  SEMANTIC: LENGTH };
+              { FORMAT: Packet; FIELD: payload; SEMANTIC: PAYLOAD };
+            @SEGMENT.SEQUENCE
+              { ROLE: CLIENT; PHASE: DATA; FORMAT: Packet };
+            @SEGMENT.TRAFFIC
+              { FORMAT: Packet; ALIGN: 16 };";
+        let mut client = interpreter_from_psf(psf, Role::Client);
+        let mut server = interpreter_from_psf(psf, Role::Server);
+
+        for app in [
+            &b"a"[..],
+            b"fourteen bytes",
+            b"fifteen bytes!!",
+            b"sixteen bytes!!!",
+        ] {
+            let args = match client.next_net_cmd_out().unwrap() {
+                NetOpOut::RecvApp(args) => args,
+                _ => panic!("Unexpected interpreter command"),
+            };
+            client.store_out(args.addr, Bytes::from(app));
+
+            let msg = match client.next_net_cmd_out().unwrap() {
+                NetOpOut::SendNet(args) => args.bytes,
+                _ => panic!("Unexpected interpreter command"),
+            };
+            assert_eq!(msg.len() % 16, 0);
+            let pad_len = msg[2] as usize;
+            assert_eq!(msg.len(), 2 + app.len() + 1 + pad_len);
+
+            // The receiver drops the padding again.
+            match recv_message(&mut server, msg) {
+                NetOpIn::SendApp(args) => assert_eq!(args.bytes, app),
+                _ => panic!("Unexpected interpreter command"),
+            }
+        }
+    }
+
+    #[test]
+    fn sent_optional_fields_follow_their_conditions() {
+        let psf = std::fs::read_to_string("examples/psf/optional.psf").unwrap();
+        let mut client = interpreter_from_psf(&psf, Role::Client);
+        let mut server = interpreter_from_psf(&psf, Role::Server);
+
+        // The flags are 'A', so ext_id is sent after the length but ext_ts is
+        // not.
+        let msg = send_message(&mut client, b"hello");
+        assert_eq!(msg.len(), 1 + 2 + 4 + 5);
+        assert_eq!(msg[0], b'A');
+        assert_eq!(&msg[7..], b"hello");
+        match recv_message(&mut server, msg) {
+            NetOpIn::SendApp(args) => assert_eq!(args.bytes, &b"hello"[..]),
+            _ => panic!("Unexpected interpreter command"),
+        }
+
+        // The kind is zero, so the session follows the one-byte length.
+        let reply = send_message(&mut server, b"world");
+        assert_eq!(&reply[2..10], b"sessionX");
+        match recv_message(&mut client, reply) {
+            NetOpIn::SendApp(args) => assert_eq!(args.bytes, &b"world"[..]),
+            _ => panic!("Unexpected interpreter command"),
+        }
+    }
+
+    #[test]
+    fn sent_lists_hold_a_record() {
+        let psf = std::fs::read_to_string("examples/psf/record_list.psf").unwrap();
+        let formats = parse_psf(&psf).unwrap().formats;
+        let mut client = interpreter_from_psf(&psf, Role::Client);
+        let mut server = interpreter_from_psf(&psf, Role::Server);
+
+        let wire = send_message(&mut client, b"hello");
+
+        // One suite and one extension, as laid out after the prefix.
+        let mut bytes = wire.clone();
+        let _length = bytes.get_u16();
+        assert_eq!(bytes.get_u8(), 1);
+        let ext_length = bytes.get_u16() as usize;
+        let mut records = &bytes[..];
+        for (name, len) in [("CipherSuite", 2), ("Extension", ext_length)] {
+            let record = &formats[&name.id()].format.format;
+            let parsed = Message::parse(record, &mut records).unwrap();
+            assert_eq!(parsed.size_of(), len);
+        }
+        assert_eq!(records, b"hello");
+
+        match recv_message(&mut server, wire) {
+            NetOpIn::SendApp(args) => assert_eq!(args.bytes, Bytes::from_static(b"hello")),
+            _ => panic!("Unexpected interpreter command"),
+        }
+    }
+
+    #[test]
+    fn tampered_variable_is_rejected() {
+        let psf = std::fs::read_to_string("examples/psf/session.psf").unwrap();
+        let mut client = interpreter_from_psf(&psf, Role::Client);
+        let mut server = interpreter_from_psf(&psf, Role::Server);
+
+        let hello = send_message(&mut client, b"hello");
+        assert!(matches!(
+            recv_message(&mut server, hello),
+            NetOpIn::SendApp(_)
+        ));
+
+        // The reply echoes the session ID right after its length field.
+        let mut reply = BytesMut::from(&send_message(&mut server, b"world")[..]);
+        reply[2] ^= 0xff;
+        match recv_message(&mut client, reply.freeze()) {
+            NetOpIn::Error(e) => assert_eq!(e, Error::VariableMismatch.to_string()),
+            _ => panic!("Unexpected interpreter command"),
+        }
+    }
+
+    #[test]
+    fn replayed_or_skipped_record_is_rejected() {
+        let psf = std::fs::read_to_string("examples/psf/sequenced.psf").unwrap();
+        let mut client = interpreter_from_psf(&psf, Role::Client);
+        let first = send_message(&mut client, b"first");
+        let second = send_message(&mut client, b"second");
+
+        let expect_rejected = |op| match op {
+            NetOpIn::Error(e) => assert_eq!(e, Error::UnexpectedSequenceNumber.to_string()),
+            _ => panic!("Unexpected interpreter command"),
+        };
+
+        let mut server = interpreter_from_psf(&psf, Role::Server);
+        assert!(matches!(
+            recv_message(&mut server, first.clone()),
+            NetOpIn::SendApp(_)
+        ));
+        expect_rejected(recv_message(&mut server, first));
+
+        let mut server = interpreter_from_psf(&psf, Role::Server);
+        expect_rejected(recv_message(&mut server, second));
+    }
+
+    #[test]
+    fn wrong_magic_is_rejected() {
+        let psf = std::fs::read_to_string("examples/psf/nested.psf").unwrap();
+        let mut client = interpreter_from_psf(&psf, Role::Client);
+        let mut server = interpreter_from_psf(&psf, Role::Server);
+
+        // Records start with the magic "PR".
+        let mut msg = BytesMut::from(&send_message(&mut client, b"hello")[..]);
+        assert_eq!(&msg[..2], b"PR");
+        msg[1] = b'X';
+        match recv_message(&mut server, msg.freeze()) {
+            NetOpIn::Error(e) => assert_eq!(e, Error::FixedFieldMismatch.to_string()),
+            _ => panic!("Unexpected interpreter command"),
+        }
+    }
+
+    #[test]
+    fn corrupted_checksummed_message_is_rejected() {
+        let psf = std::fs::read_to_string("examples/psf/checksum.psf").unwrap();
+        let mut client = interpreter_from_psf(&psf, Role::Client);
+        let msg = send_message(&mut client, b"hello");
+
+        // A payload byte and a byte of each of the header and trailing checksums.
+        for index in [5, 3, msg.len() - 1] {
+            let mut server = interpreter_from_psf(&psf, Role::Server);
+            let mut corrupted = BytesMut::from(&msg[..]);
+            corrupted[index] ^= 0x01;
+            match recv_message(&mut server, corrupted.freeze()) {
+                NetOpIn::Error(e) => assert_eq!(e, Error::ChecksumMismatch.to_string()),
+                _ => panic!("Unexpected interpreter command"),
+            }
+        }
+    }
+
+    #[test]
+    fn disallowed_value_is_rejected() {
+        let psf = std::fs::read_to_string("examples/psf/constrained.psf").unwrap();
+        let mut client = interpreter_from_psf(&psf, Role::Client);
+        let msg = send_message(&mut client, b"hello");
+
+        // A content type that is not one of those allowed, and a version out
+        // of range.
+        for (index, value) in [(0, 0x18), (2, 0x04)] {
+            let mut server = interpreter_from_psf(&psf, Role::Server);
+            let mut changed = BytesMut::from(&msg[..]);
+            changed[index] = value;
+            match recv_message(&mut server, changed.freeze()) {
+                NetOpIn::Error(e) => assert_eq!(e, Error::ValueNotAllowed.to_string()),
+                _ => panic!("Unexpected interpreter command"),
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_compressed_payload_is_rejected() {
+        let psf = "@SEGMENT.FORMATS
+            DEFINE Packet
+              { NAME: length  ; TYPE: u16 },
+              { NAME: payload ; TYPE: [u8; length.size_of]; COMPRESS: deflate };
+            @SEGMENT.SEMANTICS
+              { FORMAT: Packet; FIELD: length;  SEMANTIC: LENGTH };
+              { FORMAT: Packet; FIELD: payload; SEMANTIC: PAYLOAD };
+            @SEGMENT.SEQUENCE
+              { ROLE: CLIENT; PHASE: DATA; FORMAT: Packet };";
+        let mut client = interpreter_from_psf(psf, Role::Client);
+        let mut server = interpreter_from_psf(psf, Role::Server);
+
+        // The first byte of a deflate stream gives its block type, and 3 is
+        // reserved.
+        let mut msg = BytesMut::from(&send_message(&mut client, b"hello hello hello")[..]);
+        msg[2] = 0xff;
+        match recv_message(&mut server, msg.freeze()) {
+            NetOpIn::Error(e) => assert_eq!(e, Error::DecompressionFailed.to_string()),
+            _ => panic!("Unexpected interpreter command"),
+        }
+    }
+
+    #[test]
+    fn invalid_bool_is_rejected() {
+        let psf = std::fs::read_to_string("examples/psf/typed.psf").unwrap();
+        let mut client = interpreter_from_psf(&psf, Role::Client);
+        let mut server = interpreter_from_psf(&psf, Role::Server);
+
+        // The flag follows the two bytes of the version.
+        let mut msg = BytesMut::from(&send_message(&mut client, b"hello")[..]);
+        msg[2] = 2;
+        match recv_message(&mut server, msg.freeze()) {
+            NetOpIn::Error(e) => assert_eq!(e, Error::ValueNotAllowed.to_string()),
+            _ => panic!("Unexpected interpreter command"),
+        }
+    }
+
+    #[test]
+    fn chaff_sent_when_idle_and_discarded() {
+        let psf = "examples/psf/shaped.psf";
+        let client = ProteusParser::parse(psf, Role::Client).unwrap();
+        let server = ProteusParser::parse(psf, Role::Server).unwrap();
+
+        let mut client = Interpreter::new(Box::new(client));
+        let mut server = Interpreter::new(Box::new(server));
+        assert!(client.init().is_ok());
+        assert!(server.init().is_ok());
+
+        // The app has nothing to say before the idle timeout expires.
+        let args = match client.next_net_cmd_out().unwrap() {
+            NetOpOut::RecvApp(args) => args,
+            _ => panic!("Unexpected interpreter command"),
+        };
+        assert!(args.timeout.unwrap().as_millis() <= 250);
+        client.store_out(args.addr, Bytes::new());
+
+        let mut wire = loop {
+            match client.next_net_cmd_out().unwrap() {
+                NetOpOut::Sleep(_) => continue,
+                NetOpOut::SendNet(args) => break args.bytes,
+                _ => panic!("Unexpected interpreter command"),
+            }
+        };
+        assert!([60, 576, 1500].contains(&wire.len()));
+
+        // The server consumes the whole chaff message without writing to the
+        // app, then waits for the next message.
+        while let NetOpIn::RecvNet(args) = server.next_net_cmd_in().unwrap() {
+            if wire.is_empty() {
+                assert_eq!(args.len.start, 2);
+                return;
+            }
+            server.store_in(args.addr, wire.split_to(args.len.start));
+        }
+        panic!("Unexpected interpreter command");
+    }
 }
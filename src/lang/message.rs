@@ -13,7 +13,73 @@ pub enum SetFieldError {
 #[derive(Debug)]
 pub enum GetFieldError {
     NotDefined,
+    DowncastError,
     TypeError,
+    InvalidValue,
+}
+
+/// Writes one primitive value in the given byte order, advancing `buf`.
+fn put_primitive(buf: &mut &mut [u8], value: PrimitiveValue, endianness: Endianness) {
+    match (value, endianness) {
+        (PrimitiveValue::U8(x), _) => buf.put_u8(x),
+        (PrimitiveValue::I8(x), _) => buf.put_i8(x),
+        (PrimitiveValue::Bool(x), _) => buf.put_u8(x.into()),
+        (PrimitiveValue::U16(x), Endianness::Big) => buf.put_u16(x),
+        (PrimitiveValue::U16(x), Endianness::Little) => buf.put_u16_le(x),
+        (PrimitiveValue::U32(x), Endianness::Big) => buf.put_u32(x),
+        (PrimitiveValue::U32(x), Endianness::Little) => buf.put_u32_le(x),
+        (PrimitiveValue::U64(x), Endianness::Big) => buf.put_u64(x),
+        (PrimitiveValue::U64(x), Endianness::Little) => buf.put_u64_le(x),
+        (PrimitiveValue::I16(x), Endianness::Big) => buf.put_i16(x),
+        (PrimitiveValue::I16(x), Endianness::Little) => buf.put_i16_le(x),
+        (PrimitiveValue::I32(x), Endianness::Big) => buf.put_i32(x),
+        (PrimitiveValue::I32(x), Endianness::Little) => buf.put_i32_le(x),
+        (PrimitiveValue::I64(x), Endianness::Big) => buf.put_i64(x),
+        (PrimitiveValue::I64(x), Endianness::Little) => buf.put_i64_le(x),
+        (PrimitiveValue::Char(x), Endianness::Big) => buf.put_u32(x.into()),
+        (PrimitiveValue::Char(x), Endianness::Little) => buf.put_u32_le(x.into()),
+    }
+}
+
+/// Reads one primitive value of type `ptype` in the given byte order,
+/// advancing `buf`.
+fn get_primitive(
+    buf: &mut &[u8],
+    ptype: PrimitiveType,
+    endianness: Endianness,
+) -> Result<PrimitiveValue, GetFieldError> {
+    let little = endianness == Endianness::Little;
+    let value = match ptype {
+        PrimitiveType::Numeric(ntype) => match (ntype, little) {
+            (NumericType::U8, _) => PrimitiveValue::U8(buf.get_u8()),
+            (NumericType::I8, _) => PrimitiveValue::I8(buf.get_i8()),
+            (NumericType::U16, false) => PrimitiveValue::U16(buf.get_u16()),
+            (NumericType::U16, true) => PrimitiveValue::U16(buf.get_u16_le()),
+            (NumericType::U32, false) => PrimitiveValue::U32(buf.get_u32()),
+            (NumericType::U32, true) => PrimitiveValue::U32(buf.get_u32_le()),
+            (NumericType::U64, false) => PrimitiveValue::U64(buf.get_u64()),
+            (NumericType::U64, true) => PrimitiveValue::U64(buf.get_u64_le()),
+            (NumericType::I16, false) => PrimitiveValue::I16(buf.get_i16()),
+            (NumericType::I16, true) => PrimitiveValue::I16(buf.get_i16_le()),
+            (NumericType::I32, false) => PrimitiveValue::I32(buf.get_i32()),
+            (NumericType::I32, true) => PrimitiveValue::I32(buf.get_i32_le()),
+            (NumericType::I64, false) => PrimitiveValue::I64(buf.get_i64()),
+            (NumericType::I64, true) => PrimitiveValue::I64(buf.get_i64_le()),
+        },
+        PrimitiveType::Bool => match buf.get_u8() {
+            0 => PrimitiveValue::Bool(false),
+            1 => PrimitiveValue::Bool(true),
+            _ => return Err(GetFieldError::InvalidValue),
+        },
+        PrimitiveType::Char => {
+            let code = match little {
+                false => buf.get_u32(),
+                true => buf.get_u32_le(),
+            };
+            PrimitiveValue::Char(char::from_u32(code).ok_or(GetFieldError::InvalidValue)?)
+        }
+    };
+    Ok(value)
 }
 
 #[derive(Debug)]
@@ -34,7 +100,11 @@ impl Message {
             .unwrap();
 
         for (field_name, field_value) in &format.fixed_fields {
-            if let Some(mut bytes) = msg.try_get_field_slice_mut(field_name) {
+            // Skips optional fields that are absent.
+            if let Some(mut bytes) = msg
+                .try_get_field_slice_mut(field_name)
+                .filter(|bytes| !bytes.is_empty())
+            {
                 bytes.put_slice(&field_value[..]);
             }
         }
@@ -42,13 +112,58 @@ impl Message {
         Some(msg)
     }
 
+    /// Whether every bool and char in the message is a value its type allows.
+    pub fn holds_valid_primitives(&self) -> bool {
+        self.format
+            .format
+            .fields
+            .iter()
+            .all(|field| match field.dtype {
+                Array::Primitive(PrimitiveArray(PrimitiveType::Bool | PrimitiveType::Char, _)) => {
+                    self.get_field_primitive_array(&field.name).is_ok()
+                }
+                _ => true,
+            })
+    }
+
+    /// Whether each of the given fields holds its value followed by zeros, as
+    /// `new` writes it. Fields missing from the format and absent optional
+    /// fields are skipped. With `constant_time`, the values are compared in
+    /// constant time, so a peer learns nothing from how long the check takes.
+    pub fn holds_values(&self, values: &[(Identifier, Vec<u8>)], constant_time: bool) -> bool {
+        let mut diff = 0u8;
+        let mut valid = true;
+
+        for (field_name, value) in values {
+            let (dtype, bytes) = match self.format.format.try_get_field_by_name(field_name) {
+                Some(field) => (field.dtype, self.try_get_field_slice(field_name).unwrap()),
+                None => continue,
+            };
+            if matches!(dtype, Array::Conditional(_)) && bytes.is_empty() {
+                continue;
+            }
+
+            let fits = value.len() <= bytes.len();
+            let mut pairs = bytes.iter().zip(value.iter().chain(std::iter::repeat(&0)));
+            if constant_time {
+                valid &= fits;
+                diff |= pairs.fold(0, |acc, (a, b)| acc | (a ^ b));
+            } else if !fits || !pairs.all(|(a, b)| a == b) {
+                return false;
+            }
+        }
+
+        valid && diff == 0
+    }
+
     fn get_field_slice(&self, offset: usize, size: usize) -> &[u8] {
-        assert!(offset < self.data.len() && offset + size <= self.data.len());
+        // Empty fields (e.g., zero-length padding) may sit at the very end.
+        assert!(offset + size <= self.data.len());
         &self.data.as_ref()[offset..offset + size]
     }
 
     fn get_field_slice_mut(&mut self, offset: usize, size: usize) -> &mut [u8] {
-        assert!(offset < self.data.len() && offset + size <= self.data.len());
+        assert!(offset + size <= self.data.len());
         &mut self.data.as_mut()[offset..offset + size]
     }
 
@@ -66,103 +181,281 @@ impl Message {
             .map(|(_, offset, size)| self.get_field_slice_mut(offset, size))
     }
 
-    pub fn set_field_unsigned_numeric(
-        &mut self,
+    /// Gets the element type and count, offset, and size of the given field.
+    /// Dynamic arrays are arrays of bytes.
+    fn try_get_primitive_field(
+        &self,
         field_name: &Identifier,
-        value: u128,
-    ) -> Result<(), SetFieldError> {
-        if let Some((dtype, offset, size)) = self
+    ) -> Option<(PrimitiveArray, usize, usize)> {
+        self.format
+            .format
+            .try_get_field_type_offset_and_size(field_name)
+            .map(|(dtype, offset, size)| match dtype {
+                Array::Primitive(pa) => (pa, offset, size),
+                // Absent fields hold nothing at all.
+                Array::Conditional(Conditional {
+                    array,
+                    present: Some(true),
+                    ..
+                }) if matches!(*array, Array::Primitive(_)) => {
+                    (PrimitiveArray::try_from(*array).unwrap(), offset, size)
+                }
+                Array::Conditional(_) => {
+                    (PrimitiveArray(NumericType::U8.into(), size), offset, size)
+                }
+                Array::Dynamic(_)
+                | Array::Varint(_)
+                | Array::Bits(_)
+                | Array::List(_)
+                | Array::Text(_) => (PrimitiveArray(NumericType::U8.into(), size), offset, size),
+            })
+    }
+
+    /// Gets the encoding, offset, and size of the given field if it holds a
+    /// varint.
+    fn try_get_varint_field(&self, field_name: &Identifier) -> Option<(VarintType, usize, usize)> {
+        match self
             .format
             .format
             .try_get_field_type_offset_and_size(field_name)
         {
-            let mut field_bytes = self.get_field_slice_mut(offset, size);
+            Some((Array::Varint(Varint(vtype, _)), offset, size)) => Some((vtype, offset, size)),
+            _ => None,
+        }
+    }
 
-            match dtype {
-                Array::Primitive(PrimitiveArray(x, n)) => {
-                    if n > 1 {
-                        return Err(SetFieldError::TypeError);
-                    }
-                    match x {
-                        PrimitiveType::Char => Err(SetFieldError::TypeError),
-                        PrimitiveType::Bool => Err(SetFieldError::TypeError),
-                        PrimitiveType::Numeric(y) => match y {
-                            NumericType::U8 => {
-                                if let Ok(z) = u8::try_from(value) {
-                                    field_bytes.put_u8(z);
-                                    Ok(())
-                                } else {
-                                    Err(SetFieldError::DowncastError)
-                                }
-                            }
-                            NumericType::U16 => {
-                                if let Ok(z) = u16::try_from(value) {
-                                    field_bytes.put_u16(z);
-                                    Ok(())
-                                } else {
-                                    Err(SetFieldError::DowncastError)
-                                }
-                            }
-                            NumericType::U32 => {
-                                if let Ok(z) = u32::try_from(value) {
-                                    field_bytes.put_u32(z);
-                                    Ok(())
-                                } else {
-                                    Err(SetFieldError::DowncastError)
-                                }
-                            }
-                            NumericType::U64 => {
-                                if let Ok(z) = u64::try_from(value) {
-                                    field_bytes.put_u64(z);
-                                    Ok(())
-                                } else {
-                                    Err(SetFieldError::DowncastError)
-                                }
-                            }
-                            _ => Err(SetFieldError::TypeError),
-                        },
-                    }
-                }
-                _ => panic!(),
+    /// Gets the encoding, offset, and size of the given field if it holds a
+    /// textual number.
+    fn try_get_text_field(&self, field_name: &Identifier) -> Option<(TextNumber, usize, usize)> {
+        match self
+            .format
+            .format
+            .try_get_field_type_offset_and_size(field_name)
+        {
+            Some((Array::Text(text), offset, size)) => Some((text, offset, size)),
+            _ => None,
+        }
+    }
+
+    /// Gets the offset and size of the bytes holding the given bit field, and
+    /// the bit offset and width of the field within those bytes.
+    fn try_get_bit_field(&self, field_name: &Identifier) -> Option<(usize, usize, usize, usize)> {
+        let (group_name, bit_offset, width) = self.format.format.try_get_bit_field(field_name)?;
+        let (_, offset, size) = self
+            .format
+            .format
+            .try_get_field_type_offset_and_size(&group_name)?;
+        Some((offset, size, bit_offset, width))
+    }
+
+    fn get_field_endianness(&self, field_name: &Identifier) -> Endianness {
+        self.format
+            .format
+            .try_get_field_by_name(field_name)
+            .map(|field| field.endianness)
+            .unwrap_or_default()
+    }
+
+    /// Gets all elements of the given field.
+    pub fn get_field_primitive_array(
+        &self,
+        field_name: &Identifier,
+    ) -> Result<Vec<PrimitiveValue>, GetFieldError> {
+        let (PrimitiveArray(ptype, n), offset, size) = self
+            .try_get_primitive_field(field_name)
+            .ok_or(GetFieldError::NotDefined)?;
+        let endianness = self.get_field_endianness(field_name);
+        let mut field_bytes = self.get_field_slice(offset, size);
+
+        (0..n)
+            .map(|_| get_primitive(&mut field_bytes, ptype, endianness))
+            .collect()
+    }
+
+    /// Sets all elements of the given field. The values must match the type
+    /// and number of elements of the field.
+    pub fn set_field_primitive_array(
+        &mut self,
+        field_name: &Identifier,
+        values: &[PrimitiveValue],
+    ) -> Result<(), SetFieldError> {
+        let (PrimitiveArray(ptype, n), offset, size) = self
+            .try_get_primitive_field(field_name)
+            .ok_or(SetFieldError::NotDefined)?;
+
+        if values.len() != n || values.iter().any(|v| v.primitive_type() != ptype) {
+            return Err(SetFieldError::TypeError);
+        }
+
+        let endianness = self.get_field_endianness(field_name);
+        let mut field_bytes = self.get_field_slice_mut(offset, size);
+        for value in values {
+            put_primitive(&mut field_bytes, *value, endianness);
+        }
+
+        Ok(())
+    }
+
+    /// Gets the value of a field holding a single element.
+    pub fn get_field_primitive(
+        &self,
+        field_name: &Identifier,
+    ) -> Result<PrimitiveValue, GetFieldError> {
+        match self.get_field_primitive_array(field_name)?[..] {
+            [value] => Ok(value),
+            _ => Err(GetFieldError::TypeError),
+        }
+    }
+
+    /// Sets the value of a field holding a single element.
+    pub fn set_field_primitive(
+        &mut self,
+        field_name: &Identifier,
+        value: PrimitiveValue,
+    ) -> Result<(), SetFieldError> {
+        self.set_field_primitive_array(field_name, &[value])
+    }
+
+    /// Gets the value of a bit field.
+    pub fn get_field_bits(&self, field_name: &Identifier) -> Result<u64, GetFieldError> {
+        let (offset, size, bit_offset, width) = self
+            .try_get_bit_field(field_name)
+            .ok_or(GetFieldError::NotDefined)?;
+        let bytes = self.get_field_slice(offset, size);
+
+        Ok((bit_offset..bit_offset + width).fold(0, |acc, i| {
+            (acc << 1) | u64::from((bytes[i / 8] >> (7 - i % 8)) & 1)
+        }))
+    }
+
+    /// Sets the value of a bit field, which must fit in its width.
+    pub fn set_field_bits(
+        &mut self,
+        field_name: &Identifier,
+        value: u64,
+    ) -> Result<(), SetFieldError> {
+        let (offset, size, bit_offset, width) = self
+            .try_get_bit_field(field_name)
+            .ok_or(SetFieldError::NotDefined)?;
+
+        if width < 64 && value >> width != 0 {
+            return Err(SetFieldError::DowncastError);
+        }
+
+        let bytes = self.get_field_slice_mut(offset, size);
+
+        for (n, i) in (bit_offset..bit_offset + width).enumerate() {
+            let mask = 0x80 >> (i % 8);
+            if (value >> (width - 1 - n)) & 1 == 1 {
+                bytes[i / 8] |= mask;
+            } else {
+                bytes[i / 8] &= !mask;
             }
-        } else {
-            Err(SetFieldError::NotDefined)
         }
+
+        Ok(())
+    }
+
+    /// Bit fields, varints, and textual numbers only hold unsigned values.
+    fn is_unsigned_only(&self, field_name: &Identifier) -> bool {
+        self.try_get_varint_field(field_name).is_some()
+            || self.try_get_bit_field(field_name).is_some()
+            || self.try_get_text_field(field_name).is_some()
+    }
+
+    pub fn set_field_signed_numeric(
+        &mut self,
+        field_name: &Identifier,
+        value: i128,
+    ) -> Result<(), SetFieldError> {
+        if self.is_unsigned_only(field_name) {
+            let value = u128::try_from(value).map_err(|_| SetFieldError::DowncastError)?;
+            return self.set_field_unsigned_numeric(field_name, value);
+        }
+
+        let ntype = match self.try_get_primitive_field(field_name) {
+            Some((PrimitiveArray(PrimitiveType::Numeric(ntype), 1), _, _)) => ntype,
+            Some(_) => return Err(SetFieldError::TypeError),
+            None => return Err(SetFieldError::NotDefined),
+        };
+        let value =
+            PrimitiveValue::from_integer(ntype, value).map_err(|_| SetFieldError::DowncastError)?;
+        self.set_field_primitive(field_name, value)
+    }
+
+    pub fn get_field_signed_numeric(&self, field_name: &Identifier) -> Result<i128, GetFieldError> {
+        if self.is_unsigned_only(field_name) {
+            let value = self.get_field_unsigned_numeric(field_name)?;
+            return i128::try_from(value).map_err(|_| GetFieldError::DowncastError);
+        }
+
+        self.get_field_primitive(field_name)?
+            .as_integer()
+            .ok_or(GetFieldError::TypeError)
+    }
+
+    pub fn set_field_unsigned_numeric(
+        &mut self,
+        field_name: &Identifier,
+        value: u128,
+    ) -> Result<(), SetFieldError> {
+        // Varints must be set to a value that fits the concrete field size.
+        if let Some((vtype, offset, size)) = self.try_get_varint_field(field_name) {
+            let bytes = u64::try_from(value)
+                .ok()
+                .and_then(|v| vtype.encode(v))
+                .ok_or(SetFieldError::DowncastError)?;
+            if bytes.len() != size {
+                return Err(SetFieldError::DowncastError);
+            }
+            self.get_field_slice_mut(offset, size)
+                .copy_from_slice(&bytes);
+            return Ok(());
+        }
+
+        // So must textual numbers ending at a delimiter.
+        if let Some((text, offset, size)) = self.try_get_text_field(field_name) {
+            let bytes = text.encode(value).ok_or(SetFieldError::DowncastError)?;
+            if bytes.len() != size {
+                return Err(SetFieldError::DowncastError);
+            }
+            self.get_field_slice_mut(offset, size)
+                .copy_from_slice(&bytes);
+            return Ok(());
+        }
+
+        if self.try_get_bit_field(field_name).is_some() {
+            let value = u64::try_from(value).map_err(|_| SetFieldError::DowncastError)?;
+            return self.set_field_bits(field_name, value);
+        }
+
+        let value = i128::try_from(value).map_err(|_| SetFieldError::DowncastError)?;
+        self.set_field_signed_numeric(field_name, value)
     }
 
     pub fn get_field_unsigned_numeric(
         &self,
         field_name: &Identifier,
     ) -> Result<u128, GetFieldError> {
-        if let Some((dtype, offset, size)) = self
-            .format
-            .format
-            .try_get_field_type_offset_and_size(field_name)
-        {
-            let mut field_bytes = self.get_field_slice(offset, size);
+        if let Some((vtype, offset, size)) = self.try_get_varint_field(field_name) {
+            return vtype
+                .decode(self.get_field_slice(offset, size))
+                .map(|v| v.into())
+                .ok_or(GetFieldError::InvalidValue);
+        }
 
-            match dtype {
-                Array::Primitive(PrimitiveArray(x, n)) => {
-                    if n > 1 {
-                        return Err(GetFieldError::TypeError);
-                    }
-                    match x {
-                        PrimitiveType::Char => Err(GetFieldError::TypeError),
-                        PrimitiveType::Bool => Err(GetFieldError::TypeError),
-                        PrimitiveType::Numeric(y) => match y {
-                            NumericType::U8 => Ok(field_bytes.get_u8() as u128),
-                            NumericType::U16 => Ok(field_bytes.get_u16() as u128),
-                            NumericType::U32 => Ok(field_bytes.get_u32() as u128),
-                            NumericType::U64 => Ok(field_bytes.get_u64() as u128),
-                            _ => Err(GetFieldError::TypeError),
-                        },
-                    }
-                }
-                _ => panic!(),
-            }
-        } else {
-            Err(GetFieldError::NotDefined)
+        if let Some((text, offset, size)) = self.try_get_text_field(field_name) {
+            return text
+                .decode(self.get_field_slice(offset, size))
+                .ok_or(GetFieldError::InvalidValue);
+        }
+
+        if self.try_get_bit_field(field_name).is_some() {
+            return self.get_field_bits(field_name).map(|v| v.into());
         }
+
+        let value = self.get_field_signed_numeric(field_name)?;
+        u128::try_from(value).map_err(|_| GetFieldError::DowncastError)
     }
 
     pub fn set_length_field(&mut self) {
@@ -179,13 +472,14 @@ impl Message {
             .expect("payload length too large for length field");
     }
 
-    /// Computes the sum of the length of all fields after the given field.
+    /// Computes the sum of the length on the wire of all fields after the
+    /// given field.
     pub fn len_suffix(&self, field_name: &Identifier) -> usize {
         let mut nbytes: usize = 0;
         let mut do_sum = false;
         for field in &self.format.format.fields {
             if do_sum {
-                nbytes += field.maybe_size_of().unwrap();
+                nbytes += field.maybe_wire_size_of().unwrap();
             } else if field.name.eq(field_name) {
                 do_sum = true;
             }
@@ -193,6 +487,30 @@ impl Message {
         nbytes
     }
 
+    /// Computes the checksum of the fields from `first` to `last`, inclusive,
+    /// as if the field `checksum_field` were zero.
+    pub fn checksum(
+        &self,
+        algorithm: ChecksumAlgorithm,
+        first: &Identifier,
+        last: &Identifier,
+        checksum_field: &Identifier,
+    ) -> Option<u128> {
+        let format = &self.format.format;
+        let (_, start, _) = format.try_get_field_type_offset_and_size(first)?;
+        let (_, offset, size) = format.try_get_field_type_offset_and_size(last)?;
+        let mut covered = self.get_field_slice(start, offset + size - start).to_vec();
+
+        let (_, offset, size) = format.try_get_field_type_offset_and_size(checksum_field)?;
+        for i in offset..offset + size {
+            if let Some(byte) = i.checked_sub(start).and_then(|i| covered.get_mut(i)) {
+                *byte = 0;
+            }
+        }
+
+        Some(algorithm.compute(&covered))
+    }
+
     pub fn get_field_bytes(&self, field_name: &Identifier) -> Result<Bytes, GetFieldError> {
         match self.try_get_field_slice(field_name) {
             Some(slice) => {
@@ -222,10 +540,222 @@ impl Message {
         }
     }
 
+    /// Parses a message of the given format from the front of `buf`, sizing
+    /// each field from the fields before it, and advances `buf` past it.
+    pub fn parse(format: &Format, buf: &mut &[u8]) -> Result<Message, GetFieldError> {
+        // What we have so far, to look up the length fields in. Each field is
+        // appended once it is sized, so this ends up as the whole message.
+        let mut msg = Message {
+            format: ConcreteFormat {
+                format: Format {
+                    name: format.name.clone(),
+                    fields: vec![],
+                },
+                fixed_fields: vec![],
+            },
+            data: BytesMut::new(),
+        };
+        let mut offset = 0;
+
+        for field in format.fields.iter() {
+            let rest = &buf[offset..];
+
+            let size = match (field.maybe_size_of(), &field.dtype) {
+                (Some(size), _) => size,
+                (None, Array::Varint(Varint(vtype, _))) => {
+                    let mut len = 0;
+                    loop {
+                        match vtype.remaining_len(&rest[..len]) {
+                            Some(0) => break len,
+                            Some(n) if len + n <= rest.len() => len += n,
+                            _ => return Err(GetFieldError::InvalidValue),
+                        }
+                    }
+                }
+                (None, Array::Dynamic(DynamicArray(UnaryOp::Until(delimiter))))
+                | (
+                    None,
+                    Array::Text(TextNumber {
+                        end: TextEnd::Until(delimiter),
+                        ..
+                    }),
+                ) => {
+                    rest.windows(delimiter.len())
+                        .position(|w| w == delimiter)
+                        .ok_or(GetFieldError::InvalidValue)?
+                        + delimiter.len()
+                }
+                (None, Array::Dynamic(array)) => {
+                    // Unwrap OK: all other dynamic arrays have a length field.
+                    let value =
+                        msg.get_field_unsigned_numeric(&array.try_get_length_field().unwrap())?;
+                    match array.try_get_length_expr() {
+                        Some(expr) => expr.array_len(value),
+                        None => usize::try_from(value).ok(),
+                    }
+                    .ok_or(GetFieldError::InvalidValue)?
+                }
+                (None, Array::Conditional(conditional)) => {
+                    let value = msg.get_field_unsigned_numeric(&conditional.condition.field)?;
+                    match conditional.condition.holds(value) {
+                        true => conditional
+                            .array
+                            .maybe_size_of()
+                            .ok_or(GetFieldError::TypeError)?,
+                        false => 0,
+                    }
+                }
+                (None, Array::List(list)) => {
+                    let value = msg.get_field_unsigned_numeric(list.length_field())?;
+                    match list.length {
+                        ListLength::Count(_) => {
+                            let mut records = rest;
+                            for _ in 0..Self::record_count(value, rest.len())? {
+                                Message::parse(&list.record, &mut records)?;
+                            }
+                            rest.len() - records.len()
+                        }
+                        ListLength::Bytes(_) => {
+                            usize::try_from(value).map_err(|_| GetFieldError::InvalidValue)?
+                        }
+                    }
+                }
+                _ => unreachable!(),
+            };
+
+            if size > rest.len() {
+                return Err(GetFieldError::InvalidValue);
+            }
+
+            let sized = AbstractFormat::from(Format {
+                name: format.name.clone(),
+                fields: vec![field.clone()],
+            })
+            .concretize(&vec![(field.name.clone(), size)]);
+            msg.format.format.fields.extend(sized.format.fields);
+            msg.data.put_slice(&rest[..size]);
+            offset += size;
+        }
+
+        buf.advance(offset);
+
+        Ok(msg)
+    }
+
+    /// Checks a record count read off the wire against the bytes left to hold
+    /// the records, so a bogus count cannot keep us parsing empty records.
+    fn record_count(value: u128, remaining: usize) -> Result<usize, GetFieldError> {
+        usize::try_from(value)
+            .ok()
+            .filter(|&count| count <= remaining)
+            .ok_or(GetFieldError::InvalidValue)
+    }
+
+    /// Gets the records held by a list field.
+    pub fn get_field_records(
+        &self,
+        field_name: &Identifier,
+    ) -> Result<Vec<Message>, GetFieldError> {
+        let (list, offset, size) = match self
+            .format
+            .format
+            .try_get_field_type_offset_and_size(field_name)
+        {
+            Some((Array::List(list), offset, size)) => (list, offset, size),
+            Some(_) => return Err(GetFieldError::TypeError),
+            None => return Err(GetFieldError::NotDefined),
+        };
+
+        let mut bytes = self.get_field_slice(offset, size);
+        let mut records = vec![];
+
+        match list.length {
+            ListLength::Count(ref id) => {
+                let value = self.get_field_unsigned_numeric(id)?;
+                for _ in 0..Self::record_count(value, bytes.len())? {
+                    records.push(Message::parse(&list.record, &mut bytes)?);
+                }
+                if !bytes.is_empty() {
+                    return Err(GetFieldError::InvalidValue);
+                }
+            }
+            ListLength::Bytes(_) => {
+                while !bytes.is_empty() {
+                    records.push(Message::parse(&list.record, &mut bytes)?);
+                }
+            }
+        }
+
+        Ok(records)
+    }
+
+    /// Lays out the given records in a list field and sets its length field.
+    /// The list must have been sized to hold exactly these records.
+    pub fn set_field_records(
+        &mut self,
+        field_name: &Identifier,
+        records: &[Message],
+    ) -> Result<(), SetFieldError> {
+        let list = match self
+            .format
+            .format
+            .try_get_field_by_name(field_name)
+            .map(|field| field.dtype)
+        {
+            Some(Array::List(list)) => list,
+            Some(_) => return Err(SetFieldError::TypeError),
+            None => return Err(SetFieldError::NotDefined),
+        };
+
+        if records
+            .iter()
+            .any(|r| r.format.format.name != list.record.name)
+        {
+            return Err(SetFieldError::TypeError);
+        }
+
+        let mut bytes = BytesMut::new();
+        for record in records {
+            bytes.put_slice(&record.data);
+        }
+
+        self.set_field_bytes(field_name, &bytes.freeze())?;
+
+        let value = match list.length {
+            ListLength::Count(_) => records.len(),
+            ListLength::Bytes(_) => list.size.unwrap_or_default(),
+        };
+        self.set_field_unsigned_numeric(list.length_field(), value as u128)
+    }
+
     pub fn into_inner(self) -> Bytes {
         self.data.freeze()
     }
 
+    /// Gets the message as it is sent, with each field in its encoding.
+    pub fn into_wire(self) -> Bytes {
+        let fields = &self.format.format.fields;
+        if fields.iter().all(|field| field.encoding.is_none()) {
+            return self.into_inner();
+        }
+
+        let mut buf =
+            BytesMut::with_capacity(fields.iter().filter_map(|f| f.maybe_wire_size_of()).sum());
+        let mut offset = 0;
+
+        for field in fields {
+            let size = field.maybe_size_of().unwrap();
+            let bytes = self.get_field_slice(offset, size);
+            match field.encoding {
+                Some(encoding) => buf.put_slice(&encoding.encode(bytes)),
+                None => buf.put_slice(bytes),
+            }
+            offset += size;
+        }
+
+        buf.freeze()
+    }
+
     pub fn into_inner_field(mut self, field_name: &Identifier) -> Option<Bytes> {
         self.format
             .format
@@ -234,6 +764,12 @@ impl Message {
     }
 }
 
+impl StaticallySized for Message {
+    fn size_of(&self) -> usize {
+        self.data.len()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -256,6 +792,160 @@ mod tests {
         println!("{:?}", message.into_inner());
     }
 
+    #[test]
+    fn test_message_typed_fields() {
+        let format: ConcreteFormat = Format {
+            name: "Typed".id(),
+            fields: vec![
+                Field {
+                    name: "version".id(),
+                    dtype: PrimitiveArray(NumericType::I16.into(), 1).into(),
+                    endianness: Endianness::Big,
+                    compression: None,
+                    encoding: None,
+                },
+                Field {
+                    name: "flag".id(),
+                    dtype: PrimitiveArray(PrimitiveType::Bool, 1).into(),
+                    endianness: Endianness::Big,
+                    compression: None,
+                    encoding: None,
+                },
+                Field {
+                    name: "tag".id(),
+                    dtype: PrimitiveArray(PrimitiveType::Char, 1).into(),
+                    endianness: Endianness::Big,
+                    compression: None,
+                    encoding: None,
+                },
+                Field {
+                    name: "nonce".id(),
+                    dtype: PrimitiveArray(NumericType::U32.into(), 4).into(),
+                    endianness: Endianness::Big,
+                    compression: None,
+                    encoding: None,
+                },
+                Field {
+                    name: "length".id(),
+                    dtype: PrimitiveArray(NumericType::U32.into(), 1).into(),
+                    endianness: Endianness::Little,
+                    compression: None,
+                    encoding: None,
+                },
+            ],
+        }
+        .try_into()
+        .unwrap();
+
+        let mut message = Message::new(format).unwrap();
+
+        message
+            .set_field_signed_numeric(&"version".id(), -2)
+            .unwrap();
+        message
+            .set_field_primitive(&"flag".id(), PrimitiveValue::Bool(true))
+            .unwrap();
+        message
+            .set_field_primitive(&"tag".id(), PrimitiveValue::Char('p'))
+            .unwrap();
+        let nonce = [1, 2, 3, u32::MAX].map(PrimitiveValue::U32);
+        message
+            .set_field_primitive_array(&"nonce".id(), &nonce)
+            .unwrap();
+
+        assert_eq!(
+            message.get_field_signed_numeric(&"version".id()).unwrap(),
+            -2
+        );
+        assert!(message.get_field_unsigned_numeric(&"version".id()).is_err());
+        assert_eq!(
+            message.get_field_primitive(&"flag".id()).unwrap(),
+            PrimitiveValue::Bool(true)
+        );
+        assert_eq!(
+            message.get_field_primitive(&"tag".id()).unwrap(),
+            PrimitiveValue::Char('p')
+        );
+        assert_eq!(
+            message.get_field_primitive_array(&"nonce".id()).unwrap(),
+            nonce
+        );
+
+        // Values must match the field's type and number of elements.
+        assert!(message
+            .set_field_primitive(&"flag".id(), PrimitiveValue::U8(1))
+            .is_err());
+        assert!(message
+            .set_field_primitive_array(&"nonce".id(), &nonce[..3])
+            .is_err());
+        assert!(message
+            .set_field_signed_numeric(&"version".id(), i16::MAX as i128 + 1)
+            .is_err());
+
+        message
+            .set_field_unsigned_numeric(&"length".id(), 0x0102)
+            .unwrap();
+        assert_eq!(
+            message.get_field_unsigned_numeric(&"length".id()).unwrap(),
+            0x0102
+        );
+
+        let bytes = message.into_inner();
+        assert_eq!(&bytes[..7], &[0xff, 0xfe, 1, 0, 0, 0, b'p']);
+        assert_eq!(&bytes[bytes.len() - 4..], &[2, 1, 0, 0]);
+    }
+
+    #[test]
+    fn test_message_bit_fields() {
+        let format: ConcreteFormat = Format {
+            name: "Header".id(),
+            fields: [
+                Field {
+                    name: "flags|kind|fin".id(),
+                    dtype: BitGroup(
+                        [("flags".id(), 3), ("kind".id(), 12), ("fin".id(), 1)].to_vec(),
+                    )
+                    .into(),
+                    endianness: Endianness::Big,
+                    compression: None,
+                    encoding: None,
+                },
+                Field {
+                    name: "id".id(),
+                    dtype: PrimitiveArray(NumericType::U8.into(), 1).into(),
+                    endianness: Endianness::Big,
+                    compression: None,
+                    encoding: None,
+                },
+            ]
+            .to_vec(),
+        }
+        .try_into()
+        .unwrap();
+
+        let mut message = Message::new(format).unwrap();
+
+        message.set_field_bits(&"flags".id(), 0b101).unwrap();
+        message
+            .set_field_unsigned_numeric(&"kind".id(), 0xabc)
+            .unwrap();
+        message.set_field_bits(&"fin".id(), 1).unwrap();
+        message.set_field_unsigned_numeric(&"id".id(), 7).unwrap();
+
+        assert!(message.set_field_bits(&"flags".id(), 0b1000).is_err());
+        assert!(message.set_field_signed_numeric(&"kind".id(), -1).is_err());
+
+        assert_eq!(message.get_field_bits(&"flags".id()).unwrap(), 0b101);
+        assert_eq!(
+            message.get_field_unsigned_numeric(&"kind".id()).unwrap(),
+            0xabc
+        );
+        assert_eq!(message.get_field_signed_numeric(&"fin".id()).unwrap(), 1);
+
+        // 101 | 1010_1011_1100 | 1, then the byte after the group.
+        assert_eq!(message.into_inner(), Bytes::from_static(&[0xb5, 0x79, 7]));
+    }
+
     #[test]
     fn test_message_set_length() {
         let format: ConcreteFormat = Format {
@@ -264,10 +954,16 @@ mod tests {
                 Field {
                     name: "length".parse().unwrap(),
                     dtype: PrimitiveArray(NumericType::U16.into(), 1).into(),
+                    endianness: Endianness::Big,
+                    compression: None,
+                    encoding: None,
                 },
                 Field {
                     name: "payload".parse().unwrap(),
                     dtype: PrimitiveArray(NumericType::U8.into(), 40).into(),
+                    endianness: Endianness::Big,
+                    compression: None,
+                    encoding: None,
                 },
             ],
         }
@@ -285,4 +981,344 @@ mod tests {
                 .expect("")
         );
     }
+
+    fn make_field(name: &str, dtype: Array) -> Field {
+        Field {
+            name: name.id(),
+            dtype,
+            endianness: Endianness::Big,
+            compression: None,
+            encoding: None,
+        }
+    }
+
+    #[test]
+    fn test_message_records() {
+        let u8_field = || Array::from(PrimitiveArray(NumericType::U8.into(), 1));
+        let u16_field = || Array::from(PrimitiveArray(NumericType::U16.into(), 1));
+
+        let ext = Format {
+            name: "Extension".id(),
+            fields: [
+                make_field("kind", u16_field()),
+                make_field("value_length", u8_field()),
+                make_field(
+                    "value",
+                    DynamicArray(UnaryOp::SizeOf("value_length".id())).into(),
+                ),
+            ]
+            .to_vec(),
+        };
+        let hello = Format {
+            name: "Hello".id(),
+            fields: [
+                make_field("ext_length", u16_field()),
+                make_field(
+                    "extensions",
+                    RecordList {
+                        record: ext.clone(),
+                        length: ListLength::Bytes("ext_length".id()),
+                        size: None,
+                    }
+                    .into(),
+                ),
+                make_field("trailer", u8_field()),
+            ]
+            .to_vec(),
+        };
+
+        let records: Vec<Message> = [(1, &b"ab"[..]), (2, &b""[..]), (3, &b"xyz"[..])]
+            .iter()
+            .map(|(kind, value)| {
+                let format = AbstractFormat::from(ext.clone())
+                    .concretize(&[("value".id(), value.len())].to_vec());
+                let mut record = Message::new(format).unwrap();
+                record
+                    .set_field_unsigned_numeric(&"kind".id(), *kind)
+                    .unwrap();
+                record
+                    .set_field_unsigned_numeric(&"value_length".id(), value.len() as u128)
+                    .unwrap();
+                record
+                    .set_field_bytes(&"value".id(), &Bytes::copy_from_slice(value))
+                    .unwrap();
+                record
+            })
+            .collect();
+        let size = records.iter().map(|r| r.size_of()).sum();
+        assert_eq!(size, 5 + 3 + 6);
+
+        let format =
+            AbstractFormat::from(hello.clone()).concretize(&[("extensions".id(), size)].to_vec());
+        let mut message = Message::new(format).unwrap();
+        message
+            .set_field_records(&"extensions".id(), &records)
+            .unwrap();
+        message
+            .set_field_unsigned_numeric(&"trailer".id(), 0xff)
+            .unwrap();
+        assert!(message
+            .set_field_records(&"extensions".id(), &records[..1])
+            .is_err());
+
+        assert_eq!(
+            message
+                .get_field_unsigned_numeric(&"ext_length".id())
+                .unwrap(),
+            size as u128
+        );
+
+        // Parse the message back from its bytes, with a byte to spare.
+        let mut bytes = message.into_inner().to_vec();
+        bytes.push(0);
+        let mut buf = &bytes[..];
+        let parsed = Message::parse(&hello, &mut buf).unwrap();
+        assert_eq!(buf, [0]);
+        assert_eq!(
+            parsed.get_field_unsigned_numeric(&"trailer".id()).unwrap(),
+            0xff
+        );
+
+        let parsed_records = parsed.get_field_records(&"extensions".id()).unwrap();
+        assert_eq!(parsed_records.len(), 3);
+        assert_eq!(
+            parsed_records[2].get_field_bytes(&"value".id()).unwrap(),
+            Bytes::from_static(b"xyz")
+        );
+        assert_eq!(
+            parsed_records[1]
+                .get_field_unsigned_numeric(&"kind".id())
+                .unwrap(),
+            2
+        );
+
+        // A truncated message does not parse.
+        let mut buf = &bytes[..10];
+        assert!(Message::parse(&hello, &mut buf).is_err());
+    }
+
+    #[test]
+    fn test_message_counted_records() {
+        let suite = Format {
+            name: "Suite".id(),
+            fields: [make_field(
+                "id",
+                PrimitiveArray(NumericType::U16.into(), 1).into(),
+            )]
+            .to_vec(),
+        };
+        let hello = Format {
+            name: "Hello".id(),
+            fields: [
+                make_field("count", Varint(VarintType::Leb128, None).into()),
+                make_field(
+                    "suites",
+                    RecordList {
+                        record: suite.clone(),
+                        length: ListLength::Count("count".id()),
+                        size: None,
+                    }
+                    .into(),
+                ),
+            ]
+            .to_vec(),
+        };
+
+        let bytes = [2, 0x13, 0x01, 0x13, 0x02];
+        let mut buf = &bytes[..];
+        let parsed = Message::parse(&hello, &mut buf).unwrap();
+        assert!(buf.is_empty());
+
+        let ids: Vec<u128> = parsed
+            .get_field_records(&"suites".id())
+            .unwrap()
+            .iter()
+            .map(|r| r.get_field_unsigned_numeric(&"id".id()).unwrap())
+            .collect();
+        assert_eq!(ids, [0x1301, 0x1302]);
+
+        // A count can't claim more records than there are bytes left, even
+        // when the records take up no space.
+        let empty = Format {
+            name: "Empty".id(),
+            fields: vec![],
+        };
+        let hello = Format {
+            name: "Hello".id(),
+            fields: [
+                make_field("count", PrimitiveArray(NumericType::U64.into(), 1).into()),
+                make_field(
+                    "empties",
+                    RecordList {
+                        record: empty,
+                        length: ListLength::Count("count".id()),
+                        size: None,
+                    }
+                    .into(),
+                ),
+            ]
+            .to_vec(),
+        };
+        let bytes = u64::MAX.to_be_bytes();
+        let mut buf = &bytes[..];
+        assert!(Message::parse(&hello, &mut buf).is_err());
+    }
+
+    #[test]
+    fn test_message_holds_values() {
+        let format = Format {
+            name: "Magic".id(),
+            fields: [
+                make_field("magic", PrimitiveArray(NumericType::U8.into(), 4).into()),
+                make_field("line", PrimitiveArray(NumericType::U8.into(), 4).into()),
+            ]
+            .to_vec(),
+        };
+        let values = [
+            ("magic".id(), b"PK".to_vec()),
+            ("line".id(), b"ok\r\n".to_vec()),
+        ];
+        let parse = |bytes: &[u8]| {
+            let mut buf = bytes;
+            Message::parse(&format, &mut buf).unwrap()
+        };
+
+        for constant_time in [false, true] {
+            let holds = |bytes: &[u8], values: &[(Identifier, Vec<u8>)]| {
+                parse(bytes).holds_values(values, constant_time)
+            };
+            assert!(holds(b"PK\0\0ok\r\n", &values));
+            assert!(holds(b"PK\0\0ok\r\n", &[("other".id(), b"x".to_vec())]));
+            assert!(!holds(b"PK\0\x01ok\r\n", &values));
+            assert!(!holds(b"PX\0\0ok\r\n", &values));
+            assert!(!holds(
+                b"PK\0\0ok\r\n",
+                &[("magic".id(), b"PKZIP".to_vec())]
+            ));
+        }
+    }
+
+    #[test]
+    fn test_message_optional_fields() {
+        let optional = |op| -> Array {
+            Conditional {
+                array: Box::new(PrimitiveArray(NumericType::U16.into(), 1).into()),
+                condition: Condition {
+                    field: "flags".id(),
+                    op,
+                },
+                present: None,
+            }
+            .into()
+        };
+        let format = Format {
+            name: "Opt".id(),
+            fields: [
+                make_field("flags", PrimitiveArray(NumericType::U8.into(), 1).into()),
+                make_field("a", optional(ConditionOp::And(1))),
+                make_field("b", optional(ConditionOp::And(2))),
+                make_field("c", optional(ConditionOp::Equals(5))),
+            ]
+            .to_vec(),
+        };
+
+        // Only a and c are present.
+        let bytes = [5, 0x12, 0x34, 0xab, 0xcd];
+        let mut buf = &bytes[..];
+        let msg = Message::parse(&format, &mut buf).unwrap();
+        assert!(buf.is_empty());
+
+        assert_eq!(msg.get_field_unsigned_numeric(&"a".id()).unwrap(), 0x1234);
+        assert!(msg.get_field_unsigned_numeric(&"b".id()).is_err());
+        assert!(msg.get_field_bytes(&"b".id()).unwrap().is_empty());
+        assert_eq!(msg.get_field_unsigned_numeric(&"c".id()).unwrap(), 0xabcd);
+    }
+
+    #[test]
+    fn test_message_delimited_fields() {
+        let format = Format {
+            name: "Request".id(),
+            fields: [
+                make_field(
+                    "line",
+                    DynamicArray(UnaryOp::Until(b"\r\n".to_vec())).into(),
+                ),
+                make_field("end", DynamicArray(UnaryOp::Until(b"\r\n".to_vec())).into()),
+                make_field("flag", PrimitiveArray(NumericType::U8.into(), 1).into()),
+            ]
+            .to_vec(),
+        };
+
+        let bytes = b"GET / HTTP/1.1\r\n\r\n\x01";
+        let mut buf = &bytes[..];
+        let msg = Message::parse(&format, &mut buf).unwrap();
+        assert!(buf.is_empty());
+        assert_eq!(
+            msg.get_field_bytes(&"line".id()).unwrap(),
+            Bytes::from_static(b"GET / HTTP/1.1\r\n")
+        );
+        assert_eq!(
+            msg.get_field_bytes(&"end".id()).unwrap(),
+            Bytes::from_static(b"\r\n")
+        );
+
+        // The delimiter has not arrived yet.
+        let mut buf = &bytes[..15];
+        assert!(Message::parse(&format, &mut buf).is_err());
+    }
+
+    #[test]
+    fn test_message_text_fields() {
+        let format = Format {
+            name: "Request".id(),
+            fields: [
+                make_field(
+                    "id",
+                    TextNumber {
+                        radix: TextRadix::Hex,
+                        end: TextEnd::Width(4),
+                        size: None,
+                    }
+                    .into(),
+                ),
+                make_field(
+                    "length",
+                    TextNumber {
+                        radix: TextRadix::Decimal,
+                        end: TextEnd::Until(b"\r\n".to_vec()),
+                        size: None,
+                    }
+                    .into(),
+                ),
+                make_field(
+                    "payload",
+                    DynamicArray(UnaryOp::SizeOf("length".id())).into(),
+                ),
+            ]
+            .to_vec(),
+        };
+
+        let bytes = b"00ff12\r\nhello world!";
+        let mut buf = &bytes[..];
+        let mut msg = Message::parse(&format, &mut buf).unwrap();
+        assert!(buf.is_empty());
+        assert_eq!(msg.get_field_unsigned_numeric(&"id".id()).unwrap(), 0xff);
+        assert_eq!(msg.get_field_signed_numeric(&"length".id()).unwrap(), 12);
+
+        msg.set_field_unsigned_numeric(&"id".id(), 0xabc).unwrap();
+        msg.set_field_unsigned_numeric(&"length".id(), 34).unwrap();
+        assert_eq!(
+            msg.get_field_bytes(&"id".id()).unwrap(),
+            Bytes::from_static(b"0abc")
+        );
+        assert_eq!(
+            msg.get_field_bytes(&"length".id()).unwrap(),
+            Bytes::from_static(b"34\r\n")
+        );
+
+        // Values must fit the width, or the digits we have room for.
+        assert!(msg.set_field_unsigned_numeric(&"id".id(), 0x10000).is_err());
+        assert!(msg.set_field_unsigned_numeric(&"length".id(), 5).is_err());
+        assert!(msg.set_field_signed_numeric(&"length".id(), -1).is_err());
+    }
 }
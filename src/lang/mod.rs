@@ -1,3 +1,4 @@
+pub mod adaptive;
 pub mod common;
 pub mod compiler;
 pub mod interpreter;
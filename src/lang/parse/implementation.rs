@@ -9,7 +9,7 @@ use pest_derive::Parser;
 use std::collections::hash_map::HashMap;
 use std::fmt::Debug;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 
 #[derive(Parser)]
 #[grammar = "lang/parse/proteus_lite.pest"]
@@ -35,21 +35,92 @@ fn parse_numeric_type(p: &RulePair) -> Result<NumericType> {
     parse_simple(p)
 }
 
+fn parse_endianness(p: &RulePair) -> Result<Endianness> {
+    assert!(p.as_rule() == Rule::endianness);
+    parse_simple(p)
+}
+
 fn parse_primitive_type(p: &RulePair) -> Result<PrimitiveType> {
     assert!(p.as_rule() == Rule::primitive_type);
+
+    // Numeric types may carry an endianness suffix, which belongs to the field.
+    match p.clone().into_inner().next() {
+        Some(nt) => Ok(parse_numeric_type(&nt)?.into()),
+        None => parse_simple(p),
+    }
+}
+
+fn parse_varint_type(p: &RulePair) -> Result<VarintType> {
+    assert!(p.as_rule() == Rule::varint_type);
     parse_simple(p)
 }
 
+fn parse_bits_type(p: &RulePair) -> Result<usize> {
+    assert!(p.as_rule() == Rule::bits_type);
+    // Unwraps OK: ITR
+    parse_positive_numeric_literal(&p.clone().into_inner().next().unwrap())
+}
+
+fn parse_text_type(p: &RulePair) -> Result<TextNumber> {
+    assert!(p.as_rule() == Rule::text_type);
+
+    // Unwraps OK: ITR
+    let mut p = p.clone().into_inner();
+    let radix = parse_simple(&p.next().unwrap())?;
+
+    let end = p.next().unwrap();
+    let end = match end.as_rule() {
+        Rule::text_width => TextEnd::Width(parse_positive_numeric_literal(
+            &end.into_inner().next().unwrap(),
+        )?),
+        Rule::delimiter => TextEnd::Until(parse_delimiter(&end)?),
+        _ => unimplemented!(),
+    };
+
+    Ok(TextNumber {
+        radix,
+        end,
+        size: None,
+    })
+}
+
 fn parse_positive_numeric_literal(p: &RulePair) -> Result<usize> {
     assert!(p.as_rule() == Rule::positive_numeric_literal);
     Ok(p.as_str().parse::<usize>()?)
 }
 
+fn parse_hex_literal(p: &RulePair) -> Result<u128> {
+    assert!(p.as_rule() == Rule::hex_literal);
+    Ok(u128::from_str_radix(&p.as_str()[2..], 16)?)
+}
+
+/// Parses a hex literal as a string of bytes, two digits to a byte.
+fn parse_hex_bytes(p: &RulePair) -> Result<Vec<u8>> {
+    assert!(p.as_rule() == Rule::hex_literal);
+    let digits = &p.as_str()[2..];
+    if !digits.len().is_multiple_of(2) {
+        return Err(anyhow!(
+            "hex byte literal {} has an odd number of digits",
+            p.as_str()
+        ));
+    }
+
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| Ok(u8::from_str_radix(&digits[i..i + 2], 16)?))
+        .collect()
+}
+
 fn parse_identifier(p: &RulePair) -> Result<Identifier> {
     assert!(p.as_rule() == Rule::identifier);
     parse_simple(p)
 }
 
+fn parse_field_path(p: &RulePair) -> Result<Identifier> {
+    assert!(p.as_rule() == Rule::field_path);
+    parse_simple(p)
+}
+
 fn parse_primitive_array(p: &RulePair) -> Result<PrimitiveArray> {
     assert!(p.as_rule() == Rule::primitive_array);
 
@@ -69,20 +140,126 @@ fn parse_primitive_array(p: &RulePair) -> Result<PrimitiveArray> {
 fn parse_sizeof_op(p: &RulePair) -> Result<UnaryOp> {
     assert!(p.as_rule() == Rule::size_of_op);
     // Unwraps OK: ITR
-    Ok(UnaryOp::SizeOf(parse_identifier(
+    Ok(UnaryOp::SizeOf(parse_field_path(
         &p.clone().into_inner().next().unwrap(),
     )?))
 }
 
+fn parse_length_expr(p: &RulePair) -> Result<UnaryOp> {
+    assert!(p.as_rule() == Rule::length_expr);
+
+    // Unwraps OK: ITR
+    let mut p = p.clone().into_inner();
+    let field = parse_field_path(&p.next().unwrap())?;
+
+    let mut expr = LengthExpr {
+        field,
+        scale: 1,
+        bias: 0,
+    };
+
+    for e in p {
+        let mut inner = e.clone().into_inner();
+        match e.as_rule() {
+            Rule::length_scale => {
+                expr.scale = parse_positive_numeric_literal(&inner.next().unwrap())?;
+            }
+            Rule::length_bias => {
+                let sign = inner.next().unwrap();
+                let bias: isize =
+                    parse_positive_numeric_literal(&inner.next().unwrap())?.try_into()?;
+                expr.bias = match sign.as_str() {
+                    "-" => -bias,
+                    _ => bias,
+                };
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    Ok(UnaryOp::Expr(expr))
+}
+
+/// Gets the bytes of a string literal, with its escape sequences resolved.
+fn parse_escaped_string(p: &RulePair) -> Result<Vec<u8>> {
+    assert!(p.as_rule() == Rule::string_literal);
+
+    // Unwraps OK: ITR
+    let inner = p.clone().into_inner().next().unwrap().as_str();
+
+    let mut s = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            s.push(c);
+            continue;
+        }
+        // Unwraps OK: the grammar only allows complete escape sequences.
+        match chars.next().unwrap() {
+            'b' => s.push('\u{8}'),
+            'f' => s.push('\u{c}'),
+            'n' => s.push('\n'),
+            'r' => s.push('\r'),
+            't' => s.push('\t'),
+            'u' => {
+                let code: String = chars.by_ref().take(4).collect();
+                s.push(
+                    char::from_u32(u32::from_str_radix(&code, 16)?)
+                        .ok_or_else(|| anyhow!("invalid escape \\u{}", code))?,
+                );
+            }
+            c => s.push(c),
+        }
+    }
+
+    Ok(s.into_bytes())
+}
+
+fn parse_delimiter(p: &RulePair) -> Result<Vec<u8>> {
+    assert!(p.as_rule() == Rule::delimiter);
+    // Unwraps OK: ITR
+    parse_escaped_string(&p.clone().into_inner().next().unwrap())
+}
+
 fn parse_dynamic_array(p: &RulePair) -> Result<DynamicArray> {
     assert!(p.as_rule() == Rule::dynamic_array);
 
     // Unwraps OK: ITR
     let mut p = p.clone().into_inner();
-    let soo = p.next().unwrap();
-    let soo = parse_sizeof_op(&soo)?;
+    let op = p.next().unwrap();
+    let op = match op.as_rule() {
+        Rule::size_of_op => parse_sizeof_op(&op)?,
+        Rule::delimiter => UnaryOp::Until(parse_delimiter(&op)?),
+        Rule::length_expr => parse_length_expr(&op)?,
+        _ => unimplemented!(),
+    };
+
+    Ok(DynamicArray(op))
+}
+
+/// The records are left empty until the format they name is looked up.
+fn parse_record_list(p: &RulePair) -> Result<RecordList> {
+    assert!(p.as_rule() == Rule::record_list);
+
+    // Unwraps OK: ITR
+    let mut p = p.clone().into_inner();
+    let record = parse_identifier(&p.next().unwrap())?;
 
-    Ok(DynamicArray(soo))
+    let len = p.next().unwrap();
+    let length = match len.as_rule() {
+        Rule::size_of_op => ListLength::Bytes(parse_field_path(&len.into_inner().next().unwrap())?),
+        Rule::field_path => ListLength::Count(parse_field_path(&len)?),
+        _ => unimplemented!(),
+    };
+
+    Ok(RecordList {
+        record: Format {
+            name: record,
+            fields: vec![],
+        },
+        length,
+        size: None,
+    })
 }
 
 fn parse_array(p: &RulePair) -> Result<Array> {
@@ -94,6 +271,7 @@ fn parse_array(p: &RulePair) -> Result<Array> {
     match p.as_rule() {
         Rule::primitive_array => Ok(parse_primitive_array(&p)?.into()),
         Rule::dynamic_array => Ok(parse_dynamic_array(&p)?.into()),
+        Rule::record_list => Ok(parse_record_list(&p)?.into()),
         _ => unimplemented!(),
     }
 }
@@ -116,10 +294,61 @@ fn parse_type_value(p: &RulePair) -> Result<Array> {
             1,
         ))),
         Rule::array => Ok(parse_array(&p)?),
+        Rule::varint_type => Ok(Varint(parse_varint_type(&p)?, None).into()),
+        Rule::text_type => Ok(parse_text_type(&p)?.into()),
+        // The member is named by the enclosing field.
+        Rule::bits_type => Ok(BitGroup(vec![(Identifier::default(), parse_bits_type(&p)?)]).into()),
         _ => panic!(),
     }
 }
 
+fn parse_unsigned_value(p: &RulePair) -> Result<u128> {
+    assert!(p.as_rule() == Rule::unsigned_value);
+
+    // Unwraps OK: ITR
+    let p = p.clone().into_inner().next().unwrap();
+
+    match p.as_rule() {
+        Rule::hex_literal => parse_hex_literal(&p),
+        Rule::positive_numeric_literal => Ok(p.as_str().parse::<u128>()?),
+        _ => unimplemented!(),
+    }
+}
+
+fn parse_present_if(p: &RulePair) -> Result<Condition> {
+    assert!(p.as_rule() == Rule::present_if);
+
+    // Unwraps OK: ITR
+    let mut p = p.clone().into_inner();
+    let field = parse_field_path(&p.next().unwrap())?;
+
+    let op = match p.next() {
+        Some(op) => {
+            let value = parse_unsigned_value(&op.clone().into_inner().next().unwrap())?;
+            match op.as_rule() {
+                Rule::condition_and => ConditionOp::And(value),
+                Rule::condition_equals => ConditionOp::Equals(value),
+                _ => unimplemented!(),
+            }
+        }
+        None => ConditionOp::NonZero,
+    };
+
+    Ok(Condition { field, op })
+}
+
+fn parse_encoding(p: &RulePair) -> Result<ByteEncoding> {
+    assert!(p.as_rule() == Rule::encoding);
+    // Unwraps OK: ITR
+    parse_simple(&p.clone().into_inner().next().unwrap())
+}
+
+fn parse_compression(p: &RulePair) -> Result<Compression> {
+    assert!(p.as_rule() == Rule::compression);
+    // Unwraps OK: ITR
+    parse_simple(&p.clone().into_inner().next().unwrap())
+}
+
 fn parse_field(p: &RulePair) -> Result<Field> {
     assert!(p.as_rule() == Rule::field);
 
@@ -130,15 +359,73 @@ fn parse_field(p: &RulePair) -> Result<Field> {
     let nv = parse_name_value(&nv)?;
 
     let tv = p.next().unwrap();
-    let tv = parse_type_value(&tv)?;
+    let endianness = match tv
+        .clone()
+        .into_inner()
+        .flatten()
+        .find(|x| x.as_rule() == Rule::endianness)
+    {
+        Some(e) => parse_endianness(&e)?,
+        None => Endianness::default(),
+    };
+    let mut tv = parse_type_value(&tv)?;
+
+    if let Array::Bits(BitGroup(ref mut members)) = tv {
+        members[0].0 = nv.clone();
+    }
+
+    let mut compression = None;
+    let mut encoding = None;
+
+    for attr in p {
+        match attr.as_rule() {
+            Rule::present_if => {
+                tv = Conditional {
+                    array: Box::new(tv),
+                    condition: parse_present_if(&attr)?,
+                    present: None,
+                }
+                .into();
+            }
+            Rule::compression => compression = Some(parse_compression(&attr)?),
+            Rule::encoding => encoding = Some(parse_encoding(&attr)?),
+            _ => unimplemented!(),
+        }
+    }
 
     Ok(Field {
         name: nv,
         dtype: tv,
+        endianness,
+        compression,
+        encoding,
     })
 }
 
+/// Gets the name of the format a field includes, if its type is a format.
+fn try_parse_format_ref(p: &RulePair) -> Result<Option<Identifier>> {
+    assert!(p.as_rule() == Rule::field);
+
+    // Unwraps OK: ITR
+    let tv = p.clone().into_inner().nth(1).unwrap();
+    let t = tv.into_inner().next().unwrap();
+
+    match t.as_rule() {
+        Rule::format_ref => Ok(Some(parse_identifier(&t.into_inner().next().unwrap())?)),
+        _ => Ok(None),
+    }
+}
+
 fn parse_format(p: &RulePair) -> Result<Format> {
+    Ok(parse_format_with_includes(p, &Default::default())?.0)
+}
+
+/// Parses a format, flattening the fields of any previously defined formats
+/// it includes. Also returns the (field name, format name) of each include.
+fn parse_format_with_includes(
+    p: &RulePair,
+    defined: &HashMap<Identifier, AbstractFormatAndSemantics>,
+) -> Result<(Format, Vec<(Identifier, Identifier)>)> {
     assert!(p.as_rule() == Rule::format);
 
     // Unwraps OK: ITR
@@ -148,32 +435,145 @@ fn parse_format(p: &RulePair) -> Result<Format> {
     let id = parse_identifier(&id)?;
 
     let mut fields: Vec<Field> = Default::default();
-
-    // Unwraps OK: ITR
-    let f = p.next().unwrap();
-    fields.push(parse_field(&f)?);
+    let mut includes = vec![];
+    // Bit fields are not packed together with those of an included format.
+    let mut after_include = false;
 
     for f in p {
-        fields.push(parse_field(&f)?);
+        if let Some(included) = try_parse_format_ref(&f)? {
+            if f.clone().into_inner().nth(2).is_some() {
+                return Err(anyhow!(
+                    "included format {} cannot be optional or encoded",
+                    included.0
+                ));
+            }
+
+            // Unwraps OK: ITR
+            let prefix = parse_name_value(&f.clone().into_inner().next().unwrap())?;
+            let format = &defined
+                .get(&included)
+                .ok_or_else(|| anyhow!("format {} is included before it is defined", included.0))?
+                .format
+                .format;
+
+            fields.extend(format.fields.iter().map(|field| field.qualified(&prefix)));
+            includes.push((prefix, included));
+            after_include = true;
+            continue;
+        }
+
+        let mut field = parse_field(&f)?;
+
+        if let Array::List(ref mut list) = field.dtype {
+            let record = &list.record.name;
+            list.record = defined
+                .get(record)
+                .ok_or_else(|| anyhow!("format {} is listed before it is defined", record.0))?
+                .format
+                .format
+                .clone();
+        }
+
+        // Consecutive bit fields share the bytes they are packed into.
+        match (fields.last_mut(), field.dtype) {
+            (
+                Some(Field {
+                    name,
+                    dtype: Array::Bits(group),
+                    ..
+                }),
+                Array::Bits(BitGroup(members)),
+            ) if !after_include => {
+                group.0.extend(members);
+                *name = group.group_name();
+            }
+            (_, dtype) => fields.push(Field { dtype, ..field }),
+        }
+        after_include = false;
     }
 
-    Ok(Format { name: id, fields })
+    Ok((Format { name: id, fields }, includes))
 }
 
 fn parse_fixed_string_semantic(p: &RulePair) -> Result<FieldSemantic> {
     assert!(p.as_rule() == Rule::fixed_string_semantic);
 
+    // Escapes are resolved the same way as for delimiters.
     // Unwraps OK: ITR
-    let p = p
+    let bytes = parse_escaped_string(&p.clone().into_inner().next().unwrap())?;
+
+    Ok(FieldSemantic::FixedString(String::from_utf8(bytes)?))
+}
+
+fn parse_fixed_bytes_semantic(p: &RulePair) -> Result<FieldSemantic> {
+    assert!(p.as_rule() == Rule::fixed_bytes_semantic);
+
+    let mut bytes = vec![];
+    for literal in p.clone().into_inner() {
+        bytes.extend(parse_hex_bytes(&literal)?);
+    }
+
+    Ok(FieldSemantic::FixedBytes(bytes))
+}
+
+fn parse_length_covers_semantic(p: &RulePair) -> Result<FieldSemantic> {
+    assert!(p.as_rule() == Rule::length_covers_semantic);
+
+    // Unwraps OK: ITR
+    let mut p = p.clone().into_inner();
+    let first = parse_field_path(&p.next().unwrap())?;
+    let last = parse_field_path(&p.next().unwrap())?;
+
+    Ok(FieldSemantic::LengthCovers(first, last))
+}
+
+fn parse_checksum_semantic(p: &RulePair) -> Result<FieldSemantic> {
+    assert!(p.as_rule() == Rule::checksum_semantic);
+
+    // Unwraps OK: ITR
+    let mut p = p.clone().into_inner();
+    let algorithm = parse_simple(&p.next().unwrap())?;
+    let first = parse_field_path(&p.next().unwrap())?;
+    let last = match p.next() {
+        Some(last) => parse_field_path(&last)?,
+        None => first.clone(),
+    };
+
+    Ok(FieldSemantic::Checksum(algorithm, first, last))
+}
+
+fn parse_range_semantic(p: &RulePair) -> Result<FieldSemantic> {
+    assert!(p.as_rule() == Rule::range_semantic);
+
+    // Unwraps OK: ITR
+    let mut p = p.clone().into_inner();
+    let min = parse_unsigned_value(&p.next().unwrap())?;
+    let max = parse_unsigned_value(&p.next().unwrap())?;
+
+    Ok(FieldSemantic::Constraint(ValueConstraint::Range(min, max)))
+}
+
+fn parse_one_of_semantic(p: &RulePair) -> Result<FieldSemantic> {
+    assert!(p.as_rule() == Rule::one_of_semantic);
+
+    let values = p
         .clone()
         .into_inner()
-        .next()
-        .unwrap()
-        .into_inner()
-        .next()
-        .unwrap();
+        .map(|value| parse_unsigned_value(&value))
+        .collect::<Result<Vec<u128>>>()?;
+
+    Ok(FieldSemantic::Constraint(ValueConstraint::OneOf(values)))
+}
+
+fn parse_variable_semantic(p: &RulePair) -> Result<FieldSemantic> {
+    // Unwraps OK: ITR
+    let var = parse_identifier(&p.clone().into_inner().next().unwrap())?;
 
-    Ok(FieldSemantic::FixedString(p.as_str().to_string()))
+    match p.as_rule() {
+        Rule::save_semantic => Ok(FieldSemantic::Save(var)),
+        Rule::load_semantic => Ok(FieldSemantic::Load(var)),
+        _ => unimplemented!(),
+    }
 }
 
 fn parse_field_semantic(p: &RulePair) -> Result<FieldSemantic> {
@@ -182,10 +582,15 @@ fn parse_field_semantic(p: &RulePair) -> Result<FieldSemantic> {
     let maybe_inner_p = p.clone().into_inner().next();
 
     if let Some(ref inner_p) = maybe_inner_p {
-        if inner_p.as_rule() == Rule::fixed_string_semantic {
-            parse_fixed_string_semantic(inner_p)
-        } else {
-            unimplemented!();
+        match inner_p.as_rule() {
+            Rule::fixed_string_semantic => parse_fixed_string_semantic(inner_p),
+            Rule::fixed_bytes_semantic => parse_fixed_bytes_semantic(inner_p),
+            Rule::length_covers_semantic => parse_length_covers_semantic(inner_p),
+            Rule::checksum_semantic => parse_checksum_semantic(inner_p),
+            Rule::range_semantic => parse_range_semantic(inner_p),
+            Rule::one_of_semantic => parse_one_of_semantic(inner_p),
+            Rule::save_semantic | Rule::load_semantic => parse_variable_semantic(inner_p),
+            _ => unimplemented!(),
         }
     } else {
         parse_simple(p)
@@ -199,7 +604,7 @@ fn parse_semantic_binding(p: &RulePair) -> Result<SemanticBinding> {
 
     // Unwraps OK: ITR
     let format = parse_identifier(&p.next().unwrap())?;
-    let field = parse_identifier(&p.next().unwrap())?;
+    let field = parse_field_path(&p.next().unwrap())?;
     let semantic = parse_field_semantic(&p.next().unwrap())?;
 
     Ok(SemanticBinding {
@@ -346,20 +751,218 @@ pub fn parse_crypto_segment(p: &RulePair) -> Result<CryptoSpec> {
     ))
 }
 
+fn parse_fixed_distribution(p: &RulePair) -> Result<Distribution> {
+    assert!(p.as_rule() == Rule::fixed_distribution);
+
+    // Unwraps OK: ITR
+    let mut p = p.clone().into_inner();
+    let value = parse_positive_numeric_literal(&p.next().unwrap())?;
+
+    Ok(Distribution::Fixed(value))
+}
+
+fn parse_uniform_distribution(p: &RulePair) -> Result<Distribution> {
+    assert!(p.as_rule() == Rule::uniform_distribution);
+
+    // Unwraps OK: ITR
+    let mut p = p.clone().into_inner();
+    let low = parse_positive_numeric_literal(&p.next().unwrap())?;
+    let high = parse_positive_numeric_literal(&p.next().unwrap())?;
+
+    Ok(Distribution::Uniform(low, high))
+}
+
+fn parse_histogram_bin(p: &RulePair) -> Result<(usize, usize)> {
+    assert!(p.as_rule() == Rule::histogram_bin);
+
+    // Unwraps OK: ITR
+    let mut p = p.clone().into_inner();
+    let value = p.next().unwrap();
+    let value = match value.as_rule() {
+        Rule::infinity => Distribution::INFINITY,
+        _ => parse_positive_numeric_literal(&value)?,
+    };
+    let weight = parse_positive_numeric_literal(&p.next().unwrap())?;
+
+    Ok((value, weight))
+}
+
+fn parse_histogram_distribution(p: &RulePair) -> Result<Distribution> {
+    assert!(p.as_rule() == Rule::histogram_distribution);
+
+    let mut bins = vec![];
+
+    for x in p.clone().into_inner() {
+        bins.push(parse_histogram_bin(&x)?);
+    }
+
+    Ok(Distribution::Histogram(bins))
+}
+
+fn parse_distribution(p: &RulePair) -> Result<Distribution> {
+    assert!(p.as_rule() == Rule::distribution);
+
+    // Unwraps OK: ITR
+    let p = p.clone().into_inner().next().unwrap();
+
+    match p.as_rule() {
+        Rule::fixed_distribution => parse_fixed_distribution(&p),
+        Rule::uniform_distribution => parse_uniform_distribution(&p),
+        Rule::histogram_distribution => parse_histogram_distribution(&p),
+        _ => unimplemented!(),
+    }
+}
+
+fn parse_size_directive(p: &RulePair) -> Result<(Identifier, Distribution)> {
+    assert!(p.as_rule() == Rule::size_directive);
+
+    let mut p = p.clone().into_inner();
+
+    // Unwraps OK: ITR
+    let format = parse_identifier(&p.next().unwrap())?;
+    let distribution = parse_distribution(&p.next().unwrap())?;
+
+    Ok((format, distribution))
+}
+
+fn parse_delay_target(p: &RulePair) -> Result<DelayTarget> {
+    assert!(p.as_rule() == Rule::delay_target);
+
+    // Unwraps OK: ITR
+    let p = p.clone().into_inner().next().unwrap();
+
+    match p.as_rule() {
+        Rule::identifier => Ok(DelayTarget::Format(parse_identifier(&p)?)),
+        Rule::phase => Ok(DelayTarget::Phase(parse_phase(&p)?)),
+        _ => unimplemented!(),
+    }
+}
+
+fn parse_delay_directive(p: &RulePair) -> Result<DelayBinding> {
+    assert!(p.as_rule() == Rule::delay_directive);
+
+    let mut p = p.clone().into_inner();
+
+    // Unwraps OK: ITR
+    let target = parse_delay_target(&p.next().unwrap())?;
+    let delay = parse_distribution(&p.next().unwrap())?;
+
+    Ok(DelayBinding { target, delay })
+}
+
+fn parse_chaff_directive(p: &RulePair) -> Result<(Identifier, Distribution)> {
+    assert!(p.as_rule() == Rule::chaff_directive);
+
+    let mut p = p.clone().into_inner();
+
+    // Unwraps OK: ITR
+    let format = parse_identifier(&p.next().unwrap())?;
+    let idle = parse_distribution(&p.next().unwrap())?;
+
+    Ok((format, idle))
+}
+
+fn parse_align_directive(p: &RulePair) -> Result<(Identifier, usize)> {
+    assert!(p.as_rule() == Rule::align_directive);
+
+    let mut p = p.clone().into_inner();
+
+    // Unwraps OK: ITR
+    let format = parse_identifier(&p.next().unwrap())?;
+    let align = parse_positive_numeric_literal(&p.next().unwrap())?;
+
+    Ok((format, align))
+}
+
+fn parse_constant_rate_directive(p: &RulePair) -> Result<ConstantRate> {
+    assert!(p.as_rule() == Rule::constant_rate_directive);
+
+    let mut p = p.clone().into_inner();
+
+    // Unwraps OK: ITR
+    let size = parse_positive_numeric_literal(&p.next().unwrap())?;
+    let interval = parse_positive_numeric_literal(&p.next().unwrap())?;
+
+    Ok(ConstantRate { size, interval })
+}
+
+fn parse_adaptive_padding_directive(p: &RulePair) -> Result<(Role, AdaptivePaddingSpec)> {
+    assert!(p.as_rule() == Rule::adaptive_padding_directive);
+
+    let mut p = p.clone().into_inner();
+
+    // Unwraps OK: ITR
+    let role = parse_role(&p.next().unwrap())?;
+    let burst = parse_distribution(&p.next().unwrap())?;
+    let gap = parse_distribution(&p.next().unwrap())?;
+
+    Ok((role, AdaptivePaddingSpec { burst, gap }))
+}
+
+pub fn parse_traffic_segment(p: &RulePair) -> Result<TrafficSpec> {
+    assert!(p.as_rule() == Rule::traffic_segment);
+
+    let mut traffic_spec = TrafficSpec::default();
+
+    for e in p.clone().into_inner() {
+        match e.as_rule() {
+            Rule::size_directive => {
+                let (format, distribution) = parse_size_directive(&e)?;
+                traffic_spec.sizes.insert(format, distribution);
+            }
+            Rule::delay_directive => {
+                let binding = parse_delay_directive(&e)?;
+                match binding.target {
+                    DelayTarget::Format(format) => {
+                        traffic_spec.format_delays.insert(format, binding.delay);
+                    }
+                    DelayTarget::Phase(phase) => {
+                        traffic_spec.phase_delays.insert(phase, binding.delay);
+                    }
+                }
+            }
+            Rule::chaff_directive => {
+                let (format, idle) = parse_chaff_directive(&e)?;
+                traffic_spec.chaff.insert(format, idle);
+            }
+            Rule::align_directive => {
+                let (format, align) = parse_align_directive(&e)?;
+                traffic_spec.alignments.insert(format, align);
+            }
+            Rule::constant_rate_directive => {
+                traffic_spec.constant_rate = Some(parse_constant_rate_directive(&e)?);
+            }
+            Rule::adaptive_padding_directive => {
+                let (role, spec) = parse_adaptive_padding_directive(&e)?;
+                traffic_spec.adaptive_padding.insert(role, spec);
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    Ok(traffic_spec)
+}
+
 pub fn parse_psf_impl(p: &RulePair) -> Result<Psf> {
     assert!(p.as_rule() == Rule::psf);
 
     let mut formats: HashMap<Identifier, AbstractFormatAndSemantics> = Default::default();
     let mut sequence: Vec<SequenceSpecifier> = vec![];
     let mut crypto_spec: Option<CryptoSpec> = None;
+    let mut traffic_spec: TrafficSpec = Default::default();
+    let mut includes: Vec<(Identifier, Identifier, Identifier)> = vec![];
 
     let p = p.clone().into_inner();
 
     for x in p {
         match x.as_rule() {
             Rule::format => {
+                let (format, format_includes) = parse_format_with_includes(&x, &formats)?;
                 let format: AbstractFormatAndSemantics =
-                    Into::<AbstractFormat>::into(parse_format(&x)?).into();
+                    Into::<AbstractFormat>::into(format).into();
+                includes.extend(format_includes.into_iter().map(|(prefix, included)| {
+                    (format.format.format.name.clone(), prefix, included)
+                }));
                 formats.insert(format.format.format.name.clone(), format);
             }
             Rule::semantic_binding => {
@@ -378,14 +981,54 @@ pub fn parse_psf_impl(p: &RulePair) -> Result<Psf> {
             Rule::crypto_segment => {
                 crypto_spec = Some(parse_crypto_segment(&x)?);
             }
+            Rule::traffic_segment => {
+                traffic_spec = parse_traffic_segment(&x)?;
+            }
             _ => {}
         }
     }
 
+    // Included formats hand their semantics down once all are bound. Formats
+    // may only include earlier ones, so going in order also covers nesting.
+    for (format, prefix, included) in includes {
+        // Unwraps OK: both formats were inserted above.
+        let inherited = formats.get(&included).unwrap().semantics.clone();
+        formats
+            .get_mut(&format)
+            .unwrap()
+            .semantics
+            .inherit(&prefix, &inherited);
+    }
+
+    // Aligned formats that leave out padding get a padding length and padding
+    // of their own.
+    for format in traffic_spec.alignments.keys() {
+        if let Some(afs) = formats.get_mut(format) {
+            if afs
+                .semantics
+                .find_field_id(FieldSemantic::Padding)
+                .is_none()
+            {
+                afs.add_padding();
+            }
+        }
+    }
+
+    for (name, afs) in formats.iter() {
+        if let Some(semantic) = afs.semantics.find_duplicate_binding() {
+            return Err(anyhow!(
+                "format {} binds {:?} to more than one field",
+                name.0,
+                semantic
+            ));
+        }
+    }
+
     Ok(Psf {
         formats,
         sequence,
         crypto_spec,
+        traffic_spec,
     })
 }
 
@@ -447,6 +1090,7 @@ pub mod tests {
     fn test_parse_primitive_type() {
         let test_cases = vec![
             ("u8", NumericType::U8.into()),
+            ("u32le", NumericType::U32.into()),
             ("bool", PrimitiveType::Bool),
             ("char", PrimitiveType::Char),
         ];
@@ -497,6 +1141,46 @@ pub mod tests {
         test_rule_pair(test_cases.iter(), Rule::dynamic_array, parse_dynamic_array);
     }
 
+    #[test]
+    fn test_parse_text_type() {
+        let test_cases = [
+            (
+                "dec<4>",
+                TextNumber {
+                    radix: TextRadix::Decimal,
+                    end: TextEnd::Width(4),
+                    size: None,
+                },
+            ),
+            (
+                r#"hex UNTIL "\r\n""#,
+                TextNumber {
+                    radix: TextRadix::Hex,
+                    end: TextEnd::Until(b"\r\n".to_vec()),
+                    size: None,
+                },
+            ),
+        ];
+
+        test_rule_pair(test_cases.iter(), Rule::text_type, parse_text_type);
+    }
+
+    #[test]
+    fn test_parse_delimited_array() {
+        let test_cases = [
+            (
+                r#"[u8; UNTIL "\r\n"]"#,
+                DynamicArray(UnaryOp::Until(b"\r\n".to_vec())),
+            ),
+            (
+                r#"[u8; UNTIL "\u0000;\""]"#,
+                DynamicArray(UnaryOp::Until(b"\0;\"".to_vec())),
+            ),
+        ];
+
+        test_rule_pair(test_cases.iter(), Rule::dynamic_array, parse_dynamic_array);
+    }
+
     #[test]
     fn test_parse_array() {
         let test_cases = vec![
@@ -513,6 +1197,67 @@ pub mod tests {
         test_rule_pair(test_cases.iter(), Rule::array, parse_array);
     }
 
+    #[test]
+    fn test_parse_record_list() {
+        let list = |length| RecordList {
+            record: Format {
+                name: "Extension".id(),
+                fields: vec![],
+            },
+            length,
+            size: None,
+        };
+        let test_cases = [
+            ("[Extension; count]", list(ListLength::Count("count".id()))),
+            (
+                "[Extension; hdr.length.size_of]",
+                list(ListLength::Bytes("hdr.length".id())),
+            ),
+        ];
+
+        test_rule_pair(test_cases.iter(), Rule::record_list, parse_record_list);
+    }
+
+    #[test]
+    fn test_parse_length_expr() {
+        let test_cases = [
+            (
+                "[u8; length]",
+                DynamicArray(UnaryOp::Expr(LengthExpr {
+                    field: "length".id(),
+                    scale: 1,
+                    bias: 0,
+                })),
+            ),
+            (
+                "[u8; length - 16]",
+                DynamicArray(UnaryOp::Expr(LengthExpr {
+                    field: "length".id(),
+                    scale: 1,
+                    bias: -16,
+                })),
+            ),
+            (
+                "[u8; length * 4]",
+                DynamicArray(UnaryOp::Expr(LengthExpr {
+                    field: "length".id(),
+                    scale: 4,
+                    bias: 0,
+                })),
+            ),
+            (
+                "[u8; words*4+2]",
+                DynamicArray(UnaryOp::Expr(LengthExpr {
+                    field: "words".id(),
+                    scale: 4,
+                    bias: 2,
+                })),
+            ),
+        ];
+
+        test_rule_pair(test_cases.iter(), Rule::dynamic_array, parse_dynamic_array);
+    }
+
     #[test]
     fn test_name_value() {
         let test_cases = vec![("NAME: Foo", "Foo".parse().unwrap())];
@@ -540,12 +1285,149 @@ pub mod tests {
             Field {
                 name: "Foo".parse().unwrap(),
                 dtype: PrimitiveArray(NumericType::U8.into(), 1).into(),
+                endianness: Endianness::Big,
+                compression: None,
+                encoding: None,
             },
         )];
 
         test_rule_pair(test_cases.iter(), Rule::field, parse_field);
     }
 
+    #[test]
+    fn test_parse_varint_field() {
+        let test_cases = [
+            (
+                "{ NAME: length; TYPE: quic_varint }",
+                Field {
+                    name: "length".id(),
+                    dtype: Varint(VarintType::Quic, None).into(),
+                    endianness: Endianness::Big,
+                    compression: None,
+                    encoding: None,
+                },
+            ),
+            (
+                "{ NAME: length; TYPE: leb128 }",
+                Field {
+                    name: "length".id(),
+                    dtype: Varint(VarintType::Leb128, None).into(),
+                    endianness: Endianness::Big,
+                    compression: None,
+                    encoding: None,
+                },
+            ),
+        ];
+
+        test_rule_pair(test_cases.iter(), Rule::field, parse_field);
+    }
+
+    #[test]
+    fn test_parse_field_endianness() {
+        let test_cases = [
+            (
+                "{ NAME: Foo; TYPE: u32le }",
+                Field {
+                    name: "Foo".id(),
+                    dtype: PrimitiveArray(NumericType::U32.into(), 1).into(),
+                    endianness: Endianness::Little,
+                    compression: None,
+                    encoding: None,
+                },
+            ),
+            (
+                "{ NAME: Foo; TYPE: [i16be; 4] }",
+                Field {
+                    name: "Foo".id(),
+                    dtype: PrimitiveArray(NumericType::I16.into(), 4).into(),
+                    endianness: Endianness::Big,
+                    compression: None,
+                    encoding: None,
+                },
+            ),
+        ];
+
+        test_rule_pair(test_cases.iter(), Rule::field, parse_field);
+    }
+
+    #[test]
+    fn test_parse_field_present_if() {
+        let optional = |op| -> Array {
+            Conditional {
+                array: Box::new(PrimitiveArray(NumericType::U32.into(), 1).into()),
+                condition: Condition {
+                    field: "flags".id(),
+                    op,
+                },
+                present: None,
+            }
+            .into()
+        };
+        let test_cases = [
+            ("flags & 0x0a", optional(ConditionOp::And(10))),
+            ("flags == 3", optional(ConditionOp::Equals(3))),
+            ("flags", optional(ConditionOp::NonZero)),
+        ]
+        .map(|(condition, dtype)| {
+            (
+                format!("{{ NAME: ext; TYPE: u32; PRESENT_IF: {} }}", condition),
+                Field {
+                    name: "ext".id(),
+                    dtype,
+                    endianness: Endianness::Big,
+                    compression: None,
+                    encoding: None,
+                },
+            )
+        });
+
+        for (input, expected) in &test_cases {
+            let pair = ProteusLiteParser::parse(Rule::field, input)
+                .unwrap()
+                .next()
+                .unwrap();
+            assert_eq!(&parse_field(&pair).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_parse_field_encoding() {
+        let test_cases = [
+            (
+                "{ NAME: payload; TYPE: [u8; length.size_of]; ENCODING: base64 }",
+                Field {
+                    name: "payload".id(),
+                    dtype: DynamicArray(UnaryOp::SizeOf("length".id())).into(),
+                    endianness: Endianness::Big,
+                    compression: None,
+                    encoding: Some(ByteEncoding::Base64),
+                },
+            ),
+            (
+                "{ NAME: mac; TYPE: [u8; 16]; ENCODING: hex }",
+                Field {
+                    name: "mac".id(),
+                    dtype: PrimitiveArray(NumericType::U8.into(), 16).into(),
+                    endianness: Endianness::Big,
+                    compression: None,
+                    encoding: Some(ByteEncoding::Hex),
+                },
+            ),
+            (
+                "{ NAME: payload; TYPE: [u8; length.size_of]; COMPRESS: deflate; ENCODING: base64 }",
+                Field {
+                    name: "payload".id(),
+                    dtype: DynamicArray(UnaryOp::SizeOf("length".id())).into(),
+                    endianness: Endianness::Big,
+                    compression: Some(Compression::Deflate),
+                    encoding: Some(ByteEncoding::Base64),
+                },
+            ),
+        ];
+
+        test_rule_pair(test_cases.iter(), Rule::field, parse_field);
+    }
+
     #[test]
     fn test_format() {
         let test_cases = vec![(
@@ -558,10 +1440,16 @@ pub mod tests {
                     Field {
                         name: "Foo".parse().unwrap(),
                         dtype: PrimitiveArray(NumericType::U8.into(), 1).into(),
+                        endianness: Endianness::Big,
+                        compression: None,
+                        encoding: None,
                     },
                     Field {
                         name: "Bar".parse().unwrap(),
                         dtype: PrimitiveArray(NumericType::U32.into(), 10).into(),
+                        endianness: Endianness::Big,
+                        compression: None,
+                        encoding: None,
                     },
                 ],
             },
@@ -573,12 +1461,61 @@ pub mod tests {
     }
 
     #[test]
-    fn test_parse_fixed_string_semantic() {
-        let test_cases = vec![(
-            "FIXED_STRING(\"foo\")",
-            FieldSemantic::FixedString("foo".to_string()),
+    fn test_format_bit_fields() {
+        let test_cases = [(
+            "DEFINE Header \
+            {NAME: flags; TYPE: bits<3>}, \
+            {NAME: kind; TYPE: bits<5>}, \
+            {NAME: id; TYPE: u16}, \
+            {NAME: fin; TYPE: bits<1>}, \
+            {NAME: rest; TYPE: bits<15>};",
+            Format {
+                name: "Header".id(),
+                fields: [
+                    Field {
+                        name: "flags|kind".id(),
+                        dtype: BitGroup([("flags".id(), 3), ("kind".id(), 5)].to_vec()).into(),
+                        endianness: Endianness::Big,
+                        compression: None,
+                        encoding: None,
+                    },
+                    Field {
+                        name: "id".id(),
+                        dtype: PrimitiveArray(NumericType::U16.into(), 1).into(),
+                        endianness: Endianness::Big,
+                        compression: None,
+                        encoding: None,
+                    },
+                    Field {
+                        name: "fin|rest".id(),
+                        dtype: BitGroup([("fin".id(), 1), ("rest".id(), 15)].to_vec()).into(),
+                        endianness: Endianness::Big,
+                        compression: None,
+                        encoding: None,
+                    },
+                ]
+                .to_vec(),
+            },
         )];
 
+        test_rule_pair(test_cases.iter(), Rule::format, parse_format);
+
+        assert_eq!(test_cases[0].1.maybe_size_of(), Some(5));
+    }
+
+    #[test]
+    fn test_parse_fixed_string_semantic() {
+        let test_cases = vec![
+            (
+                "FIXED_STRING(\"foo\")",
+                FieldSemantic::FixedString("foo".to_string()),
+            ),
+            (
+                "FIXED_STRING(\"HTTP/1.1\\r\\n\")",
+                FieldSemantic::FixedString("HTTP/1.1\r\n".to_string()),
+            ),
+        ];
+
         test_rule_pair(
             test_cases.iter(),
             Rule::fixed_string_semantic,
@@ -586,16 +1523,85 @@ pub mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_length_covers_semantic() {
+        let test_cases = [(
+            "LENGTH(COVERS: header..mac)",
+            FieldSemantic::LengthCovers("header".id(), "mac".id()),
+        )];
+
+        test_rule_pair(
+            test_cases.iter(),
+            Rule::field_semantic,
+            parse_field_semantic,
+        );
+    }
+
+    #[test]
+    fn test_parse_checksum_semantic() {
+        let test_cases = [
+            (
+                "CHECKSUM(crc32, header..payload)",
+                FieldSemantic::Checksum(ChecksumAlgorithm::Crc32, "header".id(), "payload".id()),
+            ),
+            (
+                "CHECKSUM(internet, payload)",
+                FieldSemantic::Checksum(
+                    ChecksumAlgorithm::Internet,
+                    "payload".id(),
+                    "payload".id(),
+                ),
+            ),
+        ];
+
+        test_rule_pair(
+            test_cases.iter(),
+            Rule::field_semantic,
+            parse_field_semantic,
+        );
+    }
+
+    #[test]
+    fn test_parse_constraint_semantic() {
+        let test_cases = [
+            (
+                "RANGE(1, 16384)",
+                FieldSemantic::Constraint(ValueConstraint::Range(1, 16384)),
+            ),
+            (
+                "ONE_OF(0x14, 0x15, 23)",
+                FieldSemantic::Constraint(ValueConstraint::OneOf(vec![0x14, 0x15, 23])),
+            ),
+        ];
+
+        test_rule_pair(
+            test_cases.iter(),
+            Rule::field_semantic,
+            parse_field_semantic,
+        );
+    }
+
     #[test]
     fn test_parse_field_semantic() {
         let test_cases = vec![
             ("PAYLOAD", FieldSemantic::Payload),
             ("PADDING", FieldSemantic::Padding),
             ("LENGTH", FieldSemantic::Length),
+            ("SEQUENCE_NUMBER", FieldSemantic::SequenceNumber),
             (
                 "FIXED_STRING(\"foo\")",
                 FieldSemantic::FixedString("foo".to_string()),
             ),
+            (
+                "FIXED_BYTES(0x160301)",
+                FieldSemantic::FixedBytes(vec![0x16, 0x03, 0x01]),
+            ),
+            (
+                "FIXED_BYTES(0x16 0x0301)",
+                FieldSemantic::FixedBytes(vec![0x16, 0x03, 0x01]),
+            ),
+            ("SAVE(session_id)", FieldSemantic::Save("session_id".id())),
+            ("LOAD(session_id)", FieldSemantic::Load("session_id".id())),
         ];
 
         test_rule_pair(
@@ -603,6 +1609,12 @@ pub mod tests {
             Rule::field_semantic,
             parse_field_semantic,
         );
+
+        let pair = ProteusLiteParser::parse(Rule::field_semantic, "FIXED_BYTES(0x160)")
+            .unwrap()
+            .next()
+            .unwrap();
+        assert!(parse_field_semantic(&pair).is_err());
     }
 
     #[test]
@@ -803,6 +1815,99 @@ pub mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_distribution() {
+        let test_cases = [
+            ("FIXED(10)", Distribution::Fixed(10)),
+            ("UNIFORM(1, 1500)", Distribution::Uniform(1, 1500)),
+            (
+                "HISTOGRAM(64: 2, 1500: 8)",
+                Distribution::Histogram(vec![(64, 2), (1500, 8)]),
+            ),
+            (
+                "HISTOGRAM(5: 3, INF: 1)",
+                Distribution::Histogram(vec![(5, 3), (Distribution::INFINITY, 1)]),
+            ),
+        ];
+
+        test_rule_pair(test_cases.iter(), Rule::distribution, parse_distribution);
+    }
+
+    #[test]
+    fn test_parse_delay_directive() {
+        let test_cases = [
+            (
+                "{ FORMAT: Foo; DELAY: FIXED(5) };",
+                DelayBinding {
+                    target: DelayTarget::Format("Foo".id()),
+                    delay: Distribution::Fixed(5),
+                },
+            ),
+            (
+                "{ PHASE: DATA; DELAY: UNIFORM(0, 20) };",
+                DelayBinding {
+                    target: DelayTarget::Phase(Phase::Data),
+                    delay: Distribution::Uniform(0, 20),
+                },
+            ),
+        ];
+
+        test_rule_pair(
+            test_cases.iter(),
+            Rule::delay_directive,
+            parse_delay_directive,
+        );
+    }
+
+    #[test]
+    fn test_parse_traffic_segment() {
+        let input = "@SEGMENT.TRAFFIC\
+            { FORMAT: Foo; SIZE: UNIFORM(100, 200) };\
+            { FORMAT: Bar; SIZE: FIXED(512) };\
+            { FORMAT: Bar; DELAY: FIXED(10) };\
+            { PHASE: HANDSHAKE; DELAY: HISTOGRAM(0: 1, 100: 1) };\
+            { FORMAT: Foo; CHAFF: UNIFORM(50, 250) };\
+            { FORMAT: Bar; ALIGN: 16 };\
+            { CONSTANT_RATE: 1500; INTERVAL: 20 };\
+            { ROLE: CLIENT; BURST: HISTOGRAM(2: 1, INF: 1); GAP: UNIFORM(10, 50) };";
+
+        let mut output = TrafficSpec::default();
+        output
+            .sizes
+            .insert("Foo".id(), Distribution::Uniform(100, 200));
+        output.sizes.insert("Bar".id(), Distribution::Fixed(512));
+        output
+            .format_delays
+            .insert("Bar".id(), Distribution::Fixed(10));
+        output.phase_delays.insert(
+            Phase::Handshake,
+            Distribution::Histogram(vec![(0, 1), (100, 1)]),
+        );
+        output
+            .chaff
+            .insert("Foo".id(), Distribution::Uniform(50, 250));
+        output.alignments.insert("Bar".id(), 16);
+        output.constant_rate = Some(ConstantRate {
+            size: 1500,
+            interval: 20,
+        });
+        output.adaptive_padding.insert(
+            Role::Client,
+            AdaptivePaddingSpec {
+                burst: Distribution::Histogram(vec![(2, 1), (Distribution::INFINITY, 1)]),
+                gap: Distribution::Uniform(10, 50),
+            },
+        );
+
+        let test_cases = [(input, output)];
+
+        test_rule_pair(
+            test_cases.iter(),
+            Rule::traffic_segment,
+            parse_traffic_segment,
+        );
+    }
+
     pub fn parse_example_psf() -> Result<Psf> {
         let filepath = "examples/psf/example.psf";
         let input = fs::read_to_string(filepath).expect("cannot read example file");
@@ -824,4 +1929,345 @@ pub mod tests {
     fn test_parse_shadowsocks_psf() {
         assert!(parse_shadowsocks_psf().is_ok());
     }
+
+    pub fn parse_shaped_psf() -> Result<Psf> {
+        let filepath = "examples/psf/shaped.psf";
+        let input = fs::read_to_string(filepath).expect("cannot read shaped file");
+        parse_psf(&input)
+    }
+
+    #[test]
+    fn test_parse_shaped_psf() {
+        let psf = parse_shaped_psf().unwrap();
+        assert!(psf.traffic_spec.sizes.contains_key(&"EncDataMsg".id()));
+    }
+
+    #[test]
+    fn test_parse_field_path() {
+        let test_cases = [
+            ("length", "length".id()),
+            ("header.length", "header.length".id()),
+        ];
+
+        test_rule_pair(test_cases.iter(), Rule::field_path, parse_field_path);
+        test_rule_pair(
+            [(
+                "header.length.size_of",
+                UnaryOp::SizeOf("header.length".id()),
+            )]
+            .iter(),
+            Rule::size_of_op,
+            parse_sizeof_op,
+        );
+    }
+
+    #[test]
+    fn test_parse_nested_psf() {
+        let filepath = "examples/psf/nested.psf";
+        let input = fs::read_to_string(filepath).expect("cannot read nested file");
+        let psf = parse_psf(&input).unwrap();
+
+        let tagged = &psf.formats[&"TaggedRecord".id()];
+        let names: Vec<Identifier> = tagged
+            .format
+            .format
+            .fields
+            .iter()
+            .map(|f| f.name.clone())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "tag".id(),
+                "record.header.magic".id(),
+                "record.header.version|record.header.flags".id(),
+                "record.header.length".id(),
+                "record.payload".id(),
+            ]
+        );
+        assert_eq!(
+            tagged.format.format.fields[4].dtype,
+            DynamicArray(UnaryOp::SizeOf("record.header.length".id())).into()
+        );
+
+        // Semantics are inherited, except for the payload of Record.
+        let semantics = &tagged.semantics;
+        assert_eq!(
+            semantics.find_field_id(FieldSemantic::Length),
+            Some("record.header.length".id())
+        );
+        assert_eq!(
+            semantics.get_fixed_fields(),
+            [("record.header.magic".id(), b"PR".to_vec())]
+        );
+        assert_eq!(
+            semantics.find_field_id(FieldSemantic::Payload),
+            Some("record.payload".id())
+        );
+    }
+
+    /// Parses a PSF without asserting that it is valid, so that tests can
+    /// check what validation makes of it.
+    fn parse(input: &str) -> Result<Psf> {
+        let pair = ProteusLiteParser::parse(Rule::psf, input)
+            .unwrap()
+            .next()
+            .unwrap();
+        parse_psf_impl(&pair)
+    }
+
+    #[test]
+    fn test_parse_include_boundaries() {
+        // Bits on either side of an include stay in their own groups.
+        let psf = parse(
+            "@SEGMENT.FORMATS
+            DEFINE Flags
+              { NAME: a ; TYPE: bits<4> },
+              { NAME: b ; TYPE: bits<4> };
+            DEFINE Outer
+              { NAME: flags ; TYPE: Flags },
+              { NAME: c     ; TYPE: bits<4> },
+              { NAME: d     ; TYPE: bits<4> };
+            @SEGMENT.SEMANTICS
+            @SEGMENT.SEQUENCE
+              { ROLE: CLIENT; PHASE: DATA; FORMAT: Outer };",
+        )
+        .unwrap();
+        let names: Vec<Identifier> = psf.formats[&"Outer".id()]
+            .format
+            .format
+            .fields
+            .iter()
+            .map(|f| f.name.clone())
+            .collect();
+        assert_eq!(names, ["flags.a|flags.b".id(), "c|d".id()]);
+
+        // Two included formats can't both hand down the length.
+        assert!(parse(
+            "@SEGMENT.FORMATS
+            DEFINE Header
+              { NAME: length ; TYPE: u16 };
+            DEFINE Outer
+              { NAME: first  ; TYPE: Header },
+              { NAME: second ; TYPE: Header };
+            @SEGMENT.SEMANTICS
+              { FORMAT: Header; FIELD: length; SEMANTIC: LENGTH };
+            @SEGMENT.SEQUENCE
+              { ROLE: CLIENT; PHASE: DATA; FORMAT: Outer };",
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_validate_compression() {
+        let packet = |nonce: &str, payload: &str| {
+            parse(&format!(
+                "@SEGMENT.FORMATS
+                DEFINE Packet
+                  {{ NAME: length  ; TYPE: u16 }},
+                  {{ NAME: nonce   ; TYPE: [u8; 8]{} }},
+                  {{ NAME: payload ; TYPE: [u8; length.size_of]{} }};
+                @SEGMENT.SEMANTICS
+                  {{ FORMAT: Packet; FIELD: length;  SEMANTIC: LENGTH }};
+                  {{ FORMAT: Packet; FIELD: payload; SEMANTIC: PAYLOAD }};
+                @SEGMENT.SEQUENCE
+                  {{ ROLE: CLIENT; PHASE: DATA; FORMAT: Packet }};",
+                nonce, payload
+            ))
+            .unwrap()
+        };
+        assert!(packet("", "; COMPRESS: deflate").is_valid());
+        assert!(!packet("; COMPRESS: deflate", "").is_valid());
+
+        // Extension could compress its payload on its own, but not as a record
+        // in Hello's list.
+        let hello = |value: &str| {
+            parse(&format!(
+                "@SEGMENT.FORMATS
+                DEFINE Extension
+                  {{ NAME: value_length ; TYPE: u8 }},
+                  {{ NAME: value        ; TYPE: [u8; value_length.size_of]{} }};
+                DEFINE Hello
+                  {{ NAME: length     ; TYPE: u16 }},
+                  {{ NAME: ext_length ; TYPE: u16 }},
+                  {{ NAME: extensions ; TYPE: [Extension; ext_length.size_of] }},
+                  {{ NAME: payload    ; TYPE: [u8; length.size_of] }};
+                @SEGMENT.SEMANTICS
+                  {{ FORMAT: Extension; FIELD: value; SEMANTIC: PAYLOAD }};
+                  {{ FORMAT: Hello; FIELD: length;  SEMANTIC: LENGTH }};
+                  {{ FORMAT: Hello; FIELD: payload; SEMANTIC: PAYLOAD }};
+                @SEGMENT.SEQUENCE
+                  {{ ROLE: CLIENT; PHASE: DATA; FORMAT: Hello }};",
+                value
+            ))
+            .unwrap()
+        };
+        assert!(hello("").is_valid());
+        assert!(!hello("; COMPRESS: deflate").is_valid());
+    }
+
+    #[test]
+    fn test_validate_payload_room() {
+        let packet = |payload: &str, size: &str| {
+            parse(&format!(
+                "@SEGMENT.FORMATS
+                DEFINE Packet
+                  {{ NAME: length  ; TYPE: u16 }},
+                  {{ NAME: payload ; TYPE: [u8; length.size_of]{} }};
+                @SEGMENT.SEMANTICS
+                  {{ FORMAT: Packet; FIELD: length;  SEMANTIC: LENGTH }};
+                  {{ FORMAT: Packet; FIELD: payload; SEMANTIC: PAYLOAD }};
+                @SEGMENT.SEQUENCE
+                  {{ ROLE: CLIENT; PHASE: DATA; FORMAT: Packet }};
+                @SEGMENT.TRAFFIC
+                  {{ FORMAT: Packet; SIZE: {} }};",
+                payload, size
+            ))
+            .unwrap()
+        };
+
+        // The smallest message must fit a byte of app data once encoded or
+        // compressed, or the sender reads nothing and only ever pads.
+        assert!(packet("", "FIXED(3)").is_valid());
+        assert!(!packet("; ENCODING: base64", "FIXED(3)").is_valid());
+        assert!(!packet("; ENCODING: base64", "HISTOGRAM(3: 1, 1500: 1)").is_valid());
+        assert!(packet("; ENCODING: base64", "FIXED(6)").is_valid());
+        assert!(!packet("; COMPRESS: deflate", "FIXED(7)").is_valid());
+        assert!(packet("; COMPRESS: deflate", "FIXED(8)").is_valid());
+    }
+
+    #[test]
+    fn test_validate_conditions() {
+        let packet = |flags: &str, condition: &str| {
+            parse(&format!(
+                "@SEGMENT.FORMATS
+                DEFINE Packet
+                  {{ NAME: flags   ; TYPE: u8 }},
+                  {{ NAME: length  ; TYPE: u16 }},
+                  {{ NAME: ext     ; TYPE: u32; PRESENT_IF: {} }},
+                  {{ NAME: payload ; TYPE: [u8; length.size_of] }};
+                @SEGMENT.SEMANTICS
+                  {}
+                  {{ FORMAT: Packet; FIELD: length;  SEMANTIC: LENGTH }};
+                  {{ FORMAT: Packet; FIELD: payload; SEMANTIC: PAYLOAD }};
+                @SEGMENT.SEQUENCE
+                  {{ ROLE: CLIENT; PHASE: DATA; FORMAT: Packet }};",
+                condition, flags
+            ))
+            .unwrap()
+        };
+        let fixed = "{ FORMAT: Packet; FIELD: flags; SEMANTIC: FIXED_STRING(\"A\") };";
+
+        // Without a fixed value, flags are sent as zero.
+        assert!(packet("", "flags == 0").is_valid());
+        assert!(!packet("", "flags & 0x01").is_valid());
+        assert!(!packet("", "flags").is_valid());
+        assert!(packet(fixed, "flags & 0x01").is_valid());
+        assert!(packet(fixed, "flags").is_valid());
+
+        // Lengths are only known once the message is laid out.
+        assert!(!packet(fixed, "length == 0").is_valid());
+    }
+
+    #[test]
+    fn test_validate_alignment() {
+        // Optionally with a field after the flags, and with padding of the
+        // format's own given its length type and attributes.
+        let packet = |ext: &str, padding: Option<(&str, &str)>, align: usize| {
+            let (pad_length, padding, binding) = match padding {
+                Some((ptype, attrs)) => (
+                    format!("{{ NAME: pad_length ; TYPE: {} }},", ptype),
+                    format!(
+                        ", {{ NAME: padding ; TYPE: [u8; pad_length.size_of]{} }}",
+                        attrs
+                    ),
+                    "{ FORMAT: Packet; FIELD: padding; SEMANTIC: PADDING };",
+                ),
+                None => (String::new(), String::new(), ""),
+            };
+            parse(&format!(
+                "@SEGMENT.FORMATS
+                DEFINE Packet
+                  {{ NAME: flags   ; TYPE: u8 }},{}
+                  {{ NAME: length  ; TYPE: u16 }},{}
+                  {{ NAME: payload ; TYPE: [u8; length.size_of] }}{};
+                @SEGMENT.SEMANTICS
+                  {{ FORMAT: Packet; FIELD: length;  SEMANTIC: LENGTH }};
+                  {{ FORMAT: Packet; FIELD: payload; SEMANTIC: PAYLOAD }};
+                  {}
+                @SEGMENT.SEQUENCE
+                  {{ ROLE: CLIENT; PHASE: DATA; FORMAT: Packet }};
+                @SEGMENT.TRAFFIC
+                  {{ FORMAT: Packet; ALIGN: {} }};",
+                ext, pad_length, padding, binding, align
+            ))
+            .unwrap()
+        };
+        let names = |psf: &Psf| -> Vec<Identifier> {
+            psf.formats[&"Packet".id()]
+                .format
+                .format
+                .fields
+                .iter()
+                .map(|f| f.name.clone())
+                .collect()
+        };
+
+        // Formats without padding get a padding length at the end of their
+        // fixed-size prefix and padding at the end.
+        let psf = packet("", None, 16);
+        assert!(psf.is_valid());
+        assert_eq!(
+            names(&psf),
+            ["flags", "length", "pad_length", "payload", "padding"].map(|n| n.id())
+        );
+        assert!(!packet("", None, 512).is_valid());
+
+        // Formats that use the names already are left without padding.
+        let psf = packet("{ NAME: padding ; TYPE: [u8; 4] },", None, 16);
+        assert_eq!(
+            names(&psf),
+            ["flags", "padding", "length", "payload"].map(|n| n.id())
+        );
+        assert!(!psf.is_valid());
+
+        // So are formats aligned after parsing.
+        let mut psf = parse(
+            "@SEGMENT.FORMATS
+            DEFINE Packet
+              { NAME: length  ; TYPE: u16 },
+              { NAME: payload ; TYPE: [u8; length.size_of] };
+            @SEGMENT.SEMANTICS
+              { FORMAT: Packet; FIELD: length;  SEMANTIC: LENGTH };
+              { FORMAT: Packet; FIELD: payload; SEMANTIC: PAYLOAD };
+            @SEGMENT.SEQUENCE
+              { ROLE: CLIENT; PHASE: DATA; FORMAT: Packet };",
+        )
+        .unwrap();
+        assert!(psf.is_valid());
+        psf.traffic_spec.alignments.insert("Packet".id(), 16);
+        assert!(!psf.is_valid());
+
+        // Padding of the format's own must hold a block less one byte, be sent
+        // as generated, and come with a message size we know in advance.
+        assert!(packet("", Some(("u8", "")), 256).is_valid());
+        assert!(!packet("", Some(("u8", "")), 512).is_valid());
+        assert!(packet("", Some(("u16", "")), 512).is_valid());
+        assert!(!packet("", Some(("u8", "; ENCODING: hex")), 16).is_valid());
+        assert!(!packet(
+            "{ NAME: ext ; TYPE: u32; PRESENT_IF: flags == 0 },",
+            Some(("u8", "")),
+            16
+        )
+        .is_valid());
+    }
+
+    #[test]
+    fn test_parse_undefined_include() {
+        let p = ProteusLiteParser::parse(Rule::format, "DEFINE Foo { NAME: bar; TYPE: Baz };")
+            .unwrap()
+            .next()
+            .unwrap();
+        assert!(parse_format(&p).is_err());
+    }
 }
@@ -15,6 +15,7 @@ impl Parse for ProteusParser {
         let psf = crate::lang::parse::implementation::parse_psf(&psf_contents)?;
         let tg = crate::lang::compiler::compile_task_graph(psf.sequence.iter());
         let tgi = TaskGraphImpl::new(tg, role, psf);
+        tgi.check()?;
         Ok(ProteusSpec::new(tgi))
     }
 }
@@ -1,6 +1,7 @@
 use crate::lang::{
     compiler::*,
     task::{Task, TaskID, TaskProvider, TaskSet},
+    types::ConstantRate,
 };
 
 // Holds the immutable part of a proteus protocol as parsed from a PSF. This is
@@ -15,6 +16,16 @@ impl ProteusSpec {
     pub fn new(task_graph: TaskGraphImpl) -> ProteusSpec {
         ProteusSpec { task_graph }
     }
+
+    /// The constant-rate shaping the protocol runs with, if any.
+    pub fn constant_rate(&self) -> Option<ConstantRate> {
+        self.task_graph.constant_rate()
+    }
+
+    /// Runs the protocol at a constant rate, overriding the PSF.
+    pub fn set_constant_rate(&mut self, rate: ConstantRate) -> anyhow::Result<()> {
+        self.task_graph.set_constant_rate(rate)
+    }
 }
 
 impl TaskProvider for ProteusSpec {
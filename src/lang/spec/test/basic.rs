@@ -15,10 +15,16 @@ impl LengthPayloadSpec {
                 Field {
                     name: "length".id(),
                     dtype: PrimitiveArray(NumericType::U16.into(), 1).into(),
+                    endianness: Endianness::Big,
+                    compression: None,
+                    encoding: None,
                 },
                 Field {
                     name: "payload".id(),
                     dtype: DynamicArray(UnaryOp::SizeOf("length".id())).into(),
+                    endianness: Endianness::Big,
+                    compression: None,
+                    encoding: None,
                 },
             ],
         }
@@ -29,6 +35,9 @@ impl LengthPayloadSpec {
             fields: vec![Field {
                 name: "length".id(),
                 dtype: PrimitiveArray(NumericType::U16.into(), 1).into(),
+                endianness: Endianness::Big,
+                compression: None,
+                encoding: None,
             }],
         }
         .into();
@@ -38,6 +47,9 @@ impl LengthPayloadSpec {
             fields: vec![Field {
                 name: "payload".id(),
                 dtype: DynamicArray(UnaryOp::SizeOf("length".id())).into(),
+                endianness: Endianness::Big,
+                compression: None,
+                encoding: None,
             }],
         }
         .into();
@@ -64,7 +76,10 @@ impl TaskProvider for LengthPayloadSpec {
         let out_task = Task {
             ins: vec![
                 ReadAppArgs {
-                    from_len: 1..u16::MAX as usize,
+                    from_len: ReadAppLength::Range(1..u16::MAX as usize),
+                    timeout: None,
+                    compression: None,
+                    encoding: None,
                     to_heap_id: "payload".id(),
                 }
                 .into(),
@@ -158,6 +173,7 @@ impl TaskProvider for LengthPayloadSpec {
                 WriteAppArgs {
                     from_msg_heap_id: "message_payload_part".id(),
                     from_field_id: "payload".id(),
+                    compression: None,
                 }
                 .into(),
             ],
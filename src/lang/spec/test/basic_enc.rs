@@ -15,18 +15,30 @@ impl EncryptedLengthPayloadSpec {
                 Field {
                     name: "length".id(),
                     dtype: PrimitiveArray(NumericType::U16.into(), 1).into(),
+                    endianness: Endianness::Big,
+                    compression: None,
+                    encoding: None,
                 },
                 Field {
                     name: "length_mac".id(),
                     dtype: PrimitiveArray(NumericType::U8.into(), 16).into(),
+                    endianness: Endianness::Big,
+                    compression: None,
+                    encoding: None,
                 },
                 Field {
                     name: "payload".id(),
                     dtype: DynamicArray(UnaryOp::SizeOf("length".id())).into(),
+                    endianness: Endianness::Big,
+                    compression: None,
+                    encoding: None,
                 },
                 Field {
                     name: "payload_mac".id(),
                     dtype: PrimitiveArray(NumericType::U8.into(), 16).into(),
+                    endianness: Endianness::Big,
+                    compression: None,
+                    encoding: None,
                 },
             ],
         }
@@ -38,10 +50,16 @@ impl EncryptedLengthPayloadSpec {
                 Field {
                     name: "length".id(),
                     dtype: PrimitiveArray(NumericType::U16.into(), 1).into(),
+                    endianness: Endianness::Big,
+                    compression: None,
+                    encoding: None,
                 },
                 Field {
                     name: "length_mac".id(),
                     dtype: PrimitiveArray(NumericType::U8.into(), 16).into(),
+                    endianness: Endianness::Big,
+                    compression: None,
+                    encoding: None,
                 },
             ],
         }
@@ -53,10 +71,16 @@ impl EncryptedLengthPayloadSpec {
                 Field {
                     name: "payload".id(),
                     dtype: DynamicArray(UnaryOp::SizeOf("length".id())).into(),
+                    endianness: Endianness::Big,
+                    compression: None,
+                    encoding: None,
                 },
                 Field {
                     name: "payload_mac".id(),
                     dtype: PrimitiveArray(NumericType::U8.into(), 16).into(),
+                    endianness: Endianness::Big,
+                    compression: None,
+                    encoding: None,
                 },
             ],
         }
@@ -90,7 +114,10 @@ impl TaskProvider for EncryptedLengthPayloadSpec {
         let out_task = Task {
             ins: vec![
                 ReadAppArgs {
-                    from_len: 1..(u16::MAX - 32) as usize,
+                    from_len: ReadAppLength::Range(1..(u16::MAX - 32) as usize),
+                    timeout: None,
+                    compression: None,
+                    encoding: None,
                     to_heap_id: "payload".id(),
                 }
                 .into(),
@@ -270,6 +297,7 @@ impl TaskProvider for EncryptedLengthPayloadSpec {
                 WriteAppArgs {
                     from_msg_heap_id: "message_payload_part".id(),
                     from_field_id: "payload".id(),
+                    compression: None,
                 }
                 .into(),
             ],
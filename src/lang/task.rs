@@ -2,7 +2,10 @@
 
 use crate::lang::{
     common::Role,
-    types::{AbstractFormat, Identifier},
+    types::{
+        AbstractFormat, AdaptivePaddingSpec, ByteEncoding, ChecksumAlgorithm, Compression,
+        Condition, Distribution, Endianness, Identifier, TextNumber, ValueConstraint, VarintType,
+    },
 };
 use std::ops::Range;
 
@@ -63,6 +66,21 @@ pub enum ReadNetLength {
     IdentifierMinus((Identifier, usize)),
     /// Amount to read specified by this range.
     Range(Range<usize>),
+    /// Read exactly one varint of this type, a piece at a time until the bytes
+    /// read so far say it is complete.
+    Varint(VarintType),
+    /// Read up to and including the first occurrence of these bytes, failing
+    /// if it does not occur within the given number of bytes.
+    Delimiter((Vec<u8>, usize)),
+}
+
+#[derive(Debug)]
+pub enum ReadAppLength {
+    /// Amount to read specified by this range.
+    Range(Range<usize>),
+    /// Read at least one byte, and at most the amount specified in this heap
+    /// variable minus the given value.
+    UpToIdentifierMinus((Identifier, usize)),
 }
 
 // Auto-generates from implementations like
@@ -71,23 +89,75 @@ pub enum ReadNetLength {
 #[enum_from::enum_from]
 #[derive(Debug)]
 pub enum Instruction {
+    CompressBytes(CompressBytesArgs),
+    ComputeChecksum(ComputeChecksumArgs),
     ComputeLength(ComputeLengthArgs),
     ConcretizeFormat(ConcretizeFormatArgs),
     CreateMessage(CreateMessageArgs),
+    DecodeBytes(DecodeBytesArgs),
+    DecodeText(DecodeTextArgs),
+    DecodeVarint(DecodeVarintArgs),
     DecryptField(DecryptFieldArgs),
+    Delay(DelayArgs),
+    EncodeBytes(EncodeBytesArgs),
+    EncodeText(EncodeTextArgs),
+    EncodeVarint(EncodeVarintArgs),
     EncryptField(EncryptFieldArgs),
+    EvalCondition(EvalConditionArgs),
+    GenPadding(GenPaddingArgs),
     GenRandomBytes(GenRandomBytesArgs),
+    GenVariable(GenVariableArgs),
     GetArrayBytes(GetArrayBytesArgs),
+    GetArrayLength(GetArrayLengthArgs),
     GetNumericValue(GetNumericValueArgs),
+    InitAdaptivePadding(InitAdaptivePaddingArgs),
     InitFixedSharedKey(InitFixedSharedKeyArgs),
+    LoadVariable(LoadVariableArgs),
+    NextSequenceNumber(NextSequenceNumberArgs),
     ReadApp(ReadAppArgs),
     ReadNet(ReadNetArgs),
+    SampleSize(SampleSizeArgs),
+    SampleValue(SampleValueArgs),
+    SaveVariable(SaveVariableArgs),
+    ScaleNumericValue(ScaleNumericValueArgs),
     SetArrayBytes(SetArrayBytesArgs),
     SetNumericValue(SetNumericValueArgs),
+    StoreBytes(StoreBytesArgs),
+    SubtractNumericValue(SubtractNumericValueArgs),
+    SumLengths(SumLengthsArgs),
+    TruncateBytes(TruncateBytesArgs),
+    VerifyChecksum(VerifyChecksumArgs),
+    VerifyFixedFields(VerifyFixedFieldsArgs),
+    VerifySequenceNumber(VerifySequenceNumberArgs),
+    VerifyValues(VerifyValuesArgs),
+    VerifyVariable(VerifyVariableArgs),
     WriteApp(WriteAppArgs),
     WriteNet(WriteNetArgs),
 }
 
+/// Compress the bytes stored on the heap at `from_heap_id` with `compression`,
+/// and store the result on the heap in `to_heap_id`. Fails if the result is
+/// longer than `compression` allows for, since the message may not hold it.
+#[derive(Debug)]
+pub struct CompressBytesArgs {
+    pub from_heap_id: Identifier,
+    pub compression: Compression,
+    pub to_heap_id: Identifier,
+}
+
+/// Compute the checksum of the `from_msg_heap_id` fields from `from_first_field_id`
+/// to `from_last_field_id`, inclusive, taking the `checksum_field_id` field as
+/// zero, and store it in `to_heap_id`.
+#[derive(Debug)]
+pub struct ComputeChecksumArgs {
+    pub from_msg_heap_id: Identifier,
+    pub from_first_field_id: Identifier,
+    pub from_last_field_id: Identifier,
+    pub algorithm: ChecksumAlgorithm,
+    pub checksum_field_id: Identifier,
+    pub to_heap_id: Identifier,
+}
+
 /// Compute the length of all `from_msg_id` fields that are ordered after
 /// `from_field_id`, and store the length in `to_heap_id`.
 #[derive(Debug)]
@@ -115,6 +185,33 @@ pub struct CreateMessageArgs {
     pub to_heap_id: Identifier,
 }
 
+/// Decode the bytes stored on the heap at `from_heap_id`, which were sent with
+/// `encoding`, and store the result on the heap in `to_heap_id`.
+#[derive(Debug)]
+pub struct DecodeBytesArgs {
+    pub from_heap_id: Identifier,
+    pub encoding: ByteEncoding,
+    pub to_heap_id: Identifier,
+}
+
+/// Decode the textual number `text` in the bytes stored on the heap at
+/// `from_heap_id`, and store the value on the heap in `to_heap_id`.
+#[derive(Debug)]
+pub struct DecodeTextArgs {
+    pub from_heap_id: Identifier,
+    pub text: TextNumber,
+    pub to_heap_id: Identifier,
+}
+
+/// Decode the varint of type `varint` in the bytes stored on the heap at
+/// `from_heap_id`, and store the value on the heap in `to_heap_id`.
+#[derive(Debug)]
+pub struct DecodeVarintArgs {
+    pub from_heap_id: Identifier,
+    pub varint: VarintType,
+    pub to_heap_id: Identifier,
+}
+
 /// TODO
 #[derive(Debug)]
 pub struct DecryptFieldArgs {
@@ -124,6 +221,40 @@ pub struct DecryptFieldArgs {
     pub to_plaintext_heap_id: Identifier,
 }
 
+/// Sample a delay in milliseconds from `from_distribution` and wait that long
+/// before executing the next network command.
+#[derive(Debug)]
+pub struct DelayArgs {
+    pub from_distribution: Distribution,
+}
+
+/// Encode the bytes stored on the heap at `from_heap_id` with `encoding`, and
+/// store the result on the heap in `to_heap_id`.
+#[derive(Debug)]
+pub struct EncodeBytesArgs {
+    pub from_heap_id: Identifier,
+    pub encoding: ByteEncoding,
+    pub to_heap_id: Identifier,
+}
+
+/// Encode the numeric value stored on the heap at `from_heap_id` as the textual
+/// number `text`, and store the bytes on the heap in `to_heap_id`.
+#[derive(Debug)]
+pub struct EncodeTextArgs {
+    pub from_heap_id: Identifier,
+    pub text: TextNumber,
+    pub to_heap_id: Identifier,
+}
+
+/// Encode the numeric value stored on the heap at `from_heap_id` as a varint of
+/// type `varint`, and store the bytes on the heap in `to_heap_id`.
+#[derive(Debug)]
+pub struct EncodeVarintArgs {
+    pub from_heap_id: Identifier,
+    pub varint: VarintType,
+    pub to_heap_id: Identifier,
+}
+
 /// TODO
 #[derive(Debug)]
 pub struct EncryptFieldArgs {
@@ -133,13 +264,53 @@ pub struct EncryptFieldArgs {
     pub to_mac_heap_id: Identifier,
 }
 
-/// TODO. Generate cryptographically insecure random bytes.
+/// Test `condition` against the numeric value stored on the heap at
+/// `from_heap_id`, and store `present_len` on the heap in `to_heap_id` if it
+/// holds, or zero otherwise. This is the size of an optional field.
+#[derive(Debug)]
+pub struct EvalConditionArgs {
+    pub from_heap_id: Identifier,
+    pub condition: Condition,
+    pub present_len: usize,
+    pub to_heap_id: Identifier,
+}
+
+/// Generate random padding bytes so that a message with `overhead` bytes of
+/// fixed-size fields plus the bytes stored on the heap at each of
+/// `from_heap_ids` grows to the size stored on the heap at
+/// `from_target_heap_id`. At most `max_len` padding bytes are generated. The
+/// padding is stored on the heap in `to_heap_id`, rounded down to a multiple of
+/// `step` bytes. If `align` is set, the padding then grows by the fewest bytes
+/// that make the message size a multiple of it.
+#[derive(Debug)]
+pub struct GenPaddingArgs {
+    pub from_target_heap_id: Identifier,
+    pub from_heap_ids: Vec<Identifier>,
+    pub overhead: usize,
+    pub max_len: usize,
+    pub step: usize,
+    pub align: Option<usize>,
+    pub to_heap_id: Identifier,
+}
+
+/// Generate cryptographically insecure random bytes, as many as sampled from
+/// `from_len`, and store them on the heap in `to_heap_id`.
 #[derive(Debug)]
 pub struct GenRandomBytesArgs {
     pub from_len: Range<usize>,
     pub to_heap_id: Identifier,
 }
 
+/// Get the bytes of the connection variable `variable_id` and store them on the
+/// heap in `to_heap_id`. Unless a field has saved the variable already, it is
+/// first set to `len` random bytes.
+#[derive(Debug)]
+pub struct GenVariableArgs {
+    pub variable_id: Identifier,
+    pub len: usize,
+    pub to_heap_id: Identifier,
+}
+
 /// Get the bytes data from the field given by `from_field_id` inside of the
 /// message stored on the heap at `from_msg_heap_id`, and store the bytes on the
 /// heap in `to_heap_id`.
@@ -150,6 +321,16 @@ pub struct GetArrayBytesArgs {
     pub to_heap_id: Identifier,
 }
 
+/// Get the size in bytes of the field given by `from_field_id` inside of the
+/// message stored on the heap at `from_msg_heap_id`, and store the size on the
+/// heap in `to_heap_id`.
+#[derive(Debug)]
+pub struct GetArrayLengthArgs {
+    pub from_msg_heap_id: Identifier,
+    pub from_field_id: Identifier,
+    pub to_heap_id: Identifier,
+}
+
 /// Get the numeric value from the field given by `from_field_id` inside of the
 /// message stored on the heap at `from_msg_heap_id`, and store the value on the
 /// heap in `to_heap_id`.
@@ -160,6 +341,14 @@ pub struct GetNumericValueArgs {
     pub to_heap_id: Identifier,
 }
 
+/// Start adaptive padding of the records we send using the histograms in
+/// `spec`. While it runs, it decides the application read timeouts instead of
+/// the `timeout` given to `ReadApp`.
+#[derive(Debug)]
+pub struct InitAdaptivePaddingArgs {
+    pub spec: AdaptivePaddingSpec,
+}
+
 /// TODO
 #[derive(Debug)]
 pub struct InitFixedSharedKeyArgs {
@@ -167,11 +356,36 @@ pub struct InitFixedSharedKeyArgs {
     pub role: Role,
 }
 
-/// Read a number of bytes given by the `from_len` range from the application
-/// and store the result on the heap in `to_heap_id`.
+/// Store the value of the connection's counter `counter_id` on the heap in
+/// `to_heap_id`, and advance the counter. It starts from zero and wraps around
+/// after `max`.
+#[derive(Debug)]
+pub struct NextSequenceNumberArgs {
+    pub counter_id: Identifier,
+    pub max: u128,
+    pub to_heap_id: Identifier,
+}
+
+/// Get the bytes of the connection variable `from_variable_id` and store them
+/// on the heap in `to_heap_id`. Blocks until a field has saved the variable.
+#[derive(Debug)]
+pub struct LoadVariableArgs {
+    pub from_variable_id: Identifier,
+    pub to_heap_id: Identifier,
+}
+
+/// Read a number of bytes given by `from_len` from the application and store
+/// the result on the heap in `to_heap_id`. If `timeout` is set, a timeout in
+/// milliseconds is sampled from it, and if the application does not produce
+/// any data before it expires then empty bytes are stored instead. If the data
+/// is sent with `compression` or `encoding`, `from_len` gives its size once
+/// compressed and encoded.
 #[derive(Debug)]
 pub struct ReadAppArgs {
-    pub from_len: Range<usize>,
+    pub from_len: ReadAppLength,
+    pub timeout: Option<Distribution>,
+    pub compression: Option<Compression>,
+    pub encoding: Option<ByteEncoding>,
     pub to_heap_id: Identifier,
 }
 
@@ -183,6 +397,15 @@ pub struct ReadNetArgs {
     pub to_heap_id: Identifier,
 }
 
+/// Sample a message size in bytes from `from_distribution`, clamp it into the
+/// `within` range, and store it on the heap in `to_heap_id`.
+#[derive(Debug)]
+pub struct SampleSizeArgs {
+    pub from_distribution: Distribution,
+    pub within: Range<usize>,
+    pub to_heap_id: Identifier,
+}
+
 /// Set the bytes stored on the heap at `from_heap_id` in the field
 /// `to_field_id` inside the message stored on the heap at `to_msg_heap_id`.
 #[derive(Debug)]
@@ -201,12 +424,133 @@ pub struct SetNumericValueArgs {
     pub to_field_id: Identifier,
 }
 
+/// Store the constant `bytes` on the heap in `to_heap_id`.
+#[derive(Debug)]
+pub struct StoreBytesArgs {
+    pub bytes: Vec<u8>,
+    pub to_heap_id: Identifier,
+}
+
+/// Subtract the numeric value stored on the heap at `minus_heap_id` from the
+/// one stored at `from_heap_id`, and store the result on the heap in
+/// `to_heap_id`. Fails if the result would be negative.
+#[derive(Debug)]
+pub struct SubtractNumericValueArgs {
+    pub from_heap_id: Identifier,
+    pub minus_heap_id: Identifier,
+    pub to_heap_id: Identifier,
+}
+
+/// Add up the sizes of the bytes stored on the heap at each of `from_heap_ids`
+/// and `plus`, count the sum in units of `unit` bytes, and store the result on
+/// the heap in `to_heap_id`. Fails if the sum is negative or is not a whole
+/// number of units.
+#[derive(Debug)]
+pub struct SumLengthsArgs {
+    pub from_heap_ids: Vec<Identifier>,
+    pub plus: isize,
+    pub unit: usize,
+    pub to_heap_id: Identifier,
+}
+
+/// Keep at most as many of the bytes stored on the heap at `from_heap_id` as the
+/// numeric value stored on the heap at `from_len_heap_id`, and store the result
+/// on the heap in `to_heap_id`.
+#[derive(Debug)]
+pub struct TruncateBytesArgs {
+    pub from_heap_id: Identifier,
+    pub from_len_heap_id: Identifier,
+    pub to_heap_id: Identifier,
+}
+
+/// Pick a value at random from those `from_constraint` allows, and store it on
+/// the heap in `to_heap_id`.
+#[derive(Debug)]
+pub struct SampleValueArgs {
+    pub from_constraint: ValueConstraint,
+    pub to_heap_id: Identifier,
+}
+
+/// Keep the bytes of the field `from_field_id` in the message stored at
+/// `from_msg_heap_id` in the connection variable `to_variable_id`, replacing
+/// any earlier value.
+#[derive(Debug)]
+pub struct SaveVariableArgs {
+    pub from_msg_heap_id: Identifier,
+    pub from_field_id: Identifier,
+    pub to_variable_id: Identifier,
+}
+
+/// Multiply the numeric value stored on the heap at `from_heap_id` by `scale`,
+/// add `bias`, and store the result on the heap in `to_heap_id`. Fails if the
+/// result would be negative.
+#[derive(Debug)]
+pub struct ScaleNumericValueArgs {
+    pub from_heap_id: Identifier,
+    pub scale: usize,
+    pub bias: isize,
+    pub to_heap_id: Identifier,
+}
+
+/// Compute the checksum of the bytes stored on the heap at each of
+/// `from_heap_ids`, in order, taking those at `checksum_heap_id` as zero. Fails
+/// unless it equals the number held in the bytes at `checksum_heap_id`.
+#[derive(Debug)]
+pub struct VerifyChecksumArgs {
+    pub from_heap_ids: Vec<Identifier>,
+    pub algorithm: ChecksumAlgorithm,
+    pub checksum_heap_id: Identifier,
+    pub endianness: Endianness,
+}
+
+/// Fails unless each of the `fixed_fields` in the message stored at
+/// `from_msg_heap_id` holds its value, followed by zeros if the field is
+/// larger. The values are compared in constant time if `constant_time` is set.
+#[derive(Debug)]
+pub struct VerifyFixedFieldsArgs {
+    pub from_msg_heap_id: Identifier,
+    pub fixed_fields: Vec<(Identifier, Vec<u8>)>,
+    pub constant_time: bool,
+}
+
+/// Fails unless the field `from_field_id` in the message stored at
+/// `from_msg_heap_id` holds the value of the connection's counter `counter_id`,
+/// and then advances the counter as in `NextSequenceNumberArgs`.
+#[derive(Debug)]
+pub struct VerifySequenceNumberArgs {
+    pub from_msg_heap_id: Identifier,
+    pub from_field_id: Identifier,
+    pub counter_id: Identifier,
+    pub max: u128,
+}
+
+/// Fails unless each of the `constraints` allows the value of its field in the
+/// message stored at `from_msg_heap_id`, and every bool and char in the message
+/// is valid. Fields the message does not hold are skipped.
+#[derive(Debug)]
+pub struct VerifyValuesArgs {
+    pub from_msg_heap_id: Identifier,
+    pub constraints: Vec<(Identifier, ValueConstraint)>,
+}
+
+/// Fails unless the field `from_field_id` in the message stored at
+/// `from_msg_heap_id` holds the bytes of the connection variable `variable_id`.
+#[derive(Debug)]
+pub struct VerifyVariableArgs {
+    pub from_msg_heap_id: Identifier,
+    pub from_field_id: Identifier,
+    pub variable_id: Identifier,
+}
+
 /// Write the bytes from the field `from_field_id` inside of the message stored
-/// at `from_msg_heap_id` on the heap to the application.
+/// at `from_msg_heap_id` on the heap to the application. If the field was sent
+/// with `compression`, it is decompressed first, and fails if it would grow
+/// beyond the given number of bytes.
 #[derive(Debug)]
 pub struct WriteAppArgs {
     pub from_msg_heap_id: Identifier,
     pub from_field_id: Identifier, // usually payload field
+    pub compression: Option<(Compression, usize)>,
 }
 
 /// Write the bytes from the message stored on the heap at `from_msg_heap_id` to
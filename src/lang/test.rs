@@ -33,12 +33,30 @@ impl Network {
         };
     }
 
-    fn recv(&mut self, role: &Role, range: &Range<usize>) -> Result<Bytes, ()> {
+    fn recv(
+        &mut self,
+        role: &Role,
+        range: &Range<usize>,
+        delimiter: &Option<Vec<u8>>,
+    ) -> Result<Bytes, ()> {
         let net_src = match role {
             Role::Client => &self.server_to_client,
             Role::Server => &self.client_to_server,
         };
 
+        // Read up to the end of the delimiter, if it has arrived.
+        let range = match delimiter {
+            Some(delimiter) => {
+                let end = net_src
+                    .windows(delimiter.len())
+                    .position(|w| w == delimiter)
+                    .ok_or(())?
+                    + delimiter.len();
+                end..end + 1
+            }
+            None => range.clone(),
+        };
+
         match net_src.remaining() >= range.start {
             true => {
                 let mut src = net_src.clone().take(range.end - 1);
@@ -123,6 +141,8 @@ impl Host {
                         }
                     },
                     NetOpOut::SendNet(args) => net.send(&self.role, args.bytes),
+                    // The simulated network has no notion of time.
+                    NetOpOut::Sleep(_) => {}
                     NetOpOut::_Close => todo!(),
                     NetOpOut::Error(e) => panic!("NetOpOut error {}", e),
                 };
@@ -141,13 +161,15 @@ impl Host {
         {
             Ok(op) => {
                 match op {
-                    NetOpIn::RecvNet(args) => match net.recv(&self.role, &args.len) {
-                        Ok(bytes) => self.interpreter.store_in(args.addr, bytes),
-                        Err(_) => {
-                            self.next_op_in = Some(NetOpIn::RecvNet(args));
-                            return Err(());
+                    NetOpIn::RecvNet(args) => {
+                        match net.recv(&self.role, &args.len, &args.delimiter) {
+                            Ok(bytes) => self.interpreter.store_in(args.addr, bytes),
+                            Err(_) => {
+                                self.next_op_in = Some(NetOpIn::RecvNet(args));
+                                return Err(());
+                            }
                         }
-                    },
+                    }
                     NetOpIn::SendApp(args) => self.write_app(args.bytes),
                     NetOpIn::_Close => todo!(),
                     NetOpIn::Error(e) => panic!("NetOpIn error {}", e),
@@ -272,3 +294,108 @@ fn integration_psf_basic_enc() {
 fn integration_psf_padded_enc() {
     integration_with_psf(&"examples/psf/shadowsocks_padded.psf");
 }
+
+#[test]
+fn integration_psf_shaped_enc() {
+    integration_with_psf("examples/psf/shaped.psf");
+}
+
+#[test]
+fn integration_psf_adaptive_enc() {
+    integration_with_psf("examples/psf/adaptive.psf");
+}
+
+#[test]
+fn integration_psf_typed() {
+    integration_with_psf("examples/psf/typed.psf");
+}
+
+#[test]
+fn integration_psf_varint() {
+    integration_with_psf("examples/psf/varint.psf");
+}
+
+#[test]
+fn integration_psf_bits() {
+    integration_with_psf("examples/psf/bits.psf");
+}
+
+#[test]
+fn integration_psf_length_expr() {
+    integration_with_psf("examples/psf/length_expr.psf");
+}
+
+#[test]
+fn integration_psf_multi_array() {
+    integration_with_psf("examples/psf/multi_array.psf");
+}
+
+#[test]
+fn integration_psf_nested() {
+    integration_with_psf("examples/psf/nested.psf");
+}
+
+#[test]
+fn integration_psf_record_list() {
+    integration_with_psf("examples/psf/record_list.psf");
+}
+
+#[test]
+fn integration_psf_optional() {
+    integration_with_psf("examples/psf/optional.psf");
+}
+
+#[test]
+fn integration_psf_delimited() {
+    integration_with_psf("examples/psf/delimited.psf");
+}
+
+#[test]
+fn integration_psf_text_length() {
+    integration_with_psf("examples/psf/text_length.psf");
+}
+
+#[test]
+fn integration_psf_encoded() {
+    integration_with_psf("examples/psf/encoded.psf");
+}
+
+#[test]
+fn integration_psf_checksum() {
+    integration_with_psf("examples/psf/checksum.psf");
+}
+
+#[test]
+fn integration_psf_chained_checksum() {
+    integration_with_psf("examples/psf/chained_checksum.psf");
+}
+
+#[test]
+fn integration_psf_fixed_bytes() {
+    integration_with_psf("examples/psf/fixed_bytes.psf");
+}
+
+#[test]
+fn integration_psf_constrained() {
+    integration_with_psf("examples/psf/constrained.psf");
+}
+
+#[test]
+fn integration_psf_sequenced() {
+    integration_with_psf("examples/psf/sequenced.psf");
+}
+
+#[test]
+fn integration_psf_session() {
+    integration_with_psf("examples/psf/session.psf");
+}
+
+#[test]
+fn integration_psf_compressed() {
+    integration_with_psf("examples/psf/compressed.psf");
+}
+
+#[test]
+fn integration_psf_aligned() {
+    integration_with_psf("examples/psf/aligned.psf");
+}
@@ -1,8 +1,14 @@
 #![allow(dead_code)]
 
 use crate::lang::common::Role;
+use rand::{
+    distributions::{Distribution as _, WeightedIndex},
+    Rng,
+};
 use std::collections::hash_map::HashMap;
 use std::convert::{From, TryFrom};
+use std::io::{Read, Write};
+use std::ops::Range;
 use std::str::FromStr;
 
 pub trait StaticallySized {
@@ -206,6 +212,486 @@ impl From<NumericType> for PrimitiveType {
     }
 }
 
+/// An unsigned integer encoding whose size on the wire depends on its value.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum VarintType {
+    /// QUIC variable-length integers (RFC 9000): the top two bits of the
+    /// first byte give the size as 1, 2, 4, or 8 bytes.
+    Quic,
+    /// Unsigned LEB128, as used by protobuf: 7 bits per byte, where the top
+    /// bit of each byte is set if more bytes follow.
+    Leb128,
+}
+
+impl VarintType {
+    /// The largest number of bytes a single varint may occupy.
+    const LEB128_MAX_LEN: usize = 10;
+
+    pub fn max_value(&self) -> u64 {
+        match self {
+            VarintType::Quic => (1 << 62) - 1,
+            VarintType::Leb128 => u64::MAX,
+        }
+    }
+
+    /// Returns the number of bytes needed to encode `value`.
+    pub fn encoded_len(&self, value: u64) -> usize {
+        match self {
+            VarintType::Quic => match value {
+                0..=0x3f => 1,
+                0x40..=0x3fff => 2,
+                0x4000..=0x3fff_ffff => 4,
+                _ => 8,
+            },
+            VarintType::Leb128 => {
+                std::cmp::max(1, (64 - value.leading_zeros() as usize).div_ceil(7))
+            }
+        }
+    }
+
+    /// Encodes `value` in as few bytes as possible, or returns `None` if it is
+    /// too large for this encoding.
+    pub fn encode(&self, value: u64) -> Option<Vec<u8>> {
+        if value > self.max_value() {
+            return None;
+        }
+
+        let len = self.encoded_len(value);
+
+        match self {
+            VarintType::Quic => {
+                let mut bytes = value.to_be_bytes()[8 - len..].to_vec();
+                bytes[0] |= (len.trailing_zeros() as u8) << 6;
+                Some(bytes)
+            }
+            VarintType::Leb128 => Some(
+                (0..len)
+                    .map(|i| {
+                        let more = if i + 1 < len { 0x80 } else { 0 };
+                        ((value >> (7 * i)) & 0x7f) as u8 | more
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Returns how many more bytes must follow `prefix` to complete a varint,
+    /// or `None` if `prefix` cannot start a valid varint.
+    pub fn remaining_len(&self, prefix: &[u8]) -> Option<usize> {
+        match self {
+            VarintType::Quic => match prefix.first() {
+                Some(first) => (1usize << (first >> 6)).checked_sub(prefix.len()),
+                None => Some(1),
+            },
+            VarintType::Leb128 => {
+                let done = prefix.last().is_some_and(|b| b & 0x80 == 0);
+                match (done, prefix.len()) {
+                    (true, _) => Some(0),
+                    (false, len) if len < Self::LEB128_MAX_LEN => Some(1),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    /// Decodes a complete varint, or returns `None` if `bytes` holds anything
+    /// other than exactly one valid varint.
+    pub fn decode(&self, bytes: &[u8]) -> Option<u64> {
+        if bytes.is_empty() || self.remaining_len(bytes)? != 0 {
+            return None;
+        }
+
+        match self {
+            VarintType::Quic => Some(
+                bytes[1..]
+                    .iter()
+                    .fold((bytes[0] & 0x3f) as u64, |acc, b| (acc << 8) | *b as u64),
+            ),
+            VarintType::Leb128 => {
+                if bytes[..bytes.len() - 1].iter().any(|b| b & 0x80 == 0) {
+                    return None;
+                }
+                bytes.iter().enumerate().try_fold(0u64, |acc, (i, b)| {
+                    let bits = (*b & 0x7f) as u64;
+                    // Reject bits that would be shifted out of a u64.
+                    match i < 9 || bits <= 1 {
+                        true => Some(acc | (bits << (7 * i))),
+                        false => None,
+                    }
+                })
+            }
+        }
+    }
+}
+
+impl FromStr for VarintType {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, ParseError> {
+        match s {
+            "quic_varint" => Ok(VarintType::Quic),
+            "leb128" => Ok(VarintType::Leb128),
+            _ => Err(ParseError {}),
+        }
+    }
+}
+
+/// A varint field. Its size is unknown until we know the value it holds.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Varint(pub VarintType, pub Option<usize>);
+
+impl MaybeSized for Varint {
+    fn maybe_size_of(&self) -> Option<usize> {
+        self.1
+    }
+}
+
+/// The base in which a textual number is written.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TextRadix {
+    Decimal,
+    /// Written in lowercase, but either case is accepted on receive.
+    Hex,
+}
+
+impl TextRadix {
+    pub fn radix(&self) -> u32 {
+        match self {
+            TextRadix::Decimal => 10,
+            TextRadix::Hex => 16,
+        }
+    }
+}
+
+impl FromStr for TextRadix {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, ParseError> {
+        match s {
+            "dec" => Ok(TextRadix::Decimal),
+            "hex" => Ok(TextRadix::Hex),
+            _ => Err(ParseError {}),
+        }
+    }
+}
+
+/// Where the digits of a textual number end.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TextEnd {
+    /// Exactly this many digits, padded with leading zeros.
+    Width(usize),
+    /// As many digits as the value needs, followed by this delimiter, which
+    /// is part of the field like it is for delimited arrays.
+    Until(Vec<u8>),
+}
+
+/// An unsigned integer written as ASCII digits, like the `1234` of an HTTP
+/// `Content-Length: 1234` header. When it ends with a delimiter, its size is
+/// unknown until we know the value it holds.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextNumber {
+    pub radix: TextRadix,
+    pub end: TextEnd,
+    pub size: Option<usize>,
+}
+
+impl TextNumber {
+    /// The largest value the field can hold.
+    pub fn max_value(&self) -> u128 {
+        match self.end {
+            TextEnd::Width(width) => u128::from(self.radix.radix())
+                .checked_pow(width as u32)
+                .map_or(u128::MAX, |x| x - 1),
+            TextEnd::Until(_) => u128::MAX,
+        }
+    }
+
+    /// Writes `value` out as the bytes of the field, or returns `None` if it
+    /// does not fit.
+    pub fn encode(&self, value: u128) -> Option<Vec<u8>> {
+        let digits = match self.radix {
+            TextRadix::Decimal => format!("{}", value),
+            TextRadix::Hex => format!("{:x}", value),
+        };
+
+        match &self.end {
+            TextEnd::Width(width) if digits.len() <= *width => {
+                Some(format!("{:0>width$}", digits, width = width).into_bytes())
+            }
+            TextEnd::Width(_) => None,
+            TextEnd::Until(delimiter) => Some([digits.as_bytes(), delimiter].concat()),
+        }
+    }
+
+    /// Reads the value out of the bytes of the field, or returns `None` if
+    /// they hold anything but the digits and delimiter we expect.
+    pub fn decode(&self, bytes: &[u8]) -> Option<u128> {
+        let digits = match &self.end {
+            TextEnd::Width(width) if bytes.len() == *width => bytes,
+            TextEnd::Width(_) => return None,
+            TextEnd::Until(delimiter) => bytes.strip_suffix(delimiter.as_slice())?,
+        };
+
+        let radix = self.radix.radix();
+
+        // Check the digits ourselves since `from_str_radix` also accepts a sign.
+        if digits.is_empty() || !digits.iter().all(|d| (*d as char).is_digit(radix)) {
+            return None;
+        }
+
+        u128::from_str_radix(std::str::from_utf8(digits).ok()?, radix).ok()
+    }
+}
+
+impl MaybeSized for TextNumber {
+    fn maybe_size_of(&self) -> Option<usize> {
+        match self.end {
+            TextEnd::Width(width) => Some(width),
+            TextEnd::Until(_) => self.size,
+        }
+    }
+}
+
+/// A printable encoding of the bytes of a field on the wire (RFC 4648).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ByteEncoding {
+    Base64,
+    Base32,
+    /// Written in lowercase, but either case is accepted on receive.
+    Hex,
+}
+
+impl ByteEncoding {
+    fn alphabet(&self) -> &'static [u8] {
+        match self {
+            ByteEncoding::Base64 => {
+                b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/"
+            }
+            ByteEncoding::Base32 => b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567",
+            ByteEncoding::Hex => b"0123456789abcdef",
+        }
+    }
+
+    /// Each symbol holds this many bits.
+    fn symbol_bits(&self) -> usize {
+        self.alphabet().len().trailing_zeros() as usize
+    }
+
+    /// Gets the number of bytes and symbols in each block; a partial block at
+    /// the end is padded out with `=`.
+    fn block(&self) -> (usize, usize) {
+        match self {
+            ByteEncoding::Base64 => (3, 4),
+            ByteEncoding::Base32 => (5, 8),
+            ByteEncoding::Hex => (1, 2),
+        }
+    }
+
+    /// Returns the number of bytes needed to encode `len` bytes.
+    pub fn encoded_len(&self, len: usize) -> usize {
+        let (bytes, symbols) = self.block();
+        len.div_ceil(bytes) * symbols
+    }
+
+    /// Returns the largest number of bytes that encode into at most `len`.
+    pub fn max_decoded_len(&self, len: usize) -> usize {
+        let (bytes, symbols) = self.block();
+        len / symbols * bytes
+    }
+
+    pub fn encode(&self, data: &[u8]) -> Vec<u8> {
+        let alphabet = self.alphabet();
+        let nbits = self.symbol_bits();
+        let mut out = Vec::with_capacity(self.encoded_len(data.len()));
+
+        let (mut acc, mut acc_bits) = (0u32, 0);
+        for b in data {
+            acc = (acc << 8) | *b as u32;
+            acc_bits += 8;
+            while acc_bits >= nbits {
+                acc_bits -= nbits;
+                out.push(alphabet[((acc >> acc_bits) & ((1 << nbits) - 1)) as usize]);
+            }
+        }
+        if acc_bits > 0 {
+            out.push(alphabet[((acc << (nbits - acc_bits)) & ((1 << nbits) - 1)) as usize]);
+        }
+
+        out.resize(self.encoded_len(data.len()), b'=');
+        out
+    }
+
+    /// Decodes `data`, or returns `None` unless it is exactly what `encode`
+    /// gives for some bytes.
+    pub fn decode(&self, data: &[u8]) -> Option<Vec<u8>> {
+        let data = match self {
+            ByteEncoding::Hex => data.to_ascii_lowercase(),
+            _ => data.to_vec(),
+        };
+        let alphabet = self.alphabet();
+        let nbits = self.symbol_bits();
+        let mut out = Vec::with_capacity(self.max_decoded_len(data.len()));
+
+        let (mut acc, mut acc_bits) = (0u32, 0);
+        for symbol in data.iter().take_while(|s| **s != b'=') {
+            let value = alphabet.iter().position(|a| a == symbol)? as u32;
+            acc = (acc << nbits) | value;
+            acc_bits += nbits;
+            if acc_bits >= 8 {
+                acc_bits -= 8;
+                out.push((acc >> acc_bits) as u8);
+            }
+        }
+
+        // Rules out bad padding and stray bits in the last symbol.
+        match self.encode(&out) == data {
+            true => Some(out),
+            false => None,
+        }
+    }
+}
+
+impl FromStr for ByteEncoding {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, ParseError> {
+        match s {
+            "base64" => Ok(ByteEncoding::Base64),
+            "base32" => Ok(ByteEncoding::Base32),
+            "hex" => Ok(ByteEncoding::Hex),
+            _ => Err(ParseError {}),
+        }
+    }
+}
+
+/// A compression of the bytes of a field, applied before it is encrypted.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Compression {
+    /// Raw DEFLATE (RFC 1951), without a zlib or gzip wrapper.
+    Deflate,
+}
+
+impl Compression {
+    /// Returns the most bytes that compressing `len` bytes can give. Data that
+    /// does not compress is sent in stored blocks, each adding a 5-byte header
+    /// and holding no fewer than 16383 bytes.
+    pub fn max_compressed_len(&self, len: usize) -> usize {
+        match self {
+            Compression::Deflate => len + 5 * (len / 16383 + 1),
+        }
+    }
+
+    /// Returns the largest number of bytes sure to compress into at most `len`.
+    pub fn max_decompressed_len(&self, len: usize) -> usize {
+        let mut max = len.saturating_sub(self.max_compressed_len(len) - len);
+        while self.max_compressed_len(max + 1) <= len {
+            max += 1;
+        }
+        max
+    }
+
+    pub fn compress(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Compression::Deflate => {
+                let mut encoder = flate2::write::DeflateEncoder::new(
+                    Vec::with_capacity(self.max_compressed_len(data.len())),
+                    flate2::Compression::default(),
+                );
+                // Unwraps OK: writing to a vector cannot fail.
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+        }
+    }
+
+    /// Decompresses `data`, or returns `None` if it is malformed or would grow
+    /// beyond `max_len` bytes.
+    pub fn decompress(&self, data: &[u8], max_len: usize) -> Option<Vec<u8>> {
+        let mut out = vec![];
+        match self {
+            Compression::Deflate => flate2::read::DeflateDecoder::new(data)
+                .take(max_len as u64 + 1)
+                .read_to_end(&mut out)
+                .ok()?,
+        };
+        (out.len() <= max_len).then_some(out)
+    }
+}
+
+impl FromStr for Compression {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, ParseError> {
+        match s {
+            "deflate" => Ok(Compression::Deflate),
+            _ => Err(ParseError {}),
+        }
+    }
+}
+
+/// A single value of a primitive type, as stored in a message field.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PrimitiveValue {
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    U64(u64),
+    I8(i8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    Bool(bool),
+    Char(char),
+}
+
+impl PrimitiveValue {
+    /// Converts an integer to a value of the given numeric type, failing if it
+    /// does not fit.
+    pub fn from_integer(ntype: NumericType, value: i128) -> Result<Self, DowncastError> {
+        let value = match ntype {
+            NumericType::U8 => value.try_into().map(PrimitiveValue::U8),
+            NumericType::U16 => value.try_into().map(PrimitiveValue::U16),
+            NumericType::U32 => value.try_into().map(PrimitiveValue::U32),
+            NumericType::U64 => value.try_into().map(PrimitiveValue::U64),
+            NumericType::I8 => value.try_into().map(PrimitiveValue::I8),
+            NumericType::I16 => value.try_into().map(PrimitiveValue::I16),
+            NumericType::I32 => value.try_into().map(PrimitiveValue::I32),
+            NumericType::I64 => value.try_into().map(PrimitiveValue::I64),
+        };
+        value.map_err(|_| DowncastError {})
+    }
+
+    /// Returns the value as an integer, if it has a numeric type.
+    pub fn as_integer(&self) -> Option<i128> {
+        match *self {
+            PrimitiveValue::U8(x) => Some(x.into()),
+            PrimitiveValue::U16(x) => Some(x.into()),
+            PrimitiveValue::U32(x) => Some(x.into()),
+            PrimitiveValue::U64(x) => Some(x.into()),
+            PrimitiveValue::I8(x) => Some(x.into()),
+            PrimitiveValue::I16(x) => Some(x.into()),
+            PrimitiveValue::I32(x) => Some(x.into()),
+            PrimitiveValue::I64(x) => Some(x.into()),
+            PrimitiveValue::Bool(_) | PrimitiveValue::Char(_) => None,
+        }
+    }
+
+    pub fn primitive_type(&self) -> PrimitiveType {
+        match self {
+            PrimitiveValue::U8(_) => NumericType::U8.into(),
+            PrimitiveValue::U16(_) => NumericType::U16.into(),
+            PrimitiveValue::U32(_) => NumericType::U32.into(),
+            PrimitiveValue::U64(_) => NumericType::U64.into(),
+            PrimitiveValue::I8(_) => NumericType::I8.into(),
+            PrimitiveValue::I16(_) => NumericType::I16.into(),
+            PrimitiveValue::I32(_) => NumericType::I32.into(),
+            PrimitiveValue::I64(_) => NumericType::I64.into(),
+            PrimitiveValue::Bool(_) => PrimitiveType::Bool,
+            PrimitiveValue::Char(_) => PrimitiveType::Char,
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum CompoundType {
     // Non-primitive types needed for the interpreter
@@ -230,6 +716,14 @@ impl FromStr for Identifier {
     }
 }
 
+impl Identifier {
+    /// Gets the name of this field once its format is included in another
+    /// format as the field `prefix`, e.g. `header.length`.
+    pub fn qualified(&self, prefix: &Identifier) -> Identifier {
+        Identifier(format!("{}.{}", prefix.0, self.0))
+    }
+}
+
 pub trait ToIdentifier {
     fn id(&self) -> Identifier;
 }
@@ -243,6 +737,48 @@ impl ToIdentifier for &str {
 #[derive(Clone, Debug, PartialEq)]
 pub enum UnaryOp {
     SizeOf(Identifier),
+    Expr(LengthExpr),
+    /// The array runs up to and including the first occurrence of these
+    /// bytes, like a CRLF-terminated line. It has no length field.
+    Until(Vec<u8>),
+}
+
+/// The size in bytes of a dynamic array as a function of the value of its
+/// length field, i.e. `field * scale + bias`. Unlike `size_of`, which counts
+/// every byte the length field covers, the expression sizes the array alone.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LengthExpr {
+    pub field: Identifier,
+    pub scale: usize,
+    pub bias: isize,
+}
+
+impl LengthExpr {
+    /// Gets the size of the array for the given length field value, if the
+    /// result is not negative.
+    pub fn array_len(&self, value: u128) -> Option<usize> {
+        let len = i128::try_from(value)
+            .ok()?
+            .checked_mul(self.scale as i128)?
+            .checked_add(self.bias as i128)?;
+        usize::try_from(len).ok()
+    }
+
+    /// Gets the length field value describing an array of `len` bytes, if any
+    /// value does.
+    pub fn field_value(&self, len: usize) -> Option<u128> {
+        let scaled = len as i128 - self.bias as i128;
+        match scaled >= 0 && scaled % self.scale as i128 == 0 {
+            true => Some((scaled / self.scale as i128) as u128),
+            false => None,
+        }
+    }
+
+    /// Returns true if the array may be empty and every multiple of `scale`
+    /// bytes is a valid size, which is what we need to hold app data.
+    pub fn fits_any_whole_units(&self) -> bool {
+        self.scale > 0 && self.field_value(0).is_some()
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -275,6 +811,24 @@ impl DynamicArray {
     pub fn try_get_length_field(&self) -> Option<Identifier> {
         match &self.0 {
             UnaryOp::SizeOf(id) => Some(id.clone()),
+            UnaryOp::Expr(expr) => Some(expr.field.clone()),
+            UnaryOp::Until(_) => None,
+        }
+    }
+
+    /// Gets the delimiter ending this array, if it has one.
+    pub fn try_get_delimiter(&self) -> Option<Vec<u8>> {
+        match &self.0 {
+            UnaryOp::Until(delimiter) => Some(delimiter.clone()),
+            _ => None,
+        }
+    }
+
+    /// Gets the expression sizing this array, if it has one.
+    pub fn try_get_length_expr(&self) -> Option<LengthExpr> {
+        match &self.0 {
+            UnaryOp::Expr(expr) => Some(expr.clone()),
+            _ => None,
         }
     }
 }
@@ -286,10 +840,177 @@ impl MaybeSized for DynamicArray {
     }
 }
 
+/// How many records a list holds.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ListLength {
+    /// The field counts the records, which must all have the same size so the
+    /// size of the list is known from the count alone.
+    Count(Identifier),
+    /// The field counts the bytes of the list, i.e. `field.size_of`.
+    Bytes(Identifier),
+}
+
+/// A list of records of a previously defined format, laid out back to back.
+/// Like a varint, its size is unknown until we know what it holds.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordList {
+    pub record: Format,
+    pub length: ListLength,
+    pub size: Option<usize>,
+}
+
+impl RecordList {
+    pub fn length_field(&self) -> &Identifier {
+        match &self.length {
+            ListLength::Count(id) | ListLength::Bytes(id) => id,
+        }
+    }
+
+    /// Gets the byte array holding the list on the wire, which is sized by
+    /// the same length field.
+    pub fn try_as_dynamic(&self) -> Option<DynamicArray> {
+        match &self.length {
+            ListLength::Count(id) => Some(DynamicArray(UnaryOp::Expr(LengthExpr {
+                field: id.clone(),
+                scale: self.record.maybe_size_of()?,
+                bias: 0,
+            }))),
+            ListLength::Bytes(id) => Some(DynamicArray(UnaryOp::SizeOf(id.clone()))),
+        }
+    }
+}
+
+impl MaybeSized for RecordList {
+    fn maybe_size_of(&self) -> Option<usize> {
+        self.size
+    }
+}
+
+/// How a condition tests the value of the field it refers to.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ConditionOp {
+    NonZero,
+    And(u128),
+    Equals(u128),
+}
+
+/// A test on the value of an earlier field, e.g. `flags & 0x01`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Condition {
+    pub field: Identifier,
+    pub op: ConditionOp,
+}
+
+impl Condition {
+    pub fn holds(&self, value: u128) -> bool {
+        match self.op {
+            ConditionOp::NonZero => value != 0,
+            ConditionOp::And(mask) => value & mask != 0,
+            ConditionOp::Equals(x) => value == x,
+        }
+    }
+}
+
+/// A field only present when its condition holds. Its size is unknown until
+/// we know whether it is present, and is zero when it is not.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Conditional {
+    pub array: Box<Array>,
+    pub condition: Condition,
+    pub present: Option<bool>,
+}
+
+impl MaybeSized for Conditional {
+    fn maybe_size_of(&self) -> Option<usize> {
+        match self.present? {
+            true => self.array.maybe_size_of(),
+            false => Some(0),
+        }
+    }
+}
+
+/// A run of consecutive bit fields packed into whole bytes, most significant
+/// bit first. Members are (name, width in bits) pairs in wire order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BitGroup(pub Vec<(Identifier, usize)>);
+
+impl BitGroup {
+    /// The field name of the group, which joins its member names with `|` so
+    /// it can never clash with a field declared in a PSF.
+    pub fn group_name(&self) -> Identifier {
+        Identifier(
+            self.0
+                .iter()
+                .map(|(name, _)| name.0.as_str())
+                .collect::<Vec<_>>()
+                .join("|"),
+        )
+    }
+
+    pub fn nbits(&self) -> usize {
+        self.0.iter().map(|(_, width)| width).sum()
+    }
+
+    /// Groups must fill whole bytes, and each member must fit in a u64.
+    pub fn is_valid(&self) -> bool {
+        self.nbits().is_multiple_of(8) && self.0.iter().all(|(_, width)| (1..=64).contains(width))
+    }
+
+    /// Gets the bit offset from the start of the group and the width of the
+    /// given member.
+    pub fn try_get_member(&self, name: &Identifier) -> Option<(usize, usize)> {
+        let mut offset = 0;
+
+        for (member, width) in &self.0 {
+            if member == name {
+                return Some((offset, *width));
+            }
+            offset += width;
+        }
+
+        None
+    }
+}
+
+impl StaticallySized for BitGroup {
+    fn size_of(&self) -> usize {
+        self.nbits().div_ceil(8)
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum Array {
     Primitive(PrimitiveArray),
     Dynamic(DynamicArray),
+    Varint(Varint),
+    Bits(BitGroup),
+    List(RecordList),
+    Conditional(Conditional),
+    Text(TextNumber),
+}
+
+impl Array {
+    /// Gets the byte array this field is on the wire, if its size depends on
+    /// a length field.
+    pub fn try_as_dynamic(&self) -> Option<DynamicArray> {
+        match self {
+            Array::Dynamic(array) => Some(array.clone()),
+            Array::List(list) if list.size.is_none() => list.try_as_dynamic(),
+            _ => None,
+        }
+    }
+
+    /// Gets the delimiter ending this field, if it has one.
+    pub fn try_get_delimiter(&self) -> Option<Vec<u8>> {
+        match self {
+            Array::Dynamic(array) => array.try_get_delimiter(),
+            Array::Text(TextNumber {
+                end: TextEnd::Until(delimiter),
+                ..
+            }) => Some(delimiter.clone()),
+            _ => None,
+        }
+    }
 }
 
 impl TryFrom<Array> for PrimitiveArray {
@@ -321,6 +1042,11 @@ impl MaybeSized for Array {
         match *self {
             Array::Primitive(ref a) => a.maybe_size_of(),
             Array::Dynamic(ref a) => a.maybe_size_of(),
+            Array::Varint(ref a) => a.maybe_size_of(),
+            Array::Bits(ref a) => a.maybe_size_of(),
+            Array::List(ref a) => a.maybe_size_of(),
+            Array::Conditional(ref a) => a.maybe_size_of(),
+            Array::Text(ref a) => a.maybe_size_of(),
         }
     }
 }
@@ -335,12 +1061,132 @@ impl From<DynamicArray> for Array {
     fn from(item: DynamicArray) -> Array {
         Array::Dynamic(item)
     }
-}
+}
+
+impl From<Varint> for Array {
+    fn from(item: Varint) -> Array {
+        Array::Varint(item)
+    }
+}
+
+impl From<BitGroup> for Array {
+    fn from(item: BitGroup) -> Array {
+        Array::Bits(item)
+    }
+}
+
+impl From<RecordList> for Array {
+    fn from(item: RecordList) -> Array {
+        Array::List(item)
+    }
+}
+
+impl From<Conditional> for Array {
+    fn from(item: Conditional) -> Array {
+        Array::Conditional(item)
+    }
+}
+
+impl From<TextNumber> for Array {
+    fn from(item: TextNumber) -> Array {
+        Array::Text(item)
+    }
+}
+
+/// The byte order of the numeric values stored in a field.
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub enum Endianness {
+    #[default]
+    Big,
+    Little,
+}
+
+impl FromStr for Endianness {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, ParseError> {
+        match s {
+            "be" => Ok(Endianness::Big),
+            "le" => Ok(Endianness::Little),
+            _ => Err(ParseError {}),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Field {
+    pub name: Identifier,
+    pub dtype: Array,
+    pub endianness: Endianness,
+    /// The field holds raw bytes, but is sent compressed.
+    pub compression: Option<Compression>,
+    /// The field holds raw bytes, but is sent with this encoding.
+    pub encoding: Option<ByteEncoding>,
+}
+
+impl Field {
+    /// Gets this field as it appears once its format is included in another
+    /// format as the field `prefix`, qualifying every name it refers to.
+    pub fn qualified(&self, prefix: &Identifier) -> Field {
+        let dtype = match &self.dtype {
+            Array::Dynamic(DynamicArray(UnaryOp::SizeOf(id))) => {
+                DynamicArray(UnaryOp::SizeOf(id.qualified(prefix))).into()
+            }
+            Array::Dynamic(DynamicArray(UnaryOp::Expr(expr))) => {
+                DynamicArray(UnaryOp::Expr(LengthExpr {
+                    field: expr.field.qualified(prefix),
+                    ..expr.clone()
+                }))
+                .into()
+            }
+            Array::Bits(BitGroup(members)) => BitGroup(
+                members
+                    .iter()
+                    .map(|(name, width)| (name.qualified(prefix), *width))
+                    .collect(),
+            )
+            .into(),
+            Array::List(list) => RecordList {
+                length: match &list.length {
+                    ListLength::Count(id) => ListLength::Count(id.qualified(prefix)),
+                    ListLength::Bytes(id) => ListLength::Bytes(id.qualified(prefix)),
+                },
+                ..list.clone()
+            }
+            .into(),
+            Array::Conditional(conditional) => Conditional {
+                condition: Condition {
+                    field: conditional.condition.field.qualified(prefix),
+                    op: conditional.condition.op,
+                },
+                ..conditional.clone()
+            }
+            .into(),
+            dtype => dtype.clone(),
+        };
+
+        let name = match &dtype {
+            Array::Bits(group) => group.group_name(),
+            _ => self.name.qualified(prefix),
+        };
+
+        Field {
+            name,
+            dtype,
+            endianness: self.endianness,
+            compression: self.compression,
+            encoding: self.encoding,
+        }
+    }
 
-#[derive(Clone, Debug, PartialEq)]
-pub struct Field {
-    pub name: Identifier,
-    pub dtype: Array,
+    /// Gets the size of the field once encoded for the wire, if it is sized.
+    pub fn maybe_wire_size_of(&self) -> Option<usize> {
+        let size = self.maybe_size_of()?;
+        Some(
+            self.encoding
+                .map_or(size, |encoding| encoding.encoded_len(size)),
+        )
+    }
 }
 
 impl MaybeSized for Field {
@@ -375,6 +1221,36 @@ impl Format {
         None
     }
 
+    /// Gets the largest value of a fixed-size unsigned number field, or of a
+    /// bit field.
+    pub fn try_get_unsigned_max(&self, field_name: &Identifier) -> Option<u128> {
+        match self.try_get_field_by_name(field_name) {
+            Some(field) => match field.dtype {
+                Array::Primitive(PrimitiveArray(PrimitiveType::Numeric(ntype), 1))
+                    if ntype.bounds().0 == 0 =>
+                {
+                    Some(ntype.bounds().1)
+                }
+                Array::Text(ref text) if text.maybe_size_of().is_some() => Some(text.max_value()),
+                _ => None,
+            },
+            None => self
+                .try_get_bit_field(field_name)
+                .map(|(_, _, width)| u128::MAX >> (128 - width)),
+        }
+    }
+
+    /// Gets the name of the group holding the given bit field, along with the
+    /// bit offset and width of the field within that group.
+    pub fn try_get_bit_field(&self, field_name: &Identifier) -> Option<(Identifier, usize, usize)> {
+        self.fields.iter().find_map(|field| match field.dtype {
+            Array::Bits(ref group) => group
+                .try_get_member(field_name)
+                .map(|(offset, width)| (field.name.clone(), offset, width)),
+            _ => None,
+        })
+    }
+
     pub fn try_get_field_by_name(&self, field_name: &Identifier) -> Option<Field> {
         self.fields
             .iter()
@@ -382,11 +1258,12 @@ impl Format {
             .cloned()
     }
 
+    /// Gets the size on the wire of the fields whose size we already know.
     pub fn fixed_fields_size(&self) -> usize {
         self.fields
             .iter()
             .map(|x| {
-                if let Some(y) = x.maybe_size_of() {
+                if let Some(y) = x.maybe_wire_size_of() {
                     y
                 } else {
                     0
@@ -471,9 +1348,37 @@ impl AbstractFormat {
         for (id, size) in sizes {
             for field in self.format.fields.iter_mut() {
                 if id == &field.name {
-                    if let Array::Dynamic(_) = &field.dtype {
-                        field.dtype =
-                            PrimitiveArray(PrimitiveType::Numeric(NumericType::U8), *size).into()
+                    match &field.dtype {
+                        Array::Dynamic(_) => {
+                            field.dtype =
+                                PrimitiveArray(PrimitiveType::Numeric(NumericType::U8), *size)
+                                    .into()
+                        }
+                        Array::Varint(Varint(vtype, None)) => {
+                            field.dtype = Varint(*vtype, Some(*size)).into()
+                        }
+                        Array::List(list) if list.size.is_none() => {
+                            field.dtype = RecordList {
+                                size: Some(*size),
+                                ..list.clone()
+                            }
+                            .into()
+                        }
+                        Array::Text(text) if text.maybe_size_of().is_none() => {
+                            field.dtype = TextNumber {
+                                size: Some(*size),
+                                ..text.clone()
+                            }
+                            .into()
+                        }
+                        Array::Conditional(conditional) if conditional.present.is_none() => {
+                            field.dtype = Conditional {
+                                present: Some(*size > 0),
+                                ..conditional.clone()
+                            }
+                            .into()
+                        }
+                        _ => {}
                     }
                 }
             }
@@ -559,12 +1464,120 @@ impl MaybeSized for Format {
     }
 }
 
+/// How a checksum field is computed from the bytes it covers.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    Crc32,
+    Adler32,
+    /// The ones' complement sum of 16-bit words from RFC 1071.
+    Internet,
+}
+
+impl ChecksumAlgorithm {
+    /// The type of the field that holds the checksum.
+    pub fn numeric_type(&self) -> NumericType {
+        match self {
+            ChecksumAlgorithm::Crc32 | ChecksumAlgorithm::Adler32 => NumericType::U32,
+            ChecksumAlgorithm::Internet => NumericType::U16,
+        }
+    }
+
+    pub fn compute(&self, data: &[u8]) -> u128 {
+        match self {
+            ChecksumAlgorithm::Crc32 => crc32fast::hash(data) as u128,
+            ChecksumAlgorithm::Adler32 => {
+                const MOD_ADLER: u32 = 65521;
+                let (a, b) = data.iter().fold((1u32, 0u32), |(a, b), &byte| {
+                    let a = (a + byte as u32) % MOD_ADLER;
+                    (a, (b + a) % MOD_ADLER)
+                });
+                ((b << 16) | a) as u128
+            }
+            ChecksumAlgorithm::Internet => {
+                // An odd trailing byte is padded with a zero.
+                let sum: u32 = data
+                    .chunks(2)
+                    .map(|word| u16::from_be_bytes([word[0], *word.get(1).unwrap_or(&0)]) as u32)
+                    .fold(0, |sum, word| {
+                        // Carries wrap around into the low bits.
+                        let sum = sum + word;
+                        (sum & 0xffff) + (sum >> 16)
+                    });
+                (!sum & 0xffff) as u128
+            }
+        }
+    }
+}
+
+impl FromStr for ChecksumAlgorithm {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, ParseError> {
+        match s {
+            "crc32" => Ok(ChecksumAlgorithm::Crc32),
+            "adler32" => Ok(ChecksumAlgorithm::Adler32),
+            "internet" => Ok(ChecksumAlgorithm::Internet),
+            _ => Err(ParseError {}),
+        }
+    }
+}
+
+/// The values a field may hold. Receivers reject any other value, and senders
+/// pick one at random.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ValueConstraint {
+    /// Values from the first to the second, inclusive.
+    Range(u128, u128),
+    OneOf(Vec<u128>),
+}
+
+impl ValueConstraint {
+    pub fn allows(&self, value: u128) -> bool {
+        match self {
+            ValueConstraint::Range(min, max) => (*min..=*max).contains(&value),
+            ValueConstraint::OneOf(values) => values.contains(&value),
+        }
+    }
+
+    /// The largest value allowed, if any are.
+    pub fn max(&self) -> Option<u128> {
+        match self {
+            ValueConstraint::Range(min, max) => Some(*max).filter(|max| min <= max),
+            ValueConstraint::OneOf(values) => values.iter().max().copied(),
+        }
+    }
+
+    pub fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> u128 {
+        match self {
+            ValueConstraint::Range(min, max) => rng.gen_range(*min..=*max),
+            ValueConstraint::OneOf(values) => values[rng.gen_range(0..values.len())],
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum FieldSemantic {
     Payload,
     Padding,
     Length,
+    /// A length field counting the bytes of the fields from the first to the
+    /// second identifier, inclusive.
+    LengthCovers(Identifier, Identifier),
     FixedString(String),
+    FixedBytes(Vec<u8>),
+    /// A checksum over the fields from the first to the second identifier,
+    /// inclusive, taken while the checksum field itself is zero.
+    Checksum(ChecksumAlgorithm, Identifier, Identifier),
+    Constraint(ValueConstraint),
+    /// A number counting the messages sent with this field over the
+    /// connection, starting from zero.
+    SequenceNumber,
+    /// A field whose value is kept in the named connection variable by both
+    /// sides. Senders fill it with random bytes the first time they send it.
+    Save(Identifier),
+    /// A field holding the value of the named connection variable. Receivers
+    /// reject any other value.
+    Load(Identifier),
 }
 
 impl TryFrom<FieldSemantic> for String {
@@ -587,6 +1600,7 @@ impl FromStr for FieldSemantic {
             "PAYLOAD" => Ok(FieldSemantic::Payload),
             "PADDING" => Ok(FieldSemantic::Padding),
             "LENGTH" => Ok(FieldSemantic::Length),
+            "SEQUENCE_NUMBER" => Ok(FieldSemantic::SequenceNumber),
             _ => Err(ParseError {}),
         }
     }
@@ -604,7 +1618,7 @@ impl FromStr for Role {
     }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Phase {
     Handshake,
     Data,
@@ -650,20 +1664,140 @@ impl Semantics {
             .map(|e| e.0.clone())
     }
 
+    /// Gets the first and last fields covered by the given length field, if
+    /// its coverage was given explicitly.
+    pub fn get_length_coverage(&self, field_id: &Identifier) -> Option<(Identifier, Identifier)> {
+        match self.semantics.get(field_id) {
+            Some(FieldSemantic::LengthCovers(first, last)) => Some((first.clone(), last.clone())),
+            _ => None,
+        }
+    }
+
+    /// Gets the algorithm and the first and last fields covered by the given
+    /// checksum field.
+    pub fn get_checksum(
+        &self,
+        field_id: &Identifier,
+    ) -> Option<(ChecksumAlgorithm, Identifier, Identifier)> {
+        match self.semantics.get(field_id) {
+            Some(FieldSemantic::Checksum(algorithm, first, last)) => {
+                Some((*algorithm, first.clone(), last.clone()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Gets the checksum fields of the format in an order where each comes
+    /// after any other checksum it covers, or `None` if checksums cover each
+    /// other in a cycle.
+    pub fn checksum_order(&self, format: &Format) -> Option<Vec<Identifier>> {
+        let position = |id: &Identifier| format.fields.iter().position(|f| &f.name == id);
+        let mut pending: Vec<(Identifier, Range<usize>)> = format
+            .fields
+            .iter()
+            .filter_map(|field| {
+                let (_, first, last) = self.get_checksum(&field.name)?;
+                Some((field.name.clone(), position(&first)?..position(&last)? + 1))
+            })
+            .collect();
+
+        let mut order = vec![];
+        while !pending.is_empty() {
+            let next = pending.iter().position(|(name, covered)| {
+                pending.iter().all(|(other, _)| {
+                    other == name || position(other).is_none_or(|i| !covered.contains(&i))
+                })
+            })?;
+            order.push(pending.remove(next).0);
+        }
+        Some(order)
+    }
+
+    /// Gets the constraint on the values of each constrained field.
+    pub fn get_constraints(&self) -> Vec<(Identifier, ValueConstraint)> {
+        self.semantics
+            .iter()
+            .filter_map(|(id, semantic)| match semantic {
+                FieldSemantic::Constraint(constraint) => Some((id.clone(), constraint.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Gets each field saved to a connection variable, along with the variable.
+    pub fn get_saved_fields(&self) -> Vec<(Identifier, Identifier)> {
+        self.semantics
+            .iter()
+            .filter_map(|(id, semantic)| match semantic {
+                FieldSemantic::Save(var) => Some((id.clone(), var.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Gets each field holding the value of a connection variable, along with
+    /// the variable.
+    pub fn get_loaded_fields(&self) -> Vec<(Identifier, Identifier)> {
+        self.semantics
+            .iter()
+            .filter_map(|(id, semantic)| match semantic {
+                FieldSemantic::Load(var) => Some((id.clone(), var.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Adds the semantics of a format included as the field `prefix`, unless
+    /// they were overridden. Payload and padding describe a whole message, so
+    /// they are not inherited.
+    pub fn inherit(&mut self, prefix: &Identifier, included: &Semantics) {
+        for (id, semantic) in included.semantics.iter() {
+            let semantic = match semantic {
+                FieldSemantic::Payload | FieldSemantic::Padding => continue,
+                FieldSemantic::LengthCovers(first, last) => {
+                    FieldSemantic::LengthCovers(first.qualified(prefix), last.qualified(prefix))
+                }
+                FieldSemantic::Checksum(algorithm, first, last) => FieldSemantic::Checksum(
+                    *algorithm,
+                    first.qualified(prefix),
+                    last.qualified(prefix),
+                ),
+                semantic => semantic.clone(),
+            };
+            self.semantics
+                .entry(id.qualified(prefix))
+                .or_insert(semantic);
+        }
+    }
+
+    /// Gets a semantic bound to more than one field that should name just one,
+    /// if there is such a semantic. Looking it up would pick either field.
+    pub fn find_duplicate_binding(&self) -> Option<FieldSemantic> {
+        [
+            FieldSemantic::Payload,
+            FieldSemantic::Padding,
+            FieldSemantic::Length,
+            FieldSemantic::SequenceNumber,
+        ]
+        .into_iter()
+        .find(|semantic| {
+            self.semantics
+                .values()
+                .filter(|bound| *bound == semantic)
+                .count()
+                > 1
+        })
+    }
+
+    /// Gets the bytes of each field with a fixed value. Fixed strings are
+    /// UTF-8 encoded.
     pub fn get_fixed_fields(&self) -> Vec<(Identifier, Vec<u8>)> {
         self.semantics
             .iter()
-            .filter(|&e| matches!(*e.1, FieldSemantic::FixedString(_)))
-            .map(|e| {
-                (
-                    e.0.clone(),
-                    String::try_from(e.1.clone())
-                        .unwrap()
-                        .as_str()
-                        .chars()
-                        .map(|e| e as u8)
-                        .collect(),
-                )
+            .filter_map(|(id, semantic)| match semantic {
+                FieldSemantic::FixedString(s) => Some((id.clone(), s.as_bytes().to_vec())),
+                FieldSemantic::FixedBytes(bytes) => Some((id.clone(), bytes.clone())),
+                _ => None,
             })
             .collect()
     }
@@ -681,6 +1815,126 @@ pub struct AbstractFormatAndSemantics {
     pub semantics: Semantics,
 }
 
+impl AbstractFormatAndSemantics {
+    /// Whether the size of a message is known once its dynamic arrays are
+    /// sized. Optional fields and numbers whose size depends on their value
+    /// would change the size after padding is chosen.
+    fn sizes_known(&self) -> bool {
+        self.format.format.fields.iter().all(|field| {
+            !matches!(
+                field.dtype,
+                Array::Varint(_)
+                    | Array::Conditional(_)
+                    | Array::Text(TextNumber {
+                        end: TextEnd::Until(_),
+                        ..
+                    })
+            )
+        })
+    }
+
+    /// Adds a one-byte padding length and the padding it counts, so that an
+    /// aligned format without a padding field of its own can be padded. The
+    /// padding length goes at the end of the fixed-size prefix, where the
+    /// receiver reads lengths from, and the padding at the end of the format.
+    /// The receiver reads the padding like any other field and drops it.
+    /// Formats that already use either name are left as they are.
+    pub fn add_padding(&mut self) {
+        let (pad_length, padding) = ("pad_length".id(), "padding".id());
+        let format = &mut self.format.format;
+        if format.try_get_field_by_name(&pad_length).is_some()
+            || format.try_get_field_by_name(&padding).is_some()
+        {
+            return;
+        }
+
+        let prefix_len = format
+            .fields
+            .iter()
+            .position(|f| f.maybe_size_of().is_none())
+            .unwrap_or(format.fields.len());
+        format.fields.insert(
+            prefix_len,
+            Field {
+                name: pad_length.clone(),
+                dtype: PrimitiveArray(NumericType::U8.into(), 1).into(),
+                endianness: Endianness::Big,
+                compression: None,
+                encoding: None,
+            },
+        );
+        format.fields.push(Field {
+            name: padding.clone(),
+            dtype: DynamicArray(UnaryOp::SizeOf(pad_length)).into(),
+            endianness: Endianness::Big,
+            compression: None,
+            encoding: None,
+        });
+        self.semantics
+            .as_mut_ref()
+            .insert(padding, FieldSemantic::Padding);
+    }
+
+    /// Gets the most bytes of padding a message can hold, if its padding can
+    /// be any number of bytes. Encoded padding does not take up the bytes we
+    /// generate for it.
+    fn padding_max(&self) -> Option<usize> {
+        let format = &self.format.format;
+        self.semantics
+            .find_field_id(FieldSemantic::Padding)
+            .and_then(|id| format.try_get_field_by_name(&id))
+            .filter(|field| field.encoding.is_none())
+            .and_then(|field| field.dtype.try_as_dynamic())
+            .and_then(|array| {
+                let max = format.try_get_unsigned_max(&array.try_get_length_field()?)?;
+                match array.try_get_length_expr() {
+                    Some(expr) if expr.scale == 1 && expr.fits_any_whole_units() => {
+                        expr.array_len(max)
+                    }
+                    Some(_) => None,
+                    None => usize::try_from(max).ok(),
+                }
+            })
+    }
+
+    /// Gets the size on the wire of everything the sender lays out besides
+    /// the payload and padding: the fixed-size fields, the fixed strings and
+    /// delimiters of dynamic arrays, and the record in each list.
+    fn overhead(&self) -> usize {
+        let format = &self.format.format;
+        let payload = self.semantics.find_field_id(FieldSemantic::Payload);
+        let padding = self.semantics.find_field_id(FieldSemantic::Padding);
+        let fixed_fields = self.semantics.get_fixed_fields();
+
+        format
+            .fields
+            .iter()
+            .filter(|field| Some(&field.name) != payload.as_ref())
+            .filter(|field| Some(&field.name) != padding.as_ref())
+            .map(|field| {
+                if let Some(size) = field.maybe_wire_size_of() {
+                    return size;
+                }
+                let fixed_len = fixed_fields
+                    .iter()
+                    .find(|(id, _)| id == &field.name)
+                    .map_or(0, |(_, bytes)| bytes.len());
+                let len = match field.dtype {
+                    Array::List(ref list) => list.record.fixed_fields_size(),
+                    Array::Dynamic(DynamicArray(UnaryOp::Until(ref delimiter))) => {
+                        fixed_len + delimiter.len()
+                    }
+                    _ => fixed_len,
+                };
+                field
+                    .encoding
+                    .as_ref()
+                    .map_or(len, |encoding| encoding.encoded_len(len))
+            })
+            .sum()
+    }
+}
+
 impl From<AbstractFormat> for AbstractFormatAndSemantics {
     fn from(item: AbstractFormat) -> AbstractFormatAndSemantics {
         AbstractFormatAndSemantics {
@@ -697,6 +1951,157 @@ pub struct SequenceSpecifier {
     pub format: Identifier,
 }
 
+/// A distribution over non-negative integers, used to describe things like
+/// message sizes in bytes or delays in milliseconds.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Distribution {
+    /// Always the given value.
+    Fixed(usize),
+    /// Uniformly distributed over the inclusive range `[low, high]`.
+    Uniform(usize, usize),
+    /// Values with integer weights given as `(value, weight)` pairs.
+    Histogram(Vec<(usize, usize)>),
+}
+
+impl Distribution {
+    /// Histogram value standing for an infinite delay, i.e. never.
+    pub const INFINITY: usize = usize::MAX;
+
+    pub fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> usize {
+        match self {
+            Distribution::Fixed(x) => *x,
+            Distribution::Uniform(low, high) => rng.gen_range(*low..=*high),
+            Distribution::Histogram(bins) => {
+                // Unwrap OK: the parser guarantees we have a valid weight.
+                let index = WeightedIndex::new(bins.iter().map(|(_, w)| *w)).unwrap();
+                bins[index.sample(rng)].0
+            }
+        }
+    }
+
+    /// Returns true if the distribution can be sampled.
+    pub fn is_valid(&self) -> bool {
+        match self {
+            Distribution::Fixed(_) => true,
+            Distribution::Uniform(low, high) => low <= high,
+            Distribution::Histogram(bins) => bins.iter().any(|(_, w)| *w > 0),
+        }
+    }
+
+    /// Returns the smallest value the distribution may produce.
+    pub fn min(&self) -> usize {
+        match self {
+            Distribution::Fixed(x) => *x,
+            Distribution::Uniform(low, _) => *low,
+            Distribution::Histogram(bins) => bins
+                .iter()
+                .filter(|(_, w)| *w > 0)
+                .map(|(v, _)| *v)
+                .min()
+                .unwrap_or(0),
+        }
+    }
+
+    /// Returns true if the distribution may produce `INFINITY`.
+    pub fn has_infinity(&self) -> bool {
+        match self {
+            Distribution::Histogram(bins) => bins.iter().any(|(v, _)| *v == Self::INFINITY),
+            _ => false,
+        }
+    }
+}
+
+/// The messages that a send delay applies to.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DelayTarget {
+    Format(Identifier),
+    Phase(Phase),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DelayBinding {
+    pub target: DelayTarget,
+    pub delay: Distribution,
+}
+
+/// Sends records of `size` bytes every `interval` milliseconds in each
+/// direction, padding when the application is idle and queueing when it is
+/// busy.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ConstantRate {
+    pub size: usize,
+    pub interval: usize,
+}
+
+impl ConstantRate {
+    pub fn is_valid(&self) -> bool {
+        self.size > 0 && self.interval > 0
+    }
+}
+
+/// Histograms of the gaps in milliseconds between records that drive the
+/// adaptive padding machine of one direction. A sample of `INFINITY` stops
+/// padding until the application sends again.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AdaptivePaddingSpec {
+    /// Gap to wait after a real record, i.e. inside a burst.
+    pub burst: Distribution,
+    /// Gap to wait after a dummy record, i.e. between bursts.
+    pub gap: Distribution,
+}
+
+impl AdaptivePaddingSpec {
+    pub fn is_valid(&self) -> bool {
+        self.burst.is_valid() && self.gap.is_valid()
+    }
+}
+
+/// Directives that shape the traffic patterns of a protocol without changing
+/// the formats of its messages.
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct TrafficSpec {
+    /// Desired distribution of total message sizes in bytes, by format.
+    pub sizes: HashMap<Identifier, Distribution>,
+    /// Distribution of delays in milliseconds before sending, by format.
+    pub format_delays: HashMap<Identifier, Distribution>,
+    /// Distribution of delays in milliseconds before sending, by phase.
+    pub phase_delays: HashMap<Phase, Distribution>,
+    /// Distribution of how long in milliseconds the application may be idle
+    /// before we send a message of the format without payload, as chaff.
+    pub chaff: HashMap<Identifier, Distribution>,
+    /// Block size in bytes that messages of the format are padded out to a
+    /// multiple of, by format. Formats without a padding field get one when
+    /// parsed.
+    pub alignments: HashMap<Identifier, usize>,
+    /// When set, overrides all sizes and sends at a constant rate.
+    pub constant_rate: Option<ConstantRate>,
+    /// Adaptive padding for the records sent by each role.
+    pub adaptive_padding: HashMap<Role, AdaptivePaddingSpec>,
+}
+
+impl TrafficSpec {
+    /// Gets the send delay for a message of the given format sent during the
+    /// given phase. A delay bound to the format takes precedence. Under a
+    /// constant rate the schedule alone decides when we send.
+    pub fn get_delay(&self, format: &Identifier, phase: Phase) -> Option<&Distribution> {
+        if self.constant_rate.is_some() {
+            return None;
+        }
+        self.format_delays
+            .get(format)
+            .or_else(|| self.phase_delays.get(&phase))
+    }
+
+    /// Gets the distribution of total sizes for messages of the given format.
+    /// Under a constant rate every message has the record size.
+    pub fn get_size(&self, format: &Identifier) -> Option<Distribution> {
+        match self.constant_rate {
+            Some(rate) => Some(Distribution::Fixed(rate.size)),
+            None => self.sizes.get(format).cloned(),
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Password(pub String);
 
@@ -713,6 +2118,7 @@ pub struct Psf {
     pub formats: HashMap<Identifier, AbstractFormatAndSemantics>,
     pub sequence: Vec<SequenceSpecifier>,
     pub crypto_spec: Option<CryptoSpec>,
+    pub traffic_spec: TrafficSpec,
 }
 
 impl Psf {
@@ -726,9 +2132,420 @@ impl Psf {
         true
     }
 
+    fn validate_traffic(&self) -> bool {
+        let traffic = &self.traffic_spec;
+
+        for (format, dist) in traffic
+            .sizes
+            .iter()
+            .chain(traffic.format_delays.iter())
+            .chain(traffic.chaff.iter())
+        {
+            if !self.formats.contains_key(format) || !dist.is_valid() || dist.has_infinity() {
+                return false;
+            }
+        }
+
+        // Aligned formats need padding that can make up any shortfall one byte
+        // at a time. Numbers whose size depends on their value would throw off
+        // the sender's count of the bytes around the padding.
+        for (format, &align) in traffic.alignments.iter() {
+            let afs = match self.formats.get(format) {
+                Some(afs) => afs,
+                None => return false,
+            };
+            if align == 0
+                || !afs.sizes_known()
+                || afs.padding_max().is_none_or(|max| max < align - 1)
+            {
+                return false;
+            }
+        }
+
+        // The sender reads only as much app data as fits in the smallest
+        // message once compressed and encoded. If that is nothing, it would
+        // send padding forever.
+        for (format, afs) in self.formats.iter() {
+            let size = match traffic.get_size(format) {
+                Some(size) => size,
+                None => continue,
+            };
+            let payload = match afs
+                .semantics
+                .find_field_id(FieldSemantic::Payload)
+                .and_then(|id| afs.format.format.try_get_field_by_name(&id))
+            {
+                Some(payload) => payload,
+                None => continue,
+            };
+            let room = std::cmp::max(size.min().saturating_sub(afs.overhead()), 1);
+            let room = payload
+                .encoding
+                .map_or(room, |encoding| encoding.max_decoded_len(room));
+            let room = payload
+                .compression
+                .map_or(room, |compression| compression.max_decompressed_len(room));
+            if room == 0 {
+                return false;
+            }
+        }
+
+        if traffic
+            .phase_delays
+            .values()
+            .any(|dist| dist.has_infinity())
+        {
+            return false;
+        }
+
+        if !traffic
+            .adaptive_padding
+            .values()
+            .all(|spec| spec.is_valid())
+        {
+            return false;
+        }
+
+        if let Some(rate) = traffic.constant_rate {
+            if !self.constant_rate_valid(rate) {
+                return false;
+            }
+        }
+
+        traffic.phase_delays.values().all(|dist| dist.is_valid())
+    }
+
+    /// Whether every message can be sent at exactly the record size of the
+    /// given rate. Like aligned formats, each format needs padding and sizes
+    /// we can count, and also a payload that may be empty, so that the padding
+    /// alone can take a message from its overhead up to the record size.
+    pub fn constant_rate_valid(&self, rate: ConstantRate) -> bool {
+        rate.is_valid()
+            && self.sequence.iter().all(|s| {
+                let afs = match self.formats.get(&s.format) {
+                    Some(afs) => afs,
+                    None => return false,
+                };
+                let format = &afs.format.format;
+                let payload_dynamic = afs
+                    .semantics
+                    .find_field_id(FieldSemantic::Payload)
+                    .and_then(|id| format.try_get_field_by_name(&id))
+                    .is_some_and(|field| matches!(field.dtype, Array::Dynamic(_)));
+                let overhead = afs.overhead();
+
+                payload_dynamic
+                    && afs.sizes_known()
+                    && afs
+                        .padding_max()
+                        .is_some_and(|max| overhead <= rate.size && rate.size - overhead <= max)
+            })
+    }
+
+    /// Gets the sizes of the fields that save the given variable in messages
+    /// sent by `role`.
+    fn saved_variable_sizes(&self, var: &Identifier, role: Role) -> Vec<Option<usize>> {
+        self.sequence
+            .iter()
+            .filter(|s| s.role == role)
+            .filter_map(|s| self.formats.get(&s.format))
+            .flat_map(|afs| {
+                afs.semantics
+                    .get_saved_fields()
+                    .into_iter()
+                    .filter(|(_, saved_var)| saved_var == var)
+                    .map(|(id, _)| {
+                        afs.format
+                            .format
+                            .try_get_field_by_name(&id)
+                            .and_then(|field| field.maybe_size_of())
+                    })
+            })
+            .collect()
+    }
+
+    /// Gets the role whose value of the given variable a message sent by `role`
+    /// loads. Each role saves its own variables, so this is the peer if it
+    /// saves the variable, and otherwise `role` itself.
+    pub fn variable_saver(&self, var: &Identifier, role: Role) -> Option<Role> {
+        let peer = match role {
+            Role::Client => Role::Server,
+            Role::Server => Role::Client,
+        };
+        [peer, role]
+            .into_iter()
+            .find(|&saver| !self.saved_variable_sizes(var, saver).is_empty())
+    }
+
+    /// Every variable a field loads must be saved by a field of the same size.
+    fn validate_variables(&self) -> bool {
+        self.sequence.iter().all(|s| {
+            let afs = match self.formats.get(&s.format) {
+                Some(afs) => afs,
+                None => return false,
+            };
+            afs.semantics.get_loaded_fields().iter().all(|(id, var)| {
+                let size = afs
+                    .format
+                    .format
+                    .try_get_field_by_name(id)
+                    .and_then(|field| field.maybe_size_of());
+                self.variable_saver(var, s.role)
+                    .is_some_and(|saver| self.saved_variable_sizes(var, saver).contains(&size))
+            })
+        })
+    }
+
+    fn validate_formats(&self) -> bool {
+        self.formats.values().all(|afs| {
+            let format = &afs.format.format;
+            let position = |id: &Identifier| format.fields.iter().position(|f| &f.name == id);
+            let (static_prefix, _) = format.split_into_fixed_sized_prefix_dynamic_suffix();
+            let in_prefix = |id: &Identifier| {
+                static_prefix.try_get_field_by_name(id).is_some()
+                    || static_prefix.try_get_bit_field(id).is_some()
+            };
+
+            let fields_valid = format.fields.iter().all(|field| match field.dtype {
+                Array::Bits(ref group) => group.is_valid(),
+                Array::Dynamic(ref array) => match array.0 {
+                    UnaryOp::Expr(ref expr) => expr.scale > 0,
+                    UnaryOp::Until(ref delimiter) => !delimiter.is_empty(),
+                    UnaryOp::SizeOf(_) => true,
+                },
+                // We can only tell how many bytes to read for a count of
+                // non-empty records of a fixed size.
+                Array::List(ref list) => match list.length {
+                    ListLength::Count(_) => list.record.maybe_size_of().is_some_and(|n| n > 0),
+                    ListLength::Bytes(_) => true,
+                },
+                // Optional fields hold values of a fixed size, and we must
+                // know the value of the condition once the prefix is read.
+                Array::Conditional(ref conditional) => {
+                    matches!(*conditional.array, Array::Primitive(_))
+                        && conditional.array.maybe_size_of().is_some_and(|n| n > 0)
+                        && in_prefix(&conditional.condition.field)
+                }
+                Array::Text(ref text) => match text.end {
+                    TextEnd::Width(width) => width > 0,
+                    TextEnd::Until(ref delimiter) => !delimiter.is_empty(),
+                },
+                _ => true,
+            });
+
+            // Only byte arrays may be encoded, and we must know how many bytes
+            // to read before decoding them. Padding is random bytes, so it
+            // gains nothing from being printable.
+            let padding = afs.semantics.find_field_id(FieldSemantic::Padding);
+            let encodings_valid = format.fields.iter().all(|field| match field.dtype {
+                // We parse records straight off the wire.
+                Array::List(ref list) => {
+                    field.encoding.is_none()
+                        && list.record.fields.iter().all(|f| f.encoding.is_none())
+                }
+                _ if field.encoding.is_none() => true,
+                _ if Some(&field.name) == padding.as_ref() => false,
+                Array::Primitive(PrimitiveArray(ptype, _)) => ptype == NumericType::U8.into(),
+                Array::Dynamic(ref array) => match array.0 {
+                    UnaryOp::SizeOf(_) => true,
+                    UnaryOp::Expr(ref expr) => expr.scale == 1,
+                    UnaryOp::Until(_) => false,
+                },
+                _ => false,
+            });
+
+            // Only app data is compressed, and it gives a number of bytes that
+            // need not be a whole number of units.
+            let payload = afs.semantics.find_field_id(FieldSemantic::Payload);
+            let compressions_valid = format.fields.iter().all(|field| match field.dtype {
+                Array::List(ref list) => {
+                    field.compression.is_none()
+                        && list.record.fields.iter().all(|f| f.compression.is_none())
+                }
+                _ if field.compression.is_none() => true,
+                _ if Some(&field.name) != payload.as_ref() => false,
+                Array::Dynamic(ref array) => match array.0 {
+                    UnaryOp::SizeOf(_) => true,
+                    UnaryOp::Expr(ref expr) => expr.scale == 1,
+                    UnaryOp::Until(_) => false,
+                },
+                _ => false,
+            });
+
+            // Payloads must be able to hold any amount of app data, including
+            // none at all for chaff.
+            let payload_valid = afs
+                .semantics
+                .find_field_id(FieldSemantic::Payload)
+                .and_then(|id| format.try_get_field_by_name(&id))
+                .and_then(|field| DynamicArray::try_from(field.dtype).ok())
+                .and_then(|array| array.try_get_length_expr())
+                .is_none_or(|expr| expr.scale == 1 && expr.fits_any_whole_units());
+
+            // App data may hold any bytes, so the payload cannot end at a
+            // delimiter. Delimited fields also come before the payload, so we
+            // know their sizes by the time we read it.
+            let payload_position = afs
+                .semantics
+                .find_field_id(FieldSemantic::Payload)
+                .and_then(|id| position(&id));
+            let delimiters_valid = format.fields.iter().enumerate().all(|(i, field)| {
+                match field.dtype.try_get_delimiter() {
+                    Some(_) => payload_position.is_none_or(|payload| i < payload),
+                    None => true,
+                }
+            });
+
+            // Only the payload's length field may say what it covers, and it
+            // must cover the payload.
+            let payload_length_field = afs
+                .semantics
+                .find_field_id(FieldSemantic::Payload)
+                .and_then(|id| format.try_get_field_by_name(&id))
+                .and_then(|field| DynamicArray::try_from(field.dtype).ok())
+                .and_then(|array| array.try_get_length_field());
+            let coverage_valid = format.fields.iter().all(|field| {
+                match afs.semantics.get_length_coverage(&field.name) {
+                    Some((first, last)) => {
+                        payload_length_field.as_ref() == Some(&field.name)
+                            && match (position(&first), position(&last), payload_position) {
+                                (Some(first), Some(last), Some(payload)) => {
+                                    first <= payload && payload <= last
+                                }
+                                _ => false,
+                            }
+                    }
+                    None => true,
+                }
+            });
+
+            // Fixed values must fit in the fields that hold them, and must not
+            // end a delimited field early.
+            let fixed_valid = afs.semantics.get_fixed_fields().iter().all(|(id, bytes)| {
+                let field = format.try_get_field_by_name(id);
+                field
+                    .as_ref()
+                    .and_then(|field| field.maybe_size_of())
+                    .is_none_or(|size| bytes.len() <= size)
+                    && field
+                        .and_then(|field| field.dtype.try_get_delimiter())
+                        .is_none_or(|delimiter| {
+                            [bytes.as_slice(), &delimiter]
+                                .concat()
+                                .windows(delimiter.len())
+                                .position(|w| w == delimiter)
+                                == Some(bytes.len())
+                        })
+            });
+
+            // Checksums are held in a single number of the algorithm's size, and
+            // we must be able to take them one after another.
+            let checksums_valid =
+                format
+                    .fields
+                    .iter()
+                    .all(|field| match afs.semantics.get_checksum(&field.name) {
+                        Some((algorithm, first, last)) => {
+                            field.dtype == PrimitiveArray(algorithm.numeric_type().into(), 1).into()
+                                && match (position(&first), position(&last)) {
+                                    (Some(first), Some(last)) => first <= last,
+                                    _ => false,
+                                }
+                        }
+                        None => true,
+                    })
+                    && afs.semantics.checksum_order(format).is_some();
+
+            // Each dynamic array needs a length field of its own.
+            let mut length_fields: Vec<Identifier> = format
+                .fields
+                .iter()
+                .filter_map(|field| field.dtype.try_as_dynamic())
+                .filter_map(|array| array.try_get_length_field())
+                .collect();
+            let num_arrays = length_fields.len();
+            length_fields.sort_by(|a, b| a.0.cmp(&b.0));
+            length_fields.dedup();
+
+            // The sender tests conditions before it lays out the message, so
+            // they cannot test lengths, checksums, or values it picks later.
+            // Fields without a fixed value are sent as zero, so a condition
+            // that does not hold for zero would never send its field.
+            let constraints = afs.semantics.get_constraints();
+            let fixed_fields = afs.semantics.get_fixed_fields();
+            let conditions: Vec<&Condition> = format
+                .fields
+                .iter()
+                .filter_map(|field| match field.dtype {
+                    Array::Conditional(ref conditional) => Some(&conditional.condition),
+                    _ => None,
+                })
+                .collect();
+            let condition_fields: Vec<&Identifier> = conditions.iter().map(|c| &c.field).collect();
+            let conditions_valid = conditions.iter().all(|condition| {
+                let id = &condition.field;
+                !length_fields.contains(id)
+                    && !constraints.iter().any(|(c, _)| c == id)
+                    && afs.semantics.get_checksum(id).is_none()
+                    && (condition.holds(0) || fixed_fields.iter().any(|(f, _)| f == id))
+            });
+
+            // Constrained fields hold fixed-size unsigned numbers that can
+            // take every allowed value.
+            let constraints_valid = constraints.iter().all(|(id, constraint)| {
+                !length_fields.contains(id)
+                    && constraint.max().is_some_and(|max| {
+                        format
+                            .try_get_unsigned_max(id)
+                            .is_some_and(|field_max| max <= field_max)
+                    })
+            });
+
+            // Variables hold the bytes of fixed-size values the sender does
+            // not otherwise compute.
+            let variables_valid = afs
+                .semantics
+                .get_saved_fields()
+                .iter()
+                .chain(afs.semantics.get_loaded_fields().iter())
+                .all(|(id, _)| {
+                    !length_fields.contains(id)
+                        && !condition_fields.contains(&id)
+                        && format
+                            .try_get_field_by_name(id)
+                            .is_some_and(|field| matches!(field.dtype, Array::Primitive(_)))
+                });
+
+            // So do sequence numbers, which count up from zero and wrap around.
+            let sequence_number = afs.semantics.find_field_id(FieldSemantic::SequenceNumber);
+            let sequence_valid = sequence_number.is_none_or(|id| {
+                !length_fields.contains(&id)
+                    && !condition_fields.contains(&&id)
+                    && format.try_get_unsigned_max(&id).is_some()
+            });
+
+            fields_valid
+                && payload_valid
+                && coverage_valid
+                && fixed_valid
+                && checksums_valid
+                && conditions_valid
+                && constraints_valid
+                && sequence_valid
+                && variables_valid
+                && delimiters_valid
+                && encodings_valid
+                && compressions_valid
+                && length_fields.len() == num_arrays
+        })
+    }
+
     /// Run checks to ensure that the PSF is semantically valid
     pub fn is_valid(&self) -> bool {
-        self.validate_seqs()
+        self.validate_formats()
+            && self.validate_seqs()
+            && self.validate_traffic()
+            && self.validate_variables()
     }
 }
 
@@ -799,10 +2616,16 @@ pub mod tests {
                 Field {
                     name: "Foo".parse().unwrap(),
                     dtype: PrimitiveArray(NumericType::U8.into(), 1).into(),
+                    endianness: Endianness::Big,
+                    compression: None,
+                    encoding: None,
                 },
                 Field {
                     name: "Bar".parse().unwrap(),
                     dtype: PrimitiveArray(NumericType::U32.into(), 10).into(),
+                    endianness: Endianness::Big,
+                    compression: None,
+                    encoding: None,
                 },
             ],
         }
@@ -817,10 +2640,16 @@ pub mod tests {
                 Field {
                     name: "Foo".parse().unwrap(),
                     dtype: PrimitiveArray(NumericType::U8.into(), 1).into(),
+                    endianness: Endianness::Big,
+                    compression: None,
+                    encoding: None,
                 },
                 Field {
                     name: "Bar".parse().unwrap(),
                     dtype: DynamicArray(UnaryOp::SizeOf("Foo".parse().unwrap())).into(),
+                    endianness: Endianness::Big,
+                    compression: None,
+                    encoding: None,
                 },
             ],
         }
@@ -869,4 +2698,193 @@ pub mod tests {
         let sizes = vec![("Foo".parse().unwrap(), 40)];
         format.concretize(&sizes);
     }
+
+    #[test]
+    fn test_varint_roundtrip() {
+        // Examples from RFC 9000, Appendix A.1.
+        let quic_cases: [(u64, &[u8]); 4] = [
+            (
+                151_288_809_941_952_652,
+                &[0xc2, 0x19, 0x7c, 0x5e, 0xff, 0x14, 0xe8, 0x8c],
+            ),
+            (494_878_333, &[0x9d, 0x7f, 0x3e, 0x7d]),
+            (15_293, &[0x7b, 0xbd]),
+            (37, &[0x25]),
+        ];
+        let leb128_cases: [(u64, &[u8]); 4] = [
+            (0, &[0x00]),
+            (127, &[0x7f]),
+            (624_485, &[0xe5, 0x8e, 0x26]),
+            (
+                u64::MAX,
+                &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01],
+            ),
+        ];
+
+        for (vtype, cases) in [
+            (VarintType::Quic, quic_cases),
+            (VarintType::Leb128, leb128_cases),
+        ] {
+            for (value, bytes) in cases {
+                assert_eq!(vtype.encoded_len(value), bytes.len());
+                assert_eq!(vtype.encode(value).unwrap(), bytes);
+                assert_eq!(vtype.decode(bytes), Some(value));
+                for i in 1..bytes.len() {
+                    assert!(vtype.remaining_len(&bytes[..i]).unwrap() > 0);
+                }
+                assert_eq!(vtype.remaining_len(bytes), Some(0));
+            }
+        }
+
+        assert_eq!(VarintType::Quic.encode(1 << 62), None);
+        assert_eq!(VarintType::Quic.decode(&[0x40]), None);
+        assert_eq!(VarintType::Leb128.decode(&[0x80]), None);
+        assert_eq!(VarintType::Leb128.remaining_len(&[0x80; 10]), None);
+    }
+
+    #[test]
+    fn test_text_number_roundtrip() {
+        let text = |radix, end| TextNumber {
+            radix,
+            end,
+            size: None,
+        };
+        let dec_line = text(TextRadix::Decimal, TextEnd::Until(b"\r\n".to_vec()));
+        let hex_4 = text(TextRadix::Hex, TextEnd::Width(4));
+
+        let cases: [(&TextNumber, u128, &[u8]); 4] = [
+            (&dec_line, 0, b"0\r\n"),
+            (&dec_line, 1234, b"1234\r\n"),
+            (&hex_4, 0, b"0000"),
+            (&hex_4, 0xbeef, b"beef"),
+        ];
+
+        for (text, value, bytes) in cases {
+            assert_eq!(text.encode(value).unwrap(), bytes);
+            assert_eq!(text.decode(bytes), Some(value));
+        }
+
+        assert_eq!(hex_4.max_value(), 0xffff);
+        assert_eq!(hex_4.encode(0x10000), None);
+        assert_eq!(hex_4.decode(b"BEEF"), Some(0xbeef));
+        assert_eq!(hex_4.decode(b"+eef"), None);
+        assert_eq!(hex_4.decode(b"beef0"), None);
+        assert_eq!(dec_line.decode(b"\r\n"), None);
+        assert_eq!(dec_line.decode(b"12a\r\n"), None);
+        assert_eq!(dec_line.decode(b"1234"), None);
+    }
+
+    #[test]
+    fn test_byte_encoding_roundtrip() {
+        // Test vectors from RFC 4648.
+        let cases: [(ByteEncoding, &[u8], &[u8]); 7] = [
+            (ByteEncoding::Base64, b"", b""),
+            (ByteEncoding::Base64, b"fo", b"Zm8="),
+            (ByteEncoding::Base64, b"foobar", b"Zm9vYmFy"),
+            (ByteEncoding::Base32, b"f", b"MY======"),
+            (ByteEncoding::Base32, b"foo", b"MZXW6==="),
+            (ByteEncoding::Base32, b"fooba", b"MZXW6YTB"),
+            (ByteEncoding::Hex, b"foo", b"666f6f"),
+        ];
+
+        for (encoding, raw, encoded) in cases {
+            assert_eq!(encoding.encode(raw), encoded);
+            assert_eq!(encoding.encoded_len(raw.len()), encoded.len());
+            assert_eq!(encoding.decode(encoded).unwrap(), raw);
+            assert!(encoding.max_decoded_len(encoded.len()) >= raw.len());
+        }
+
+        assert_eq!(ByteEncoding::Hex.decode(b"666F6F").unwrap(), b"foo");
+        assert_eq!(ByteEncoding::Hex.decode(b"666"), None);
+        assert_eq!(ByteEncoding::Base64.decode(b"Zm8"), None);
+        assert_eq!(ByteEncoding::Base64.decode(b"Zm9="), None);
+        assert_eq!(ByteEncoding::Base32.decode(b"my======"), None);
+    }
+
+    #[test]
+    fn test_compression_roundtrip() {
+        let compression = Compression::Deflate;
+        let mut random = vec![0u8; 100_000];
+        rand::thread_rng().fill(&mut random[..]);
+        let repetitive = b"foobar".repeat(1000);
+
+        for data in [&b""[..], &random, &repetitive] {
+            let compressed = compression.compress(data);
+            assert!(compressed.len() <= compression.max_compressed_len(data.len()));
+            assert_eq!(
+                compression.decompress(&compressed, data.len()).unwrap(),
+                data
+            );
+        }
+
+        for len in [5, 6, 16388, 16389, 65535] {
+            let max = compression.max_decompressed_len(len);
+            assert!(compression.max_compressed_len(max) <= len);
+            assert!(compression.max_compressed_len(max + 1) > len);
+        }
+
+        let compressed = compression.compress(&repetitive);
+        assert_eq!(compression.decompress(&compressed, 5999), None);
+        assert_eq!(compression.decompress(b"foobar", 6), None);
+    }
+
+    #[test]
+    fn test_value_constraint() {
+        let range = ValueConstraint::Range(0x0301, 0x0303);
+        let one_of = ValueConstraint::OneOf(vec![0x14, 0x17]);
+
+        assert!(range.allows(0x0301) && range.allows(0x0303));
+        assert!(!range.allows(0x0300) && !range.allows(0x0304));
+        assert!(one_of.allows(0x17) && !one_of.allows(0x15));
+        assert_eq!(ValueConstraint::Range(2, 1).max(), None);
+        assert_eq!(ValueConstraint::OneOf(vec![]).max(), None);
+
+        let mut rng = rand::thread_rng();
+        for _ in 0..100 {
+            assert!(range.allows(range.sample(&mut rng)));
+            assert!(one_of.allows(one_of.sample(&mut rng)));
+        }
+    }
+
+    #[test]
+    fn test_checksum_algorithms() {
+        let cases: [(ChecksumAlgorithm, &[u8], u128); 5] = [
+            (ChecksumAlgorithm::Crc32, b"123456789", 0xcbf43926),
+            (ChecksumAlgorithm::Adler32, b"Wikipedia", 0x11e60398),
+            (ChecksumAlgorithm::Adler32, b"", 1),
+            // The example from RFC 1071.
+            (
+                ChecksumAlgorithm::Internet,
+                &[0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6, 0xf7],
+                0x220d,
+            ),
+            (ChecksumAlgorithm::Internet, &[0x00, 0x01, 0xf2], 0x0dfe),
+        ];
+
+        for (algorithm, data, checksum) in cases {
+            assert_eq!(algorithm.compute(data), checksum);
+        }
+    }
+
+    #[test]
+    fn test_length_expr() {
+        let words = LengthExpr {
+            field: "words".id(),
+            scale: 4,
+            bias: -8,
+        };
+        assert_eq!(words.array_len(2), Some(0));
+        assert_eq!(words.array_len(1), None);
+        assert_eq!(words.field_value(12), Some(5));
+        assert_eq!(words.field_value(13), None);
+        assert!(words.fits_any_whole_units());
+
+        let odd = LengthExpr {
+            field: "odd".id(),
+            scale: 4,
+            bias: 2,
+        };
+        assert_eq!(odd.field_value(6), Some(1));
+        assert!(!odd.fits_any_whole_units());
+    }
 }
@@ -13,6 +13,7 @@ pub mod proto;
 pub enum Error {
     Eof,
     IoError(std::io::Error),
+    InvalidFrame,
     _Reunite,
 }
 
@@ -21,6 +22,7 @@ impl fmt::Display for Error {
         match self {
             Error::Eof => write!(f, "Reached EOF during network I/O operation"),
             Error::IoError(e) => write!(f, "I/O error during network operation: {}", e),
+            Error::InvalidFrame => write!(f, "Received bytes that cannot form a valid frame"),
             Error::_Reunite => write!(f, "Error reuniting read and write stream halves"),
         }
     }
@@ -44,6 +46,11 @@ trait Serializer<F> {
 /// Trait for a formatter that can deserialize one or more protocol frames.
 trait Deserializer<F> {
     fn deserialize_frame(&mut self, src: &mut Cursor<&BytesMut>) -> Option<F>;
+
+    /// Returns true if no amount of further bytes would complete the frame.
+    fn is_invalid(&self) -> bool {
+        false
+    }
 }
 
 pub struct Connection {
@@ -127,6 +134,10 @@ impl NetSource {
                 return Ok(frame);
             }
 
+            if deserializer.is_invalid() {
+                return Err(net::Error::InvalidFrame);
+            }
+
             // Pull more bytes in from the source.
             self.read_inner().await?;
         }
@@ -6,11 +6,29 @@ use crate::net::{proto::proteus::frames::NetworkData, Deserializer, Serialize, S
 
 pub struct Formatter {
     valid_read_range: Range<usize>,
+    delimiter: Option<Vec<u8>>,
+    // How many of the buffered bytes we already scanned for the delimiter.
+    scanned: usize,
 }
 
 impl Formatter {
     pub fn new(valid_read_range: Range<usize>) -> Formatter {
-        Formatter { valid_read_range }
+        Formatter {
+            valid_read_range,
+            delimiter: None,
+            scanned: 0,
+        }
+    }
+
+    /// Reads up to and including the first occurrence of `delimiter` instead,
+    /// scanning the buffered bytes for it. The frame is invalid if the
+    /// delimiter does not end before the end of the range.
+    pub fn with_delimiter(valid_read_range: Range<usize>, delimiter: Vec<u8>) -> Formatter {
+        Formatter {
+            valid_read_range,
+            delimiter: Some(delimiter),
+            scanned: 0,
+        }
     }
 }
 
@@ -22,6 +40,28 @@ impl Serializer<NetworkData> for Formatter {
 
 impl Deserializer<NetworkData> for Formatter {
     fn deserialize_frame(&mut self, src: &mut std::io::Cursor<&BytesMut>) -> Option<NetworkData> {
+        if let Some(ref delimiter) = self.delimiter {
+            // Only look at bytes that could still be part of the frame, and pick
+            // up where we left off, allowing for a delimiter split across reads.
+            let max_len = self.valid_read_range.end - 1;
+            let buf = &src.chunk()[..std::cmp::min(src.remaining(), max_len)];
+            let from = self.scanned.saturating_sub(delimiter.len() - 1);
+
+            match buf[from..]
+                .windows(delimiter.len())
+                .position(|w| w == delimiter)
+            {
+                Some(i) => {
+                    let num = from + i + delimiter.len();
+                    return Some(NetworkData::from(src.copy_to_bytes(num)));
+                }
+                None => {
+                    self.scanned = buf.len();
+                    return None;
+                }
+            }
+        }
+
         match src.remaining() >= self.valid_read_range.start {
             true => {
                 let num = std::cmp::min(src.remaining(), self.valid_read_range.end - 1);
@@ -30,4 +70,40 @@ impl Deserializer<NetworkData> for Formatter {
             false => None,
         }
     }
+
+    fn is_invalid(&self) -> bool {
+        self.delimiter.is_some() && self.scanned >= self.valid_read_range.end - 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::BufMut;
+    use std::io::Cursor;
+
+    #[test]
+    fn delimiter_split_across_reads() {
+        let mut fmt = Formatter::with_delimiter(2..64, b"\r\n".to_vec());
+        let mut buf = BytesMut::from(&b"GET / HTTP/1.1\r"[..]);
+        assert!(fmt.deserialize_frame(&mut Cursor::new(&buf)).is_none());
+        assert!(!fmt.is_invalid());
+
+        buf.put(&b"\nHost"[..]);
+        let data = fmt.deserialize_frame(&mut Cursor::new(&buf)).unwrap();
+        assert_eq!(Bytes::from(data), &b"GET / HTTP/1.1\r\n"[..]);
+    }
+
+    #[test]
+    fn delimiter_missing_from_range() {
+        let mut fmt = Formatter::with_delimiter(2..9, b"\r\n".to_vec());
+        let mut buf = BytesMut::from(&b"abcd"[..]);
+        assert!(fmt.deserialize_frame(&mut Cursor::new(&buf)).is_none());
+        assert!(!fmt.is_invalid());
+
+        // The delimiter arrives, but ends past the largest frame we accept.
+        buf.put(&b"efg\r\n"[..]);
+        assert!(fmt.deserialize_frame(&mut Cursor::new(&buf)).is_none());
+        assert!(fmt.is_invalid());
+    }
 }
@@ -1,7 +1,7 @@
 use std::{collections::HashMap, fmt};
 
 use crate::{
-    lang::spec::proteus::ProteusSpec,
+    lang::{spec::proteus::ProteusSpec, types::ConstantRate},
     net::{
         self,
         proto::proteus::{self, spec::proteus::*},
@@ -35,12 +35,55 @@ impl fmt::Display for Error {
     }
 }
 
+/// Transport option holding the record size in bytes for constant-rate mode.
+static RATE_SIZE_OPTION: &str = "rate-size";
+/// Transport option holding the record interval in milliseconds for
+/// constant-rate mode.
+static RATE_INTERVAL_OPTION: &str = "rate-interval";
+
+/// Gets the constant rate configured in the transport options, if any. Both
+/// options must be given together.
+fn constant_rate_from_options(
+    options: &HashMap<String, String>,
+) -> Result<Option<ConstantRate>, proteus::Error> {
+    let parse = |key: &str| -> Result<Option<usize>, proteus::Error> {
+        match options.get(key) {
+            Some(val) => match val.parse::<usize>() {
+                Ok(num) => Ok(Some(num)),
+                Err(e) => Err(Error::Protocol(format!("invalid {} '{}': {}", key, val, e))),
+            },
+            None => Ok(None),
+        }
+    };
+
+    match (parse(RATE_SIZE_OPTION)?, parse(RATE_INTERVAL_OPTION)?) {
+        (Some(size), Some(interval)) => {
+            let rate = ConstantRate { size, interval };
+            match rate.is_valid() {
+                true => Ok(Some(rate)),
+                false => Err(Error::Protocol(format!("invalid constant rate {:?}", rate))),
+            }
+        }
+        (None, None) => Ok(None),
+        _ => Err(Error::Protocol(format!(
+            "{} and {} must be given together",
+            RATE_SIZE_OPTION, RATE_INTERVAL_OPTION
+        ))),
+    }
+}
+
 pub async fn run_proteus(
     proteus_conn: Connection,
     other_conn: Connection,
-    _options: HashMap<String, String>,
-    spec: ProteusSpec,
+    options: HashMap<String, String>,
+    mut spec: ProteusSpec,
 ) -> Result<(), proteus::Error> {
+    // The transport options take precedence over the rate in the PSF.
+    if let Some(rate) = constant_rate_from_options(&options)? {
+        spec.set_constant_rate(rate)
+            .map_err(|e| Error::Protocol(e.to_string()))?;
+    }
+
     match ProteusProtocol::new(other_conn, proteus_conn, spec)
         .run()
         .await
@@ -52,3 +95,42 @@ pub async fn run_proteus(
         RunResult::Error(e) => Err(e.finish()),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn constant_rate_options() {
+        let options = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+
+        assert_eq!(
+            constant_rate_from_options(&options(&[("psf", "a.psf")])).unwrap(),
+            None
+        );
+        assert_eq!(
+            constant_rate_from_options(&options(&[("rate-size", "1500"), ("rate-interval", "20")]))
+                .unwrap(),
+            Some(ConstantRate {
+                size: 1500,
+                interval: 20
+            })
+        );
+        assert!(constant_rate_from_options(&options(&[("rate-size", "1500")])).is_err());
+        assert!(constant_rate_from_options(&options(&[
+            ("rate-size", "0"),
+            ("rate-interval", "20")
+        ]))
+        .is_err());
+        assert!(constant_rate_from_options(&options(&[
+            ("rate-size", "big"),
+            ("rate-interval", "20")
+        ]))
+        .is_err());
+    }
+}
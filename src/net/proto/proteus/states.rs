@@ -1,15 +1,19 @@
 use async_trait::async_trait;
+use bytes::Bytes;
+use tokio::time::{self, Duration, Instant};
 
 use crate::{
     lang::{
         interpreter::{NetOpIn, NetOpOut, SharedAsyncInterpreter},
         spec::proteus::ProteusSpec,
+        types::ConstantRate,
     },
     net::{
         self,
         proto::proteus::{
             self,
             formatter::Formatter,
+            frames::NetworkData,
             spec::{self, proteus::*},
         },
         Connection, NetSink, NetSource,
@@ -33,6 +37,7 @@ impl InitState for ProteusProtocol<Init> {
         let (net_source, net_sink) = self.state.net_conn.into_split();
         let (app_source, app_sink) = self.state.app_conn.into_split();
 
+        let rate = self.state.spec.constant_rate();
         let mut shared_int1 = SharedAsyncInterpreter::new(self.state.spec);
         if let Err(e) = shared_int1.init().await {
             return RunResult::Error(proteus::Error::Protocol(e.to_string()).into());
@@ -40,7 +45,7 @@ impl InitState for ProteusProtocol<Init> {
         let mut shared_int2 = shared_int1.clone();
 
         match tokio::try_join!(
-            obfuscate(app_source, net_sink, &mut shared_int1),
+            obfuscate(app_source, net_sink, &mut shared_int1, rate),
             deobfuscate(net_source, app_sink, &mut shared_int2),
         ) {
             Ok(_) => RunResult::Success(Success {}.into()),
@@ -67,16 +72,25 @@ impl ErrorState for ProteusProtocol<spec::proteus::Error> {
 /// Returns a tuple of the total number of bytes read from the source and
 /// written to the sink as `(read, written)`.
 ///
+/// If a constant `rate` is given, writes are paced to one every interval, and
+/// reads from the source give up at the next send time so that an idle source
+/// produces padding rather than a gap on the wire. A busy source stays queued
+/// in its buffer until the next send.
+///
 /// Upon return, the `source` and `sink` references will be dropped and shutdown
 /// will be called on the `sink` indicating no more data will be written to it.
 async fn obfuscate(
     mut source: NetSource,
     mut sink: NetSink,
     shared_int: &mut SharedAsyncInterpreter,
+    rate: Option<ConstantRate>,
 ) -> Result<(usize, usize), proteus::Error> {
     let mut total_num_read: usize = 0;
     let mut total_num_written: usize = 0;
 
+    let interval = rate.map(|r| Duration::from_millis(r.interval as u64));
+    let mut next_send = Instant::now();
+
     loop {
         // TODO: refactor the read/write here and in deobfuscate are identical.
         match shared_int.next_net_cmd_out().await {
@@ -87,7 +101,31 @@ async fn obfuscate(
                 );
                 let mut fmt = Formatter::new(args.len);
 
-                let net_data = match source.read_frame(&mut fmt).await {
+                let result = match (interval, args.timeout) {
+                    // Reading the frame is cancel safe: anything we already
+                    // pulled in stays buffered in the source.
+                    (Some(_), _) => {
+                        match time::timeout_at(next_send, source.read_frame(&mut fmt)).await {
+                            Ok(result) => result,
+                            Err(_) => {
+                                log::trace!("obfuscate: app idle at send time, sending padding");
+                                Ok(NetworkData::from(Bytes::new()))
+                            }
+                        }
+                    }
+                    (None, Some(timeout)) => {
+                        match time::timeout(timeout, source.read_frame(&mut fmt)).await {
+                            Ok(result) => result,
+                            Err(_) => {
+                                log::trace!("obfuscate: app idle for {:?}, sending chaff", timeout);
+                                Ok(NetworkData::from(Bytes::new()))
+                            }
+                        }
+                    }
+                    (None, None) => source.read_frame(&mut fmt).await,
+                };
+
+                let net_data = match result {
                     Ok(data) => data,
                     Err(net_err) => match net_err {
                         net::Error::Eof => break,
@@ -100,9 +138,19 @@ async fn obfuscate(
 
                 shared_int.store_out(args.addr, net_data.into()).await;
             }
+            NetOpOut::Sleep(args) => {
+                log::trace!("obfuscate: sleeping for {:?} before writing", args.duration);
+                time::sleep(args.duration).await;
+            }
             NetOpOut::SendNet(args) => {
                 log::trace!("obfuscate: trying to write bytes to net");
 
+                if let Some(interval) = interval {
+                    time::sleep_until(next_send).await;
+                    // Don't burst to catch up if we fell behind schedule.
+                    next_send = Instant::max(next_send + interval, Instant::now());
+                }
+
                 let num_written = match sink.write_bytes(&args.bytes).await {
                     Ok(num) => num,
                     Err(e) => return Err(proteus::Error::from(e)),
@@ -149,7 +197,10 @@ async fn deobfuscate(
                     "deobfuscate: trying to read frame of size {:?} from app",
                     args.len
                 );
-                let mut fmt = Formatter::new(args.len);
+                let mut fmt = match args.delimiter {
+                    Some(delimiter) => Formatter::with_delimiter(args.len, delimiter),
+                    None => Formatter::new(args.len),
+                };
 
                 let net_data = match source.read_frame(&mut fmt).await {
                     Ok(data) => data,